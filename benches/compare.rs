@@ -0,0 +1,121 @@
+//! Per-request overhead of the Server-Timing middleware, compared with a
+//! hand-rolled timing middleware doing the bare minimum.
+//!
+//! Run with `cargo bench --bench compare`, and see `load` for end-to-end
+//! numbers over HTTP.
+
+use std::{
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    hint::black_box,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_main, Criterion};
+use http::{HeaderValue, Request, Response};
+use miku_server_timing::{ServerTimingHandle, ServerTimingLayer, TimingMetric, SERVER_TIMING};
+use tower::ServiceExt;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// An inner service which responds immediately, recording a custom metric if
+/// it can.
+#[derive(Clone)]
+struct Inner;
+
+impl Service<Request<()>> for Inner {
+    type Response = Response<()>;
+    type Error = Infallible;
+    type Future = Ready<Result<Response<()>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<()>) -> Self::Future {
+        if let Some(handle) = req.extensions().get::<ServerTimingHandle>() {
+            handle.record(TimingMetric::new("db").with_duration(Duration::from_micros(1234)));
+        }
+
+        ready(Ok(Response::new(())))
+    }
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Calls the service and drives the response future to completion.
+fn oneshot<S>(service: &mut S, cx: &mut Context<'_>) -> Response<()>
+where
+    S: Service<Request<()>, Response = Response<()>, Error = Infallible>,
+{
+    let mut fut = pin!(service.call(Request::new(())));
+
+    loop {
+        if let Poll::Ready(Ok(response)) = fut.as_mut().poll(cx) {
+            return response;
+        }
+    }
+}
+
+fn compare(c: &mut Criterion) {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+
+    let mut group = c.benchmark_group("compare");
+
+    group.bench_function("baseline", |b| {
+        let mut service = Inner;
+        b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+    });
+
+    // What one would write with `map_future`: a single entry, no custom
+    // metrics, no options.
+    group.bench_function("hand_rolled", |b| {
+        let mut service = Inner.map_future(|fut| {
+            let start = Instant::now();
+            async move {
+                fut.await.map(|mut response| {
+                    let dur = start.elapsed().as_secs_f64() * 1000.0;
+                    if let Ok(value) = HeaderValue::from_str(&format!("app;dur={dur:.1}")) {
+                        response.headers_mut().append(SERVER_TIMING, value);
+                    }
+                    response
+                })
+            }
+        });
+        b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+    });
+
+    group.bench_function("server_timing", |b| {
+        let mut service = ServerTimingLayer::new("app").layer(Inner);
+        b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+    });
+
+    group.bench_function("server_timing_full", |b| {
+        let mut service = ServerTimingLayer::new("app")
+            .with_description("gateway")
+            .with_version("1.4.2")
+            .with_sequence(true)
+            .with_timeout_marker(true)
+            .layer(Inner);
+        b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+    });
+
+    group.finish();
+}
+
+#[allow(missing_docs, unreachable_pub, reason = "criterion macro-generated")]
+mod group {
+    use criterion::criterion_group;
+
+    criterion_group!(benches, super::compare);
+}
+
+criterion_main!(group::benches);
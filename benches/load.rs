@@ -0,0 +1,166 @@
+//! A small load harness serving the same axum router over HTTP with and
+//! without the Server-Timing middleware, reporting throughput and latency
+//! percentiles as seen by keep-alive clients.
+//!
+//! Run with `cargo bench --bench load`, optionally followed by `-- <requests>
+//! <connections>`, default 20000 requests over 8 connections per variant.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::Request, middleware::Next, response::Response, routing::get, Router};
+use http::HeaderValue;
+use miku_server_timing::{ServerTimingHandle, ServerTimingLayer, TimingMetric, SERVER_TIMING};
+
+async fn handler(request: Request) -> &'static str {
+    if let Some(handle) = request.extensions().get::<ServerTimingHandle>() {
+        handle.record(TimingMetric::new("db").with_duration(Duration::from_micros(1234)));
+    }
+
+    "ok"
+}
+
+/// What one would write with `axum::middleware::from_fn`: a single entry, no
+/// custom metrics, no options.
+async fn hand_rolled(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let mut response = next.run(request).await;
+
+    let dur = start.elapsed().as_secs_f64() * 1000.0;
+    if let Ok(value) = HeaderValue::from_str(&format!("app;dur={dur:.1}")) {
+        response.headers_mut().append(SERVER_TIMING, value);
+    }
+
+    response
+}
+
+/// Serves the router on a background thread, returning its address.
+fn serve(router: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, router).await.unwrap();
+            });
+    });
+
+    addr
+}
+
+/// Sends the given number of requests over one keep-alive connection,
+/// returning their latencies.
+fn client(addr: SocketAddr, requests: usize) -> Vec<Duration> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+
+    let mut latencies = Vec::with_capacity(requests);
+    let mut line = String::new();
+
+    for _ in 0..requests {
+        let start = Instant::now();
+        writer
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        let mut content_length = 0;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+
+            if line == "\r\n" {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        latencies.push(start.elapsed());
+    }
+
+    latencies
+}
+
+/// Loads the router, then prints the results.
+fn run(name: &str, router: Router, requests: usize, connections: usize) {
+    let addr = serve(router);
+
+    // Warm up.
+    client(addr, 100);
+
+    let start = Instant::now();
+    let clients: Vec<_> = (0..connections)
+        .map(|_| thread::spawn(move || client(addr, requests / connections)))
+        .collect();
+    let mut latencies: Vec<_> = clients
+        .into_iter()
+        .flat_map(|client| client.join().unwrap())
+        .collect();
+    let elapsed = start.elapsed();
+
+    latencies.sort_unstable();
+    let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+
+    println!(
+        "{name:<20} {:>10.0} req/s    p50 {:>8.1?}    p99 {:>8.1?}",
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        percentile(50),
+        percentile(99),
+    );
+}
+
+fn main() {
+    // Skip the flags passed by `cargo bench`.
+    let mut args = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.parse().expect("numeric arguments"));
+    let requests = args.next().unwrap_or(20_000);
+    let connections = args.next().unwrap_or(8);
+
+    let router = || Router::new().route("/", get(handler));
+
+    run("baseline", router(), requests, connections);
+    run(
+        "hand_rolled",
+        router().layer(axum::middleware::from_fn(hand_rolled)),
+        requests,
+        connections,
+    );
+    run(
+        "server_timing",
+        router().layer(ServerTimingLayer::new("app")),
+        requests,
+        connections,
+    );
+    run(
+        "server_timing_full",
+        router().layer(
+            ServerTimingLayer::new("app")
+                .with_description("gateway")
+                .with_version("1.4.2")
+                .with_sequence(true)
+                .with_timeout_marker(true),
+        ),
+        requests,
+        connections,
+    );
+}
@@ -0,0 +1,156 @@
+//! Per-request overhead of the Server-Timing middleware.
+//!
+//! Run with `cargo bench --bench overhead`.
+
+use std::{
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    hint::black_box,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use criterion::{criterion_main, BenchmarkId, Criterion};
+use http::{HeaderValue, Request, Response};
+use miku_server_timing::{ServerTimingHandle, ServerTimingLayer, TimingMetric};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// An inner service which responds immediately, optionally with an existing
+/// `Server-Timing` header.
+#[derive(Clone)]
+struct Inner(Option<HeaderValue>);
+
+/// An inner service which records the given number of custom metrics.
+#[derive(Clone)]
+struct Recording(usize);
+
+impl Service<Request<()>> for Inner {
+    type Response = Response<()>;
+    type Error = Infallible;
+    type Future = Ready<Result<Response<()>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<()>) -> Self::Future {
+        let mut response = Response::new(());
+
+        if let Some(value) = &self.0 {
+            response
+                .headers_mut()
+                .insert("server-timing", value.clone());
+        }
+
+        ready(Ok(response))
+    }
+}
+
+impl Service<Request<()>> for Recording {
+    type Response = Response<()>;
+    type Error = Infallible;
+    type Future = Ready<Result<Response<()>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<()>) -> Self::Future {
+        if let Some(handle) = req.extensions().get::<ServerTimingHandle>() {
+            for _ in 0..self.0 {
+                handle.record(
+                    TimingMetric::new("db")
+                        .with_description("query")
+                        .with_duration(Duration::from_micros(1234)),
+                );
+            }
+        }
+
+        ready(Ok(Response::new(())))
+    }
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Calls the service and drives the response future to completion.
+fn oneshot<S>(service: &mut S, cx: &mut Context<'_>) -> Response<()>
+where
+    S: Service<Request<()>, Response = Response<()>, Error = Infallible>,
+{
+    let mut fut = pin!(service.call(Request::new(())));
+
+    loop {
+        if let Poll::Ready(Ok(response)) = fut.as_mut().poll(cx) {
+            return response;
+        }
+    }
+}
+
+fn overhead(c: &mut Criterion) {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+
+    let upstream = Some(HeaderValue::from_static(
+        "db;dur=12.3, cache;desc=\"hit\";dur=0.1",
+    ));
+
+    let mut group = c.benchmark_group("overhead");
+
+    for (name, existing) in [("vacant", None), ("occupied", upstream)] {
+        group.bench_function(BenchmarkId::new("baseline", name), |b| {
+            let mut service = Inner(existing.clone());
+            b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+        });
+
+        group.bench_function(BenchmarkId::new("app", name), |b| {
+            let mut service = ServerTimingLayer::new("svc").layer(Inner(existing.clone()));
+            b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+        });
+
+        group.bench_function(BenchmarkId::new("app_desc", name), |b| {
+            let mut service = ServerTimingLayer::new("svc")
+                .with_description("whatever")
+                .layer(Inner(existing.clone()));
+            b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+        });
+
+        group.bench_function(BenchmarkId::new("app_no_scratch", name), |b| {
+            let mut service = ServerTimingLayer::new("svc")
+                .with_scratch_buffer(false)
+                .layer(Inner(existing.clone()));
+            b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+        });
+
+        group.bench_function(BenchmarkId::new("app_append", name), |b| {
+            let mut service = ServerTimingLayer::new("svc")
+                .with_append(true)
+                .layer(Inner(existing.clone()));
+            b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+        });
+    }
+
+    for n in [1, 4, 16] {
+        group.bench_function(BenchmarkId::new("metrics", n), |b| {
+            let mut service = ServerTimingLayer::new("svc").layer(Recording(n));
+            b.iter(|| black_box(oneshot(&mut service, &mut cx)));
+        });
+    }
+
+    group.finish();
+}
+
+#[allow(missing_docs, unreachable_pub, reason = "criterion macro-generated")]
+mod group {
+    use criterion::criterion_group;
+
+    criterion_group!(benches, super::overhead);
+}
+
+criterion_main!(group::benches);
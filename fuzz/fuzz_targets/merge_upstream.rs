@@ -0,0 +1,35 @@
+//! Merging arbitrary upstream header values never panics, and keeps them
+//! byte for byte.
+
+#![no_main]
+
+use std::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use http::{HeaderValue, Request};
+use libfuzzer_sys::fuzz_target;
+use miku_server_timing::{testing::MockUpstream, ServerTimingLayer};
+use tower_layer::Layer;
+use tower_service::Service;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(upstream) = HeaderValue::from_bytes(data) else {
+        return;
+    };
+
+    let mut service =
+        ServerTimingLayer::new("app").layer(MockUpstream::<()>::new().with_header(upstream));
+
+    // The mock upstream is always ready.
+    let future = pin!(service.call(Request::new(())));
+    let Poll::Ready(Ok(response)) = future.poll(&mut Context::from_waker(Waker::noop())) else {
+        panic!("the mock upstream is always ready");
+    };
+
+    let value = response.headers()["server-timing"].as_bytes();
+    assert!(value.starts_with(b"app;dur="));
+    assert!(value.ends_with(data));
+});
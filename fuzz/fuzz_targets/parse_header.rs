@@ -0,0 +1,15 @@
+//! Parsing arbitrary header values never panics.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use miku_server_timing::parse::parse_header;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(entries) = parse_header(data) {
+        for entry in &entries {
+            assert!(!entry.name().is_empty());
+            let _ = entry.duration();
+        }
+    }
+});
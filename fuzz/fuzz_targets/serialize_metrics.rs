@@ -0,0 +1,70 @@
+//! Serializing arbitrary metrics never panics, and valid names always yield a
+//! header which parses back.
+
+#![no_main]
+
+use std::{
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use http::{Request, Response};
+use libfuzzer_sys::fuzz_target;
+use miku_server_timing::{parse::parse_header, ServerTimingHandle, ServerTimingLayer, TimingMetric};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Records the metrics `name\0desc\0name\0desc...` given by the fuzzer.
+#[derive(Clone)]
+struct Record(String);
+
+impl Service<Request<()>> for Record {
+    type Response = Response<()>;
+    type Error = Infallible;
+    type Future = Ready<Result<Response<()>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<()>) -> Self::Future {
+        let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+
+        let mut fields = self.0.split('\0');
+        while let Some(name) = fields.next() {
+            let mut metric = TimingMetric::new(name.to_owned()).with_millis(name.len() as f64);
+            if let Some(desc) = fields.next() {
+                metric = metric.with_description(desc.to_owned());
+            }
+            handle.record(metric);
+        }
+
+        ready(Ok(Response::new(())))
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data).into_owned();
+    let names: Vec<_> = input.split('\0').step_by(2).collect();
+    let valid = names.iter().all(|name| {
+        !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+    });
+
+    let mut service = ServerTimingLayer::new("app").layer(Record(input.clone()));
+
+    let future = pin!(service.call(Request::new(())));
+    let Poll::Ready(Ok(response)) = future.poll(&mut Context::from_waker(Waker::noop())) else {
+        panic!("the service is always ready");
+    };
+
+    if valid {
+        let value = response.headers()["server-timing"].as_bytes();
+        let entries = parse_header(value).unwrap();
+        assert_eq!(entries.len(), names.len() + 1);
+    }
+});
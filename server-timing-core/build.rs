@@ -0,0 +1,49 @@
+//! Detects the capabilities of the compiler beyond the MSRV, enabling the code
+//! paths using them, see the README.
+
+use std::{env, process::Command};
+
+/// The cfgs enabled from the given minor versions of the compiler on.
+const CFGS: &[(&str, u32)] = &[
+    // Float arithmetic and comparisons in `const fn`.
+    ("server_timing_const_float", 82),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=MIKU_SERVER_TIMING_MSRV");
+
+    let minor = rustc_minor();
+
+    // Only known to cargo from 1.80 on.
+    if minor >= 80 {
+        for (cfg, _) in CFGS {
+            println!("cargo:rustc-check-cfg=cfg({cfg})");
+        }
+    }
+
+    // Builds the fallbacks like the MSRV does, to test them on newer compilers.
+    if env::var_os("MIKU_SERVER_TIMING_MSRV").is_some() {
+        return;
+    }
+
+    for (cfg, since) in CFGS {
+        if minor >= *since {
+            println!("cargo:rustc-cfg={cfg}");
+        }
+    }
+}
+
+/// Returns the minor version of the compiler, e.g. 75 for `rustc 1.75.0`, or
+/// 0 if unknown.
+fn rustc_minor() -> u32 {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+
+    Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|version| version.split('.').nth(1)?.parse().ok())
+        .unwrap_or(0)
+}
@@ -0,0 +1,403 @@
+//! Allocation-free formatting of durations, and formatting of entries.
+
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::{fmt, time::Duration};
+
+use crate::TimingMetric;
+#[cfg(doc)]
+use crate::{push_description, Style};
+
+/// Defines the given functions as `const fn` when the compiler supports
+/// floats in `const` contexts, see `build.rs`, or as plain functions on older
+/// ones down to the MSRV.
+macro_rules! const_float {
+    ($($(#[$attr:meta])* $vis:vis fn $name:ident $args:tt -> $ret:ty $body:block)*) => {
+        $(
+            #[cfg(server_timing_const_float)]
+            $(#[$attr])*
+            $vis const fn $name $args -> $ret $body
+
+            #[cfg(not(server_timing_const_float))]
+            $(#[$attr])*
+            $vis fn $name $args -> $ret $body
+        )*
+    };
+}
+
+/// The default number of decimal places of `dur`.
+pub const DEFAULT_PRECISION: u8 = 1;
+
+/// The max number of decimal places, i.e. nanoseconds.
+pub const MAX_PRECISION: u8 = 6;
+
+/// `10^n` for `n` in `0..=MAX_PRECISION`.
+const POW10: [u64; MAX_PRECISION as usize + 1] = [1, 10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+/// The order of the params within an entry, see [`Style::order`] and
+/// `ServerTimingLayer::with_param_order`.
+///
+/// Whatever the order, `start` follows `dur`, and the params of the entry of
+/// the service come next, e.g. `app;desc="gateway";dur=12.3;attempts=2`. The
+/// output for a given order is stable across releases.
+pub enum ParamOrder {
+    #[default]
+    /// `desc` before `dur`, e.g. `db;desc="users";dur=12.3`.
+    DescFirst,
+
+    /// `dur` before `desc`, e.g. `db;dur=12.3;desc="users"`.
+    DurFirst,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+/// What happens to the non-ASCII characters of descriptions, see
+/// [`push_description`] and `ServerTimingLayer::with_non_ascii`.
+///
+/// Header values should only be made of visible ASCII: other bytes are
+/// allowed, but many clients fail to read such headers, e.g.
+/// [`HeaderValue::to_str`](http::HeaderValue::to_str) does.
+pub enum NonAsciiPolicy {
+    #[default]
+    /// Kept as UTF-8.
+    Keep,
+
+    /// Percent-encoded as UTF-8, along with `%` itself, e.g. `M%C3%BCller`
+    /// for `Müller`, for clients to decode.
+    PercentEncode,
+
+    /// Replaced with `?`, e.g. `M?ller` for `Müller`.
+    Replace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A duration in milliseconds, formatted as a fixed-point decimal number as
+/// the `dur` param of `Server-Timing` expects.
+///
+/// The value is rounded to the given number of decimal places, which is
+/// capped at 6 (nanoseconds). With 0 decimal places, there will be no
+/// decimal point.
+///
+/// This is the formatter used by the middleware, exposed so that custom
+/// metrics can be serialized consistently.
+///
+/// The output is guaranteed to only contain ASCII digits and at most one `.`
+/// as the decimal separator, with no grouping nor exponent, whatever the
+/// locale of the process: `dur` values never come out as `12,3`.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use server_timing_core::Millis;
+/// assert_eq!(
+///     Millis::from_duration(Duration::from_micros(12_345), 1).to_string(),
+///     "12.3"
+/// );
+/// assert_eq!(Millis::from_f64(0.5, 3).to_string(), "0.500");
+/// ```
+pub struct Millis {
+    /// The value scaled by `10^precision`.
+    scaled: u64,
+
+    /// The number of decimal places.
+    precision: u8,
+}
+
+impl Millis {
+    #[inline]
+    /// Creates a new [`Millis`] from the given [`Duration`].
+    pub const fn from_duration(duration: Duration, precision: u8) -> Self {
+        let precision = clamp_precision(precision);
+
+        // nanoseconds -> scaled milliseconds, rounded half up.
+        let divisor = (POW10[MAX_PRECISION as usize] / POW10[precision as usize]) as u128;
+        let scaled = (duration.as_nanos() + divisor / 2) / divisor;
+
+        Self {
+            scaled: if scaled > u64::MAX as u128 {
+                u64::MAX
+            } else {
+                scaled as u64
+            },
+            precision,
+        }
+    }
+
+    const_float! {
+        #[inline]
+        /// Creates a new [`Millis`] from the given number of milliseconds.
+        ///
+        /// The value is never formatted as an invalid token: negative values
+        /// and `NaN` are clamped to 0, and values too large (including
+        /// infinity) saturate. See [`Millis::checked_from_f64`] to omit
+        /// non-finite values instead.
+        ///
+        /// `const` from Rust 1.82 on.
+        pub fn from_f64(millis: f64, precision: u8) -> Self {
+            let precision = clamp_precision(precision);
+
+            Self {
+                scaled: round(millis * POW10[precision as usize] as f64),
+                precision,
+            }
+        }
+
+        #[inline]
+        /// Creates a new [`Millis`] from the given number of milliseconds, or
+        /// `None` if it's `NaN` or infinite, in which case `dur` should be
+        /// omitted.
+        ///
+        /// Negative values are clamped to 0, like [`Millis::from_f64`].
+        ///
+        /// `const` from Rust 1.82 on.
+        pub fn checked_from_f64(millis: f64, precision: u8) -> Option<Self> {
+            // Like `f64::is_finite`, not `const` before 1.83.
+            if millis > f64::NEG_INFINITY && millis < f64::INFINITY {
+                Some(Self::from_f64(millis, precision))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Appends the formatted value to the given buffer.
+    pub fn encode(self, buf: &mut Vec<u8>) {
+        let mut digits = [b'0'; 24];
+        let len = self.encode_digits(&mut digits);
+
+        buf.extend_from_slice(&digits[digits.len() - len..]);
+    }
+
+    /// Writes the formatted value to the end of the given buffer, returning
+    /// the written length.
+    fn encode_digits(self, digits: &mut [u8; 24]) -> usize {
+        let mut n = self.scaled;
+        let mut pos = digits.len();
+        let mut written = 0;
+
+        loop {
+            if self.precision > 0 && written == self.precision {
+                pos -= 1;
+                digits[pos] = b'.';
+            }
+
+            pos -= 1;
+            digits[pos] = b'0' + (n % 10) as u8;
+            n /= 10;
+            written += 1;
+
+            // Keep at least one digit before the decimal point.
+            if n == 0 && written > self.precision {
+                break;
+            }
+        }
+
+        digits.len() - pos
+    }
+}
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut digits = [b'0'; 24];
+        let len = self.encode_digits(&mut digits);
+
+        // Only ASCII digits and the decimal point are written.
+        f.write_str(core::str::from_utf8(&digits[digits.len() - len..]).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Formats a `Server-Timing` entry the way the middleware does, e.g.
+/// `db;desc="query users";dur=12.3`, so that entries written by application
+/// code elsewhere stay consistent: `dur` gets 1 decimal place, formatted by
+/// [`Millis`] whatever the locale, and `desc` is quoted and escaped.
+///
+/// The name should be a valid token, i.e. alphanumeric or one of
+/// ``!#$%&'*+-.^_`|~``.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use server_timing_core::format_entry;
+/// assert_eq!(
+///     format_entry(
+///         "db",
+///         Some(Duration::from_micros(12_345)),
+///         Some(r#"say "hi""#)
+///     ),
+///     r#"db;desc="say \"hi\"";dur=12.3"#
+/// );
+/// assert_eq!(format_entry("miss", None, None), "miss");
+/// ```
+pub fn format_entry(name: &str, dur: Option<Duration>, desc: Option<&str>) -> String {
+    let mut metric = TimingMetric::new(name.to_owned());
+    if let Some(dur) = dur {
+        metric = metric.with_duration(dur);
+    }
+    if let Some(desc) = desc {
+        metric = metric.with_description(desc.to_owned());
+    }
+
+    let mut buf = Vec::new();
+    metric.encode(&mut buf, false, DEFAULT_PRECISION);
+
+    // Only made of the given strings and ASCII.
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+const_float! {
+    /// Rounds the given value half away from zero, like `f64::round` which
+    /// is missing without `std` and not `const`.
+    ///
+    /// Saturating, `NaN` and negative values become 0.
+    fn round(value: f64) -> u64 {
+        let truncated = value as u64;
+
+        // Exact, as `value` and `truncated` are within 1 of each other below
+        // 2^53, above which floats are integers anyway.
+        if value - truncated as f64 >= 0.5 {
+            truncated.saturating_add(1)
+        } else {
+            truncated
+        }
+    }
+}
+
+#[inline]
+const fn clamp_precision(precision: u8) -> u8 {
+    if precision > MAX_PRECISION {
+        MAX_PRECISION
+    } else {
+        precision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_entry, round, Millis};
+
+    #[test]
+    fn from_duration() {
+        let d = Duration::from_nanos(102_345_678);
+        assert_eq!(Millis::from_duration(d, 0).to_string(), "102");
+        assert_eq!(Millis::from_duration(d, 1).to_string(), "102.3");
+        assert_eq!(Millis::from_duration(d, 2).to_string(), "102.35");
+        assert_eq!(Millis::from_duration(d, 6).to_string(), "102.345678");
+        assert_eq!(Millis::from_duration(d, 9).to_string(), "102.345678");
+
+        assert_eq!(Millis::from_duration(Duration::ZERO, 1).to_string(), "0.0");
+        assert_eq!(
+            Millis::from_duration(Duration::from_micros(50), 1).to_string(),
+            "0.1"
+        );
+        assert_eq!(
+            Millis::from_duration(Duration::from_micros(7), 3).to_string(),
+            "0.007"
+        );
+    }
+
+    #[test]
+    fn from_f64() {
+        assert_eq!(Millis::from_f64(2.3, 1).to_string(), "2.3");
+        assert_eq!(Millis::from_f64(0.05, 1).to_string(), "0.1");
+        assert_eq!(Millis::from_f64(1234.5, 0).to_string(), "1235");
+        assert_eq!(Millis::from_f64(12.0, 3).to_string(), "12.000");
+    }
+
+    #[cfg(server_timing_const_float)]
+    #[test]
+    fn const_from_f64() {
+        const MILLIS: Millis = Millis::from_f64(12.345, 1);
+        const NAN: Option<Millis> = Millis::checked_from_f64(f64::NAN, 1);
+
+        assert_eq!(MILLIS.to_string(), "12.3");
+        assert_eq!(NAN, None);
+    }
+
+    #[test]
+    fn round_like_std() {
+        for value in [
+            0.0,
+            0.49999999999999994,
+            0.5,
+            2.5,
+            123.456_789,
+            4_503_599_627_370_495.5,
+            9_007_199_254_740_993.0,
+            1e30,
+            f64::MAX,
+            f64::INFINITY,
+            -0.5,
+            f64::NAN,
+        ] {
+            assert_eq!(round(value), value.round() as u64, "{value}");
+        }
+    }
+
+    #[test]
+    fn from_f64_non_finite() {
+        assert_eq!(Millis::from_f64(-0.3, 1).to_string(), "0.0");
+        assert_eq!(Millis::from_f64(-0.0, 1).to_string(), "0.0");
+        assert_eq!(Millis::from_f64(f64::NAN, 1).to_string(), "0.0");
+        assert_eq!(Millis::from_f64(f64::NEG_INFINITY, 1).to_string(), "0.0");
+        assert_eq!(
+            Millis::from_f64(f64::INFINITY, 1).to_string(),
+            "1844674407370955161.5"
+        );
+
+        assert_eq!(
+            Millis::checked_from_f64(-0.3, 1),
+            Some(Millis::from_f64(0.0, 1))
+        );
+        assert_eq!(Millis::checked_from_f64(f64::NAN, 1), None);
+        assert_eq!(Millis::checked_from_f64(f64::INFINITY, 1), None);
+        assert_eq!(Millis::checked_from_f64(f64::NEG_INFINITY, 1), None);
+    }
+
+    #[test]
+    fn locale_independent() {
+        let durations = (0..64).map(|shift| Duration::from_nanos(1_234_567_891_234 >> shift));
+        let millis = [0.0, 0.04, 0.5, 999.95, 1234.5678, 1e9, 1e15];
+
+        let formatted = durations
+            .flat_map(|d| (0..=7).map(move |p| (Millis::from_duration(d, p), p)))
+            .chain(
+                millis
+                    .iter()
+                    .flat_map(|&m| (0..=7).map(move |p| (Millis::from_f64(m, p), p))),
+            );
+
+        for (millis, precision) in formatted {
+            let s = millis.to_string();
+            let (int, frac) = s.split_once('.').unwrap_or((&s, ""));
+
+            assert!(!int.is_empty(), "{s}");
+            assert!(int.bytes().all(|b| b.is_ascii_digit()), "{s}");
+            assert!(frac.bytes().all(|b| b.is_ascii_digit()), "{s}");
+            assert_eq!(frac.len(), usize::from(precision.min(6)), "{s}");
+
+            let mut buf = Vec::new();
+            millis.encode(&mut buf);
+            assert_eq!(buf, s.as_bytes());
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let mut buf = b"dur=".to_vec();
+        Millis::from_f64(12.34, 1).encode(&mut buf);
+        assert_eq!(buf, b"dur=12.3");
+    }
+
+    #[test]
+    fn entry() {
+        assert_eq!(
+            format_entry("tls", Some(Duration::from_nanos(1_250_000)), None),
+            "tls;dur=1.3"
+        );
+        assert_eq!(
+            format_entry("cache", None, Some("line\nbreak")),
+            "cache;desc=\"line break\""
+        );
+    }
+}
@@ -0,0 +1,27 @@
+//! The metric model, parser and serializer of the `Server-Timing` header,
+//! without async dependencies, e.g. for CLIs and clients reading the header.
+//!
+//! The middleware adding the header lives in `miku-server-timing`, which
+//! re-exports this crate.
+
+#![cfg_attr(not(any(feature = "feat-std", test)), no_std)]
+
+extern crate alloc;
+
+mod format;
+mod metric;
+mod name;
+pub mod parse;
+
+#[cfg(feature = "feat-std")]
+use http::HeaderName;
+
+pub use crate::{
+    format::{format_entry, Millis, NonAsciiPolicy, ParamOrder, DEFAULT_PRECISION, MAX_PRECISION},
+    metric::{push_description, push_quoted, rollup, Style, TimingMetric},
+    name::ServerTimingName,
+};
+
+#[cfg(feature = "feat-std")]
+/// The `Server-Timing` header name.
+pub const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
@@ -0,0 +1,649 @@
+//! Custom metrics.
+
+use alloc::{
+    borrow::{Cow, ToOwned},
+    format,
+    vec::Vec,
+};
+use core::time::Duration;
+
+use crate::format::{Millis, NonAsciiPolicy, ParamOrder, DEFAULT_PRECISION};
+
+#[derive(Debug, Clone, PartialEq)]
+/// A custom metric, serialized as an entry of the `Server-Timing` header, e.g.
+/// `db;desc="query users";dur=12.3`.
+///
+/// Without a duration, it's a marker entry, e.g. `shed`.
+///
+/// A metric may belong to a parent, e.g. `db.query1` under `db`, see
+/// [`TimingMetric::with_parent`].
+pub struct TimingMetric {
+    /// The metric name.
+    name: Cow<'static, str>,
+
+    /// The name of the parent metric, if any.
+    parent: Option<Cow<'static, str>>,
+
+    /// The duration in milliseconds.
+    dur: Option<f64>,
+
+    /// The start offset relative to the start of the request, in
+    /// milliseconds.
+    start: Option<f64>,
+
+    /// An optional description.
+    desc: Option<Cow<'static, str>>,
+
+    /// The number of decimal places, the default of the layer if `None`.
+    precision: Option<u8>,
+}
+
+impl TimingMetric {
+    #[inline]
+    /// Creates a new marker metric with the given name.
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            name: name.into(),
+            parent: None,
+            dur: None,
+            start: None,
+            desc: None,
+            precision: None,
+        }
+    }
+
+    #[inline]
+    /// Sets the parent metric, e.g. `db` for `query1`, serialized as
+    /// `db.query1`.
+    ///
+    /// The `ServerTimingLayer`, like [`rollup`], serializes children
+    /// right after their parent, adding the parent if not recorded. A parent
+    /// without a duration gets the sum of the durations of its children.
+    pub fn with_parent(mut self, parent: impl Into<Cow<'static, str>>) -> Self {
+        self.parent = Some(parent.into());
+        self
+    }
+
+    #[inline]
+    /// Sets the duration.
+    pub fn with_duration(mut self, dur: Duration) -> Self {
+        self.dur = Some(dur.as_secs_f64() * 1000.0);
+        self
+    }
+
+    #[inline]
+    /// Sets the duration in milliseconds.
+    ///
+    /// Non-finite values are omitted and negative ones are clamped to 0 when
+    /// serialized, see [`Millis::checked_from_f64`].
+    pub fn with_millis(mut self, millis: f64) -> Self {
+        self.dur = Some(millis);
+        self
+    }
+
+    #[inline]
+    /// Sets the start offset relative to the start of the request, e.g. to
+    /// render a waterfall.
+    ///
+    /// It's serialized as the `start` param only when asked to, see
+    /// [`TimingMetric::encode`], e.g. with
+    /// `ServerTimingLayer::with_start_offsets`, or in a `TimingReport`
+    /// serialized directly.
+    pub fn with_start(mut self, start: Duration) -> Self {
+        self.start = Some(start.as_secs_f64() * 1000.0);
+        self
+    }
+
+    #[inline]
+    /// Sets the name, e.g. to rename a metric recorded by a library.
+    pub fn with_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    #[inline]
+    /// Sets the description.
+    pub fn with_description(mut self, desc: impl Into<Cow<'static, str>>) -> Self {
+        self.desc = Some(desc.into());
+        self
+    }
+
+    #[inline]
+    /// Sets the number of decimal places of `dur` and `start`, capped at 6,
+    /// e.g. 3 for a `dns` or `tls` metric which needs microsecond precision.
+    ///
+    /// It overrides the default of the serializer, e.g. the one set with
+    /// `ServerTimingLayer::with_precision`.
+    pub fn with_precision(mut self, precision: u8) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    #[inline]
+    /// Returns the metric name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    /// Returns the name of the parent metric, if any.
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    #[inline]
+    /// Returns the name as serialized, i.e. prefixed with the parent one, e.g.
+    /// `db.query1`.
+    pub fn full_name(&self) -> Cow<'_, str> {
+        match &self.parent {
+            Some(parent) => Cow::Owned(format!("{parent}.{}", self.name)),
+            None => Cow::Borrowed(&self.name),
+        }
+    }
+
+    #[inline]
+    /// Returns the duration in milliseconds, if any.
+    pub fn millis(&self) -> Option<f64> {
+        self.dur
+    }
+
+    #[inline]
+    /// Returns the start offset in milliseconds, if any.
+    pub fn start_millis(&self) -> Option<f64> {
+        self.start
+    }
+
+    #[inline]
+    /// Returns the description, if any.
+    pub fn description(&self) -> Option<&str> {
+        self.desc.as_deref()
+    }
+
+    #[inline]
+    /// Returns the number of decimal places, if overridden.
+    pub fn precision(&self) -> Option<u8> {
+        self.precision
+    }
+
+    #[inline]
+    /// Whether the duration or the start offset is negative, and will be
+    /// clamped to 0 when serialized.
+    pub fn is_negative(&self) -> bool {
+        self.dur.is_some_and(|dur| dur < 0.0) || self.start.is_some_and(|start| start < 0.0)
+    }
+
+    /// Moves the metric into the given namespace, which becomes its parent
+    /// or prefixes the existing one.
+    pub fn within(mut self, namespace: Cow<'static, str>) -> Self {
+        self.parent = Some(match self.parent {
+            Some(parent) => format!("{namespace}.{parent}").into(),
+            None => namespace,
+        });
+        self
+    }
+
+    /// Appends the serialized entry to the given buffer, with the `start`
+    /// param if asked to and set, and the given number of decimal places
+    /// unless overridden.
+    pub fn encode(&self, buf: &mut Vec<u8>, with_start: bool, precision: u8) {
+        self.encode_styled(
+            buf,
+            &Style {
+                with_start,
+                precision,
+                ..Style::default()
+            },
+        );
+    }
+
+    /// Like [`TimingMetric::encode`], with the given style.
+    pub fn encode_styled(&self, buf: &mut Vec<u8>, style: &Style) {
+        let precision = self.precision.unwrap_or(style.precision);
+
+        if let Some(parent) = &self.parent {
+            buf.extend_from_slice(parent.as_bytes());
+            buf.push(b'.');
+        }
+        buf.extend_from_slice(self.name.as_bytes());
+
+        let desc = |buf: &mut Vec<u8>| {
+            if let Some(desc) = &self.desc {
+                buf.extend_from_slice(b";desc=");
+                push_description(buf, &truncate(desc, style.max_description), style.non_ascii);
+            }
+        };
+
+        if style.order == ParamOrder::DescFirst {
+            desc(buf);
+        }
+
+        if let Some(dur) = self
+            .dur
+            .and_then(|dur| Millis::checked_from_f64(dur, precision))
+        {
+            buf.extend_from_slice(b";dur=");
+            dur.encode(buf);
+        }
+
+        if let Some(start) = self
+            .start
+            .filter(|_| style.with_start)
+            .and_then(|start| Millis::checked_from_f64(start, precision))
+        {
+            buf.extend_from_slice(b";start=");
+            start.encode(buf);
+        }
+
+        if let Some(share) = self
+            .dur
+            .zip(style.total)
+            .and_then(|(dur, total)| share(dur, total))
+        {
+            buf.extend_from_slice(b";share=");
+            share.encode(buf);
+        }
+
+        if style.order == ParamOrder::DurFirst {
+            desc(buf);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// How metrics are serialized, see [`TimingMetric::encode_styled`].
+pub struct Style {
+    /// Whether to add the `start` param if set.
+    pub with_start: bool,
+
+    /// The number of decimal places, unless overridden by the metric.
+    pub precision: u8,
+
+    /// The order of `desc` and `dur`.
+    pub order: ParamOrder,
+
+    /// What happens to non-ASCII characters of descriptions.
+    pub non_ascii: NonAsciiPolicy,
+
+    /// The max number of characters of descriptions, if any.
+    pub max_description: Option<usize>,
+
+    /// The total duration in milliseconds, if durations get the `share`
+    /// param.
+    pub total: Option<f64>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            with_start: false,
+            precision: DEFAULT_PRECISION,
+            order: ParamOrder::DescFirst,
+            non_ascii: NonAsciiPolicy::Keep,
+            max_description: None,
+            total: None,
+        }
+    }
+}
+
+/// Returns the given duration as a whole percentage of the given total, e.g.
+/// `33` for 40ms out of 120ms, or `None` if the total isn't positive and
+/// finite.
+fn share(dur: f64, total: f64) -> Option<Millis> {
+    if total > 0.0 && total < f64::INFINITY {
+        Millis::checked_from_f64(dur / total * 100.0, 0)
+    } else {
+        None
+    }
+}
+
+/// The suffix of truncated descriptions.
+const ELLIPSIS: &str = "...";
+
+/// Truncates the given description to the given number of characters, ending
+/// with `...` if truncated.
+///
+/// Characters are counted before escaping, so that escape sequences are never
+/// split.
+pub(crate) fn truncate(s: &str, max: Option<usize>) -> Cow<'_, str> {
+    let Some(max) = max else {
+        return Cow::Borrowed(s);
+    };
+
+    if s.char_indices().nth(max).is_none() {
+        return Cow::Borrowed(s);
+    }
+
+    let keep = max.saturating_sub(ELLIPSIS.len());
+    let end = s.char_indices().nth(keep).map_or(s.len(), |(i, _)| i);
+    let mut truncated = s[..end].to_owned();
+    truncated.push_str(&ELLIPSIS[..max - keep]);
+    Cow::Owned(truncated)
+}
+
+/// Appends a quoted string, escaping quotes and backslashes, and replacing
+/// control characters which are not allowed in header values with spaces.
+pub fn push_quoted(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.push(b'"');
+    for &b in s {
+        push_quoted_byte(buf, b);
+    }
+    buf.push(b'"');
+}
+
+/// Appends a byte of a quoted string, see [`push_quoted`].
+fn push_quoted_byte(buf: &mut Vec<u8>, b: u8) {
+    match b {
+        b'"' | b'\\' => buf.extend_from_slice(&[b'\\', b]),
+        b'\t' => buf.push(b),
+        _ if b.is_ascii_control() => buf.push(b' '),
+        _ => buf.push(b),
+    }
+}
+
+/// The uppercase hexadecimal digits of percent-encoding.
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Appends a quoted description, with its non-ASCII characters handled
+/// according to the given policy.
+pub fn push_description(buf: &mut Vec<u8>, s: &str, policy: NonAsciiPolicy) {
+    match policy {
+        NonAsciiPolicy::Keep => push_quoted(buf, s.as_bytes()),
+        NonAsciiPolicy::PercentEncode => {
+            buf.push(b'"');
+            for &b in s.as_bytes() {
+                if b == b'%' || !b.is_ascii() {
+                    buf.extend_from_slice(&[
+                        b'%',
+                        HEX[usize::from(b >> 4)],
+                        HEX[usize::from(b & 0xF)],
+                    ]);
+                } else {
+                    push_quoted_byte(buf, b);
+                }
+            }
+            buf.push(b'"');
+        }
+        NonAsciiPolicy::Replace => {
+            buf.push(b'"');
+            for c in s.chars() {
+                push_quoted_byte(buf, if c.is_ascii() { c as u8 } else { b'?' });
+            }
+            buf.push(b'"');
+        }
+    }
+}
+
+/// Groups children right after their parent, adding missing parents and
+/// rolling up the durations of children into parents without one.
+pub fn rollup(metrics: Vec<TimingMetric>) -> Vec<TimingMetric> {
+    if metrics.iter().all(|metric| metric.parent.is_none()) {
+        return metrics;
+    }
+
+    // (parent, whether it was added, children)
+    let mut groups: Vec<(TimingMetric, bool, Vec<TimingMetric>)> = Vec::new();
+
+    for metric in metrics {
+        let Some(parent) = &metric.parent else {
+            match groups
+                .iter_mut()
+                .find(|(group, added, _)| *added && group.name == metric.name)
+            {
+                // Recorded after its children.
+                Some((group, added, _)) => {
+                    *group = metric;
+                    *added = false;
+                }
+                None => groups.push((metric, false, Vec::new())),
+            }
+            continue;
+        };
+
+        match groups
+            .iter_mut()
+            .find(|(group, _, _)| group.parent.is_none() && group.name == *parent)
+        {
+            Some((_, _, children)) => children.push(metric),
+            None => groups.push((TimingMetric::new(parent.clone()), true, alloc::vec![metric])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|(mut parent, _, children)| {
+            if parent.dur.is_none() {
+                parent.dur = children
+                    .iter()
+                    .filter_map(|child| child.dur)
+                    .reduce(|a, b| a + b);
+            }
+
+            core::iter::once(parent).chain(children)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{push_description, rollup, truncate, Style, TimingMetric};
+    use crate::format::{NonAsciiPolicy, ParamOrder, DEFAULT_PRECISION};
+
+    fn encode(metric: &TimingMetric) -> String {
+        let mut buf = Vec::new();
+        metric.encode(&mut buf, true, DEFAULT_PRECISION);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn encode_metric() {
+        assert_eq!(encode(&TimingMetric::new("shed")), "shed");
+        assert_eq!(
+            encode(&TimingMetric::new("db").with_duration(Duration::from_micros(12_345))),
+            "db;dur=12.3"
+        );
+        assert_eq!(
+            encode(
+                &TimingMetric::new("cache")
+                    .with_description("hit")
+                    .with_millis(0.04)
+            ),
+            "cache;desc=\"hit\";dur=0.0"
+        );
+        assert_eq!(
+            encode(&TimingMetric::new("db").with_description("a \"b\"\\\n")),
+            "db;desc=\"a \\\"b\\\"\\\\ \""
+        );
+        assert_eq!(encode(&TimingMetric::new("db").with_millis(f64::NAN)), "db");
+        assert_eq!(
+            encode(&TimingMetric::new("db").with_millis(-1.0)),
+            "db;dur=0.0"
+        );
+    }
+
+    #[test]
+    fn encode_precision() {
+        let metric = TimingMetric::new("tls")
+            .with_start(Duration::from_micros(1_234))
+            .with_duration(Duration::from_micros(567));
+        let mut buf = Vec::new();
+        metric.encode(&mut buf, true, 2);
+        assert_eq!(buf, b"tls;dur=0.57;start=1.23");
+
+        let metric = metric.with_precision(3);
+        assert_eq!(metric.precision(), Some(3));
+        assert_eq!(encode(&metric), "tls;dur=0.567;start=1.234");
+    }
+
+    #[test]
+    fn encode_ordered() {
+        let metric = TimingMetric::new("db")
+            .with_description("users")
+            .with_start(Duration::from_micros(1_234))
+            .with_duration(Duration::from_micros(12_345));
+
+        for (order, expected) in [
+            (
+                ParamOrder::DescFirst,
+                "db;desc=\"users\";dur=12.3;start=1.2",
+            ),
+            (ParamOrder::DurFirst, "db;dur=12.3;start=1.2;desc=\"users\""),
+        ] {
+            let mut buf = Vec::new();
+            let style = Style {
+                with_start: true,
+                order,
+                ..Style::default()
+            };
+            metric.encode_styled(&mut buf, &style);
+            assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        }
+
+        let mut buf = Vec::new();
+        let marker = TimingMetric::new("cache").with_description("hit");
+        let style = Style {
+            order: ParamOrder::DurFirst,
+            ..Style::default()
+        };
+        marker.encode_styled(&mut buf, &style);
+        assert_eq!(buf, b"cache;desc=\"hit\"");
+    }
+
+    #[test]
+    fn non_ascii() {
+        for (policy, expected) in [
+            (NonAsciiPolicy::Keep, "\"Müller 100%\""),
+            (NonAsciiPolicy::PercentEncode, "\"M%C3%BCller 100%25\""),
+            (NonAsciiPolicy::Replace, "\"M?ller 100%\""),
+        ] {
+            let mut buf = Vec::new();
+            push_description(&mut buf, "Müller 100%", policy);
+            assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        }
+
+        let mut buf = Vec::new();
+        push_description(&mut buf, "日本 \"a\"", NonAsciiPolicy::Replace);
+        assert_eq!(buf, br#""?? \"a\"""#);
+    }
+
+    #[test]
+    fn truncate_description() {
+        assert_eq!(truncate("select 1", None), "select 1");
+        assert_eq!(truncate("select 1", Some(8)), "select 1");
+        assert_eq!(truncate("select * from users", Some(10)), "select ...");
+        assert_eq!(truncate("Zürich Zürich", Some(6)), "Zür...");
+        assert_eq!(truncate("abcdef", Some(2)), "..");
+        assert_eq!(truncate("abcdef", Some(0)), "");
+
+        let metric = TimingMetric::new("db").with_description(r#"a "quoted" query"#);
+        let mut buf = Vec::new();
+        let style = Style {
+            max_description: Some(8),
+            ..Style::default()
+        };
+        metric.encode_styled(&mut buf, &style);
+        assert_eq!(buf, br#"db;desc="a \"qu...""#);
+    }
+
+    #[test]
+    fn encode_share() {
+        let style = Style {
+            total: Some(120.0),
+            ..Style::default()
+        };
+
+        for (metric, expected) in [
+            (
+                TimingMetric::new("db").with_millis(40.0),
+                "db;dur=40.0;share=33",
+            ),
+            (
+                TimingMetric::new("db").with_millis(0.0),
+                "db;dur=0.0;share=0",
+            ),
+            (
+                TimingMetric::new("db").with_millis(240.0),
+                "db;dur=240.0;share=200",
+            ),
+            (TimingMetric::new("db").with_millis(f64::NAN), "db"),
+            (TimingMetric::new("cache"), "cache"),
+        ] {
+            let mut buf = Vec::new();
+            metric.encode_styled(&mut buf, &style);
+            assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        }
+
+        for total in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let mut buf = Vec::new();
+            let style = Style {
+                total: Some(total),
+                ..Style::default()
+            };
+            TimingMetric::new("db")
+                .with_millis(40.0)
+                .encode_styled(&mut buf, &style);
+            assert_eq!(buf, b"db;dur=40.0");
+        }
+    }
+
+    #[test]
+    fn within() {
+        let metric = TimingMetric::new("query").within("db".into());
+        assert_eq!(metric.full_name(), "db.query");
+
+        let metric = TimingMetric::new("commit")
+            .with_parent("tx")
+            .within("db".into());
+        assert_eq!(metric.parent(), Some("db.tx"));
+        assert_eq!(metric.full_name(), "db.tx.commit");
+    }
+
+    #[test]
+    fn negative() {
+        assert!(!TimingMetric::new("db").is_negative());
+        assert!(!TimingMetric::new("db").with_millis(-0.0).is_negative());
+        assert!(!TimingMetric::new("db").with_millis(f64::NAN).is_negative());
+        assert!(TimingMetric::new("db").with_millis(-0.3).is_negative());
+    }
+
+    #[test]
+    fn encode_start() {
+        let metric = TimingMetric::new("db")
+            .with_start(Duration::from_micros(12_345))
+            .with_millis(5.0);
+        assert_eq!(encode(&metric), "db;dur=5.0;start=12.3");
+
+        let mut buf = Vec::new();
+        metric.encode(&mut buf, false, DEFAULT_PRECISION);
+        assert_eq!(buf, b"db;dur=5.0");
+    }
+
+    #[test]
+    fn rollup_children() {
+        let metrics = rollup(vec![
+            TimingMetric::new("query1")
+                .with_parent("db")
+                .with_millis(2.0),
+            TimingMetric::new("cache").with_millis(1.0),
+            TimingMetric::new("query2")
+                .with_parent("db")
+                .with_millis(3.0),
+            TimingMetric::new("get").with_parent("kv").with_millis(4.0),
+            TimingMetric::new("kv").with_millis(10.0),
+        ]);
+
+        let encoded = metrics.iter().map(encode).collect::<Vec<_>>();
+        assert_eq!(
+            encoded,
+            [
+                "db;dur=5.0",
+                "db.query1;dur=2.0",
+                "db.query2;dur=3.0",
+                "cache;dur=1.0",
+                "kv;dur=10.0",
+                "kv.get;dur=4.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Service names validated at compile time.
+
+use alloc::borrow::Cow;
+
+use crate::parse::is_token;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A service name known to be a valid `Server-Timing` token, usually built
+/// with [`server_timing_name!`](crate::server_timing_name) which checks it at
+/// compile time.
+///
+/// ```rust
+/// # use server_timing_core::{server_timing_name, ServerTimingName, TimingMetric};
+/// let metric = TimingMetric::new(server_timing_name!("api-gateway"));
+///
+/// // Also in a const context.
+/// const NAME: &str = server_timing_name!("api-gateway").as_str();
+/// ```
+pub struct ServerTimingName(&'static str);
+
+impl ServerTimingName {
+    #[inline]
+    /// Creates a new [`ServerTimingName`], see
+    /// [`server_timing_name!`](crate::server_timing_name).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is not a valid token, i.e. it's empty or contains
+    /// characters other than alphanumerics and ``!#$%&'*+-.^_`|~``, which is
+    /// a compile error in a const context.
+    pub const fn new(name: &'static str) -> Self {
+        assert!(is_token(name), "invalid `server-timing` name");
+        Self(name)
+    }
+
+    #[inline]
+    /// Returns the name.
+    pub const fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl From<ServerTimingName> for Cow<'static, str> {
+    #[inline]
+    fn from(name: ServerTimingName) -> Self {
+        Cow::Borrowed(name.0)
+    }
+}
+
+#[macro_export]
+/// Creates a [`ServerTimingName`](crate::ServerTimingName), checking at
+/// compile time that it's a valid `Server-Timing` token.
+///
+/// ```rust
+/// # use server_timing_core::server_timing_name;
+/// let name = server_timing_name!("api-gateway");
+/// assert_eq!(name.as_str(), "api-gateway");
+/// ```
+///
+/// ```rust,compile_fail
+/// # use server_timing_core::server_timing_name;
+/// let name = server_timing_name!("API gateway");
+/// ```
+macro_rules! server_timing_name {
+    ($name:expr) => {{
+        const NAME: $crate::ServerTimingName = $crate::ServerTimingName::new($name);
+        NAME
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::ServerTimingName;
+
+    #[test]
+    fn valid() {
+        let name = server_timing_name!("svc-1.a_b");
+        assert_eq!(name.as_str(), "svc-1.a_b");
+        assert_eq!(Cow::from(name), "svc-1.a_b");
+    }
+
+    #[test]
+    #[should_panic = "invalid `server-timing` name"]
+    fn invalid() {
+        let name = String::from("my svc").leak();
+        let _ = ServerTimingName::new(name);
+    }
+}
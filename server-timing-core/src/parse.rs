@@ -0,0 +1,760 @@
+//! Parsing of `Server-Timing` header values.
+//!
+//! ```rust
+//! # use server_timing_core::parse::parse_header;
+//! let entries = parse_header(br#"app;dur=12.3, db;desc="query, users";dur=4"#).unwrap();
+//!
+//! assert_eq!(entries[0].name(), "app");
+//! assert_eq!(entries[0].duration(), Some(12.3));
+//! assert_eq!(entries[1].description(), Some("query, users"));
+//! ```
+//!
+//! Entries are serialized back with [`TimingEntry::to_bytes`], e.g. to merge
+//! them with other ones, without loss, including opaque non-UTF-8 bytes of
+//! quoted values, which [`Display`](fmt::Display) replaces.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, ops::Range};
+
+#[cfg(feature = "feat-std")]
+use http::HeaderMap;
+
+use crate::metric::push_quoted;
+#[cfg(feature = "feat-std")]
+use crate::SERVER_TIMING;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An entry of a `Server-Timing` header, e.g. `db;desc="query";dur=12.3`.
+pub struct TimingEntry {
+    /// The metric name.
+    name: String,
+
+    /// The params in order, with quoted values unescaped.
+    params: Vec<Param>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A param of an entry.
+struct Param {
+    /// The param name.
+    name: String,
+
+    /// The value, with opaque bytes replaced by U+FFFD.
+    value: String,
+
+    /// The unescaped bytes of the value, kept when not valid UTF-8 to
+    /// serialize them back as is.
+    opaque: Option<Vec<u8>>,
+}
+
+impl Param {
+    #[inline]
+    /// Returns the value as received.
+    fn bytes(&self) -> &[u8] {
+        self.opaque.as_deref().unwrap_or(self.value.as_bytes())
+    }
+}
+
+impl TimingEntry {
+    #[inline]
+    /// Returns the metric name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    /// Returns the value of the first param with the given name, compared
+    /// case-insensitively, if any.
+    ///
+    /// Params without a value, e.g. `cache;hit`, have an empty one.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|param| param.name.eq_ignore_ascii_case(name))
+            .map(|param| param.value.as_str())
+    }
+
+    #[inline]
+    /// Returns the params in order.
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|param| (param.name.as_str(), param.value.as_str()))
+    }
+
+    #[inline]
+    /// Returns the `dur` param in milliseconds, if any and valid.
+    pub fn duration(&self) -> Option<f64> {
+        self.param("dur")?
+            .parse()
+            .ok()
+            .filter(|dur: &f64| dur.is_finite())
+    }
+
+    #[inline]
+    /// Returns the `desc` param, if any.
+    pub fn description(&self) -> Option<&str> {
+        self.param("desc")
+    }
+
+    /// Serializes the entry, quoting the param values which are not tokens.
+    ///
+    /// Unlike [`Display`](fmt::Display), opaque bytes of quoted values are
+    /// kept as received.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+
+    /// Serializes the entry into the given buffer, see [`Self::to_bytes`].
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.name.as_bytes());
+
+        for param in &self.params {
+            push_param(buf, &param.name, param.bytes());
+        }
+    }
+}
+
+/// Appends a param, e.g. `;name=value`, quoting the value if it isn't a
+/// token, or omitting it if empty.
+pub fn push_param(buf: &mut Vec<u8>, name: &str, value: &[u8]) {
+    buf.push(b';');
+    buf.extend_from_slice(name.as_bytes());
+
+    if value.is_empty() {
+        return;
+    }
+
+    buf.push(b'=');
+    if value.iter().copied().all(is_tchar) {
+        buf.extend_from_slice(value);
+    } else {
+        push_quoted(buf, value);
+    }
+}
+
+/// Returns whether the given param or entry name is a valid token.
+pub const fn is_token(name: &str) -> bool {
+    let bytes = name.as_bytes();
+
+    // Iterators are not allowed in `const fn`.
+    let mut i = 0;
+    while i < bytes.len() {
+        if !is_tchar(bytes[i]) {
+            return false;
+        }
+        i += 1;
+    }
+
+    !bytes.is_empty()
+}
+
+impl fmt::Display for TimingEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        f.write_str(&String::from_utf8_lossy(&buf))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An error parsing a `Server-Timing` header value.
+pub struct ParseError {
+    /// Where the error occurred.
+    offset: usize,
+}
+
+impl ParseError {
+    #[inline]
+    /// Returns the offset of the unexpected byte in the header value.
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid `server-timing` header value at byte {}",
+            self.offset
+        )
+    }
+}
+
+#[cfg(feature = "feat-std")]
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How malformed `Server-Timing` header values are handled, see [`Entries`]
+/// and `ServerTimingLayer::with_upstream_parsing`.
+pub enum ParseMode {
+    /// Drops a whole header value if any of its entries is malformed.
+    Strict,
+
+    /// Drops the malformed entries only, keeping the valid ones as is.
+    Lenient,
+}
+
+/// Parses a `Server-Timing` header value.
+///
+/// Empty list elements are ignored, as HTTP allows.
+///
+/// # Errors
+///
+/// When the value is malformed.
+pub fn parse_header(value: &[u8]) -> Result<Vec<TimingEntry>, ParseError> {
+    Entries::new(value, ParseMode::Strict)
+        .map(|entry| entry.map(|(entry, _)| entry))
+        .collect()
+}
+
+/// Parses a `Server-Timing` header value, skipping malformed entries.
+///
+/// Returns the valid entries, and the errors of the malformed ones.
+pub fn parse_header_lenient(value: &[u8]) -> (Vec<TimingEntry>, Vec<ParseError>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+
+    for entry in Entries::new(value, ParseMode::Lenient) {
+        match entry {
+            Ok((entry, _)) => entries.push(entry),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (entries, errors)
+}
+
+#[derive(Debug, Clone)]
+/// An iterator over the entries of a header value, with their byte range.
+///
+/// In strict mode it stops after the first error, in lenient mode it resumes
+/// from the next entry.
+pub struct Entries<'v> {
+    /// The cursor.
+    parser: Parser<'v>,
+
+    /// How malformed entries are handled.
+    mode: ParseMode,
+
+    /// Whether a strict error has been returned.
+    done: bool,
+}
+
+impl<'v> Entries<'v> {
+    #[inline]
+    /// Creates a new [`Entries`] over the given header value.
+    pub const fn new(value: &'v [u8], mode: ParseMode) -> Self {
+        Self {
+            parser: Parser { value, offset: 0 },
+            mode,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Result<(TimingEntry, Range<usize>), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let parser = &mut self.parser;
+        loop {
+            parser.skip_ows();
+            match parser.peek()? {
+                b',' => parser.offset += 1,
+                _ => break,
+            }
+        }
+
+        let start = parser.offset;
+        let entry = parser.entry().and_then(|entry| {
+            let end = parser.offset;
+            parser.skip_ows();
+            match parser.peek() {
+                None => Ok((entry, start..end)),
+                Some(b',') => {
+                    parser.offset += 1;
+                    Ok((entry, start..end))
+                }
+                Some(_) => Err(parser.error()),
+            }
+        });
+
+        if entry.is_err() {
+            match self.mode {
+                ParseMode::Strict => self.done = true,
+                ParseMode::Lenient => {
+                    parser.offset = start;
+                    parser.skip_entry();
+                }
+            }
+        }
+
+        Some(entry)
+    }
+}
+
+/// Splits a header value into its list elements, whether well-formed or not,
+/// trimmed and skipping the empty ones.
+pub fn elements(value: &[u8]) -> Vec<&[u8]> {
+    let mut parser = Parser { value, offset: 0 };
+    let mut elements = Vec::new();
+
+    loop {
+        parser.skip_ows();
+        let start = parser.offset;
+        match parser.peek() {
+            None => return elements,
+            Some(b',') => parser.offset += 1,
+            Some(_) => {
+                parser.skip_entry();
+
+                let mut element = &value[start..parser.offset];
+                if let [rest @ .., b','] = element {
+                    element = rest;
+                }
+                while let [rest @ .., b' ' | b'\t'] = element {
+                    element = rest;
+                }
+                elements.push(element);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "feat-std")]
+/// Parses all the `Server-Timing` headers of a header map, in order.
+///
+/// # Errors
+///
+/// When any of the values is malformed.
+pub fn parse_headers(headers: &HeaderMap) -> Result<Vec<TimingEntry>, ParseError> {
+    let mut entries = Vec::new();
+    for value in headers.get_all(SERVER_TIMING) {
+        entries.extend(parse_header(value.as_bytes())?);
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, Clone)]
+/// A cursor over a header value.
+struct Parser<'v> {
+    /// The header value.
+    value: &'v [u8],
+
+    /// The current position.
+    offset: usize,
+}
+
+impl Parser<'_> {
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        self.value.get(self.offset).copied()
+    }
+
+    #[inline]
+    fn error(&self) -> ParseError {
+        ParseError {
+            offset: self.offset,
+        }
+    }
+
+    /// Skips to the next entry, past the next comma outside quoted strings.
+    fn skip_entry(&mut self) {
+        let mut quoted = false;
+        while let Some(b) = self.peek() {
+            self.offset += 1;
+            match b {
+                b'"' => quoted = !quoted,
+                b'\\' if quoted => self.offset += 1,
+                b',' if !quoted => return,
+                _ => {}
+            }
+        }
+
+        self.offset = self.offset.min(self.value.len());
+    }
+
+    #[inline]
+    fn skip_ows(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.offset += 1;
+        }
+    }
+
+    /// Parses `name *( OWS ";" OWS param )`.
+    fn entry(&mut self) -> Result<TimingEntry, ParseError> {
+        let name = self.token()?;
+        let mut params = Vec::new();
+
+        loop {
+            let checkpoint = self.offset;
+            self.skip_ows();
+            if self.peek() != Some(b';') {
+                self.offset = checkpoint;
+                return Ok(TimingEntry { name, params });
+            }
+
+            self.offset += 1;
+            self.skip_ows();
+            params.push(self.param()?);
+        }
+    }
+
+    /// Parses `name [ OWS "=" OWS ( token / quoted-string ) ]`.
+    fn param(&mut self) -> Result<Param, ParseError> {
+        let name = self.token()?;
+
+        let checkpoint = self.offset;
+        self.skip_ows();
+        if self.peek() != Some(b'=') {
+            self.offset = checkpoint;
+            return Ok(Param {
+                name,
+                value: String::new(),
+                opaque: None,
+            });
+        }
+
+        self.offset += 1;
+        self.skip_ows();
+        let (value, opaque) = if self.peek() == Some(b'"') {
+            self.quoted_string()?
+        } else {
+            (self.token()?, None)
+        };
+
+        Ok(Param {
+            name,
+            value,
+            opaque,
+        })
+    }
+
+    /// Parses a non-empty token.
+    fn token(&mut self) -> Result<String, ParseError> {
+        let start = self.offset;
+        while self.peek().is_some_and(is_tchar) {
+            self.offset += 1;
+        }
+
+        if self.offset == start {
+            return Err(self.error());
+        }
+
+        // Tokens are ASCII.
+        Ok(String::from_utf8_lossy(&self.value[start..self.offset]).into_owned())
+    }
+
+    /// Parses a quoted string, unescaping quoted pairs.
+    ///
+    /// Returns the value, and its bytes if they are not valid UTF-8.
+    fn quoted_string(&mut self) -> Result<(String, Option<Vec<u8>>), ParseError> {
+        // The opening quote.
+        self.offset += 1;
+
+        let mut unescaped = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.offset += 1;
+                    return Ok(match String::from_utf8(unescaped) {
+                        Ok(value) => (value, None),
+                        Err(e) => (
+                            String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                            Some(e.into_bytes()),
+                        ),
+                    });
+                }
+                Some(b'\\') => {
+                    self.offset += 1;
+                    match self.peek() {
+                        Some(b) if is_qdtext(b) || b == b'"' || b == b'\\' => unescaped.push(b),
+                        _ => return Err(self.error()),
+                    }
+                }
+                Some(b) if is_qdtext(b) => unescaped.push(b),
+                _ => return Err(self.error()),
+            }
+            self.offset += 1;
+        }
+    }
+}
+
+#[inline]
+/// Whether the byte is allowed in a token.
+const fn is_tchar(b: u8) -> bool {
+    matches!(
+        b,
+        b'!' | b'#'
+            | b'$'
+            | b'%'
+            | b'&'
+            | b'\''
+            | b'*'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~'
+            | b'0'..=b'9'
+            | b'a'..=b'z'
+            | b'A'..=b'Z'
+    )
+}
+
+#[inline]
+/// Whether the byte is allowed unescaped in a quoted string, including
+/// opaque non-ASCII bytes.
+const fn is_qdtext(b: u8) -> bool {
+    matches!(b, b'\t' | b' ' | 0x21 | 0x23..=0x5B | 0x5D..=0x7E | 0x80..=0xFF)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "feat-std")]
+    use http::HeaderMap;
+    use http::HeaderValue;
+    use proptest::prelude::{any, prop, proptest, Strategy};
+
+    #[cfg(feature = "feat-std")]
+    use super::parse_headers;
+    use super::{elements, parse_header, parse_header_lenient, TimingEntry};
+    use crate::{format::DEFAULT_PRECISION, TimingMetric};
+
+    #[test]
+    fn parse() {
+        let entries =
+            parse_header(br#"app;desc="a \"b\", c";dur=12.3 , , cache ; hit;dur = 0.5,shed"#)
+                .unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name(), "app");
+        assert_eq!(entries[0].description(), Some(r#"a "b", c"#));
+        assert_eq!(entries[0].duration(), Some(12.3));
+        assert_eq!(entries[1].name(), "cache");
+        assert_eq!(entries[1].param("HIT"), Some(""));
+        assert_eq!(entries[1].duration(), Some(0.5));
+        assert_eq!(entries[2].name(), "shed");
+        assert_eq!(entries[2].params().count(), 0);
+
+        assert_eq!(parse_header(b"").unwrap(), []);
+        assert_eq!(parse_header(b" , ").unwrap(), []);
+        assert_eq!(parse_header(b"db;dur=abc").unwrap()[0].duration(), None);
+    }
+
+    #[test]
+    fn malformed() {
+        for (value, offset) in [
+            (&b"app;"[..], 4),
+            (b"app;dur=", 8),
+            (b"app dur", 4),
+            (b"app;desc=\"unterminated", 22),
+            (b"app;desc=\"\x01\"", 10),
+            (b"=1", 0),
+            (b"app;desc=\"a\"b", 12),
+        ] {
+            assert_eq!(
+                parse_header(value).unwrap_err().offset(),
+                offset,
+                "{}",
+                String::from_utf8_lossy(value)
+            );
+        }
+    }
+
+    #[test]
+    fn lenient() {
+        let (entries, errors) =
+            parse_header_lenient(br#"a;dur=1, b;desc="x, \"y"z, c;, d;desc="unterminated, e"#);
+
+        let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["a"]);
+        let offsets: Vec<_> = errors.iter().map(|e| e.offset()).collect();
+        assert_eq!(offsets, [24, 29, 54]);
+
+        let (entries, errors) = parse_header_lenient(b"a b, c;dur=1, =, d");
+        let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["c", "d"]);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn split_elements() {
+        assert_eq!(
+            elements(br#" a;desc="x, y" ,, b c , d;desc="\"," "#),
+            [&br#"a;desc="x, y""#[..], b"b c", br#"d;desc="\",""#]
+        );
+        assert!(elements(b" , ").is_empty());
+    }
+
+    #[test]
+    fn opaque_bytes() {
+        let entries = parse_header(b"db;desc=\"\xE9\xFF\"").unwrap();
+        assert_eq!(entries[0].description(), Some("\u{FFFD}\u{FFFD}"));
+        assert_eq!(entries[0].to_bytes(), b"db;desc=\"\xE9\xFF\"");
+    }
+
+    #[cfg(feature = "feat-std")]
+    #[test]
+    fn headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("server-timing", HeaderValue::from_static("a;dur=1"));
+        headers.append("server-timing", HeaderValue::from_static("b, c"));
+
+        let names = parse_headers(&headers).unwrap();
+        let names: Vec<_> = names.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn display() {
+        let entries = parse_header(br#"db ; desc = "a \"b\"" ; dur=1.50;hit;x="tok""#).unwrap();
+        assert_eq!(
+            entries[0].to_string(),
+            r#"db;desc="a \"b\"";dur=1.50;hit;x=tok"#
+        );
+    }
+
+    /// A token, e.g. a metric or param name.
+    fn token() -> impl Strategy<Value = String> {
+        "[!#$%&'*+.^_`|~0-9a-zA-Z-]{1,12}"
+    }
+
+    /// Optional whitespaces.
+    fn ows() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec!["", " ", "\t", "  "])
+    }
+
+    /// Quotes a value, escaping quotes and backslashes.
+    fn quote(value: &str) -> String {
+        format!("\"{}\"", value.replace('\\', r"\\").replace('"', "\\\""))
+    }
+
+    /// A param of an upstream entry, e.g. `desc="query"`, and its expected
+    /// serialization, with OWS removed, empty values omitted and values
+    /// quoted only if they aren't tokens.
+    fn param() -> impl Strategy<Value = (String, String)> {
+        (
+            token(),
+            ows(),
+            prop::option::of(prop::strategy::Union::new([
+                token().prop_map(|value| (value.clone(), value)).boxed(),
+                r#"[^\p{Cc}"\\]{0,12}"#.prop_map(|value| (format!("\"{value}\""), value)).boxed(),
+                r#"[ -~]{0,12}"#.prop_map(|value| (quote(&value), value)).boxed(),
+            ])),
+        )
+            .prop_map(|(name, ows, value)| match value {
+                Some((raw, value)) => {
+                    let expected = if value.is_empty() {
+                        name.clone()
+                    } else if super::is_token(&value) {
+                        format!("{name}={value}")
+                    } else {
+                        format!("{name}={}", quote(&value))
+                    };
+                    (format!("{name}{ows}={ows}{raw}"), expected)
+                }
+                None => (name.clone(), name),
+            })
+    }
+
+    /// An upstream entry, and its expected serialization.
+    fn entry() -> impl Strategy<Value = (String, String)> {
+        (token(), prop::collection::vec((ows(), param()), 0..4)).prop_map(|(name, params)| {
+            params.into_iter().fold(
+                (name.clone(), name),
+                |(entry, expected), (ows, (param, expected_param))| {
+                    (
+                        format!("{entry}{ows};{ows}{param}"),
+                        format!("{expected};{expected_param}"),
+                    )
+                },
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn metric_round_trip(
+            name in token(),
+            desc in prop::option::of(r"[^\p{Cc}]{0,24}"),
+            dur in prop::option::of(0.0..1e9f64),
+        ) {
+            let mut metric = TimingMetric::new(name.clone());
+            if let Some(desc) = &desc {
+                metric = metric.with_description(desc.clone());
+            }
+            if let Some(dur) = dur {
+                metric = metric.with_millis(dur);
+            }
+
+            let mut buf = Vec::new();
+            metric.encode(&mut buf, false, DEFAULT_PRECISION);
+            HeaderValue::from_bytes(&buf).unwrap();
+
+            let entries = parse_header(&buf).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].name(), name);
+            assert_eq!(entries[0].description(), desc.as_deref());
+            match (entries[0].duration(), dur) {
+                (Some(parsed), Some(dur)) => assert!((parsed - dur).abs() <= 0.05 + dur * 1e-12),
+                (parsed, dur) => assert_eq!(parsed, dur),
+            }
+        }
+
+        #[test]
+        fn header_round_trip(
+            entries in prop::collection::vec((entry(), ows()), 0..6),
+            opaque in any::<bool>(),
+        ) {
+            let mut value = entries
+                .iter()
+                .map(|((entry, _), ows)| format!("{ows}{entry}{ows}"))
+                .collect::<Vec<_>>()
+                .join(",")
+                .into_bytes();
+            let mut expected = entries
+                .iter()
+                .map(|((_, expected), _)| expected.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+                .into_bytes();
+            if opaque {
+                value.extend_from_slice(b", opaque;desc=\"\xfa\xfb\"");
+                if !expected.is_empty() {
+                    expected.extend_from_slice(b", ");
+                }
+                expected.extend_from_slice(b"opaque;desc=\"\xfa\xfb\"");
+            }
+
+            let parsed = parse_header(&value).unwrap();
+            assert_eq!(parsed.len(), entries.len() + usize::from(opaque));
+
+            let serialized = parsed
+                .iter()
+                .map(TimingEntry::to_bytes)
+                .collect::<Vec<_>>()
+                .join(&b", "[..]);
+            assert_eq!(
+                serialized,
+                expected,
+                "{}",
+                String::from_utf8_lossy(&value)
+            );
+            HeaderValue::from_bytes(&serialized).unwrap();
+            assert_eq!(parse_header(&serialized).unwrap(), parsed);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Rolling latency statistics.
+
+use std::{
+    cmp::Ordering,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The number of slots the window is split into.
+const SLOTS: usize = 6;
+
+/// The number of latency buckets, 4 per power of two microseconds, i.e. up to
+/// about an hour.
+const BUCKETS: usize = 128;
+
+#[derive(Debug, Clone)]
+/// Latency statistics over a rolling window, shared by all the clones.
+///
+/// Latencies are counted in logarithmic buckets (4 per power of two
+/// microseconds, i.e. within 19% of the actual value), and the window is
+/// split into 6 slots which expire one at a time, so memory usage is fixed
+/// whatever the traffic.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use miku_server_timing::Aggregator;
+/// let aggregator = Aggregator::new(Duration::from_secs(300));
+///
+/// for ms in 1..=100 {
+///     aggregator.record(Duration::from_millis(ms));
+/// }
+///
+/// assert_eq!(aggregator.rank(Duration::from_secs(1)), Some(100));
+/// assert_eq!(aggregator.rank(Duration::ZERO), Some(0));
+/// ```
+pub struct Aggregator {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// The reference point of slot epochs.
+    origin: Instant,
+
+    /// How long a slot lasts.
+    granularity: Duration,
+
+    /// The slots, indexed by epoch modulo [`SLOTS`].
+    slots: Mutex<[Slot; SLOTS]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    /// Which part of the timeline the counts belong to.
+    epoch: u64,
+
+    /// The number of samples per bucket.
+    counts: [u32; BUCKETS],
+}
+
+impl Aggregator {
+    #[inline]
+    /// Creates a new [`Aggregator`] keeping samples for the given window.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                origin: Instant::now(),
+                granularity: (window / SLOTS as u32).max(Duration::from_millis(1)),
+                slots: Mutex::new(
+                    [Slot {
+                        epoch: 0,
+                        counts: [0; BUCKETS],
+                    }; SLOTS],
+                ),
+            }),
+        }
+    }
+
+    #[inline]
+    /// Records a latency sample.
+    pub fn record(&self, elapsed: Duration) {
+        self.record_at(Instant::now(), elapsed);
+    }
+
+    #[inline]
+    /// Returns the percentile rank (0 to 100) of the given latency among the
+    /// samples of the window, or `None` if there's none.
+    pub fn rank(&self, elapsed: Duration) -> Option<u8> {
+        self.rank_at(Instant::now(), elapsed)
+    }
+
+    fn record_at(&self, now: Instant, elapsed: Duration) {
+        let epoch = self.epoch(now);
+        let mut slots = self
+            .inner
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let slot = &mut slots[(epoch % SLOTS as u64) as usize];
+        if slot.epoch != epoch {
+            slot.epoch = epoch;
+            slot.counts = [0; BUCKETS];
+        }
+
+        let count = &mut slot.counts[bucket(elapsed)];
+        *count = count.saturating_add(1);
+    }
+
+    fn rank_at(&self, now: Instant, elapsed: Duration) -> Option<u8> {
+        let epoch = self.epoch(now);
+        let bucket = bucket(elapsed);
+
+        let (mut below, mut same, mut total) = (0u64, 0u64, 0u64);
+        for slot in self
+            .inner
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|slot| epoch.saturating_sub(slot.epoch) < SLOTS as u64)
+        {
+            for (i, &count) in slot.counts.iter().enumerate() {
+                let count = u64::from(count);
+                total += count;
+                match i.cmp(&bucket) {
+                    Ordering::Less => below += count,
+                    Ordering::Equal => same += count,
+                    Ordering::Greater => {}
+                }
+            }
+        }
+
+        // Samples in the same bucket count as half below.
+        (total > 0).then(|| ((2 * below + same) * 100 / (2 * total)) as u8)
+    }
+
+    #[inline]
+    fn epoch(&self, now: Instant) -> u64 {
+        // Epochs start from `SLOTS` so that the initial slots look expired.
+        (now.saturating_duration_since(self.inner.origin).as_nanos()
+            / self.inner.granularity.as_nanos()) as u64
+            + SLOTS as u64
+    }
+}
+
+#[inline]
+/// Maps a latency to its bucket.
+fn bucket(elapsed: Duration) -> usize {
+    let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+    if micros < 4 {
+        return micros as usize;
+    }
+
+    let msb = 63 - micros.leading_zeros();
+    let sub = (micros >> (msb - 2)) & 3;
+
+    (((msb - 1) * 4) as usize + sub as usize).min(BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{bucket, Aggregator};
+
+    #[test]
+    fn buckets() {
+        assert_eq!(bucket(Duration::ZERO), 0);
+        assert_eq!(bucket(Duration::from_micros(3)), 3);
+        assert_eq!(bucket(Duration::from_micros(4)), 4);
+        assert_eq!(bucket(Duration::from_micros(7)), 7);
+        assert_eq!(bucket(Duration::from_micros(8)), 8);
+        assert!(bucket(Duration::from_millis(10)) < bucket(Duration::from_millis(13)));
+        assert_eq!(bucket(Duration::MAX), 127);
+    }
+
+    #[test]
+    fn rank() {
+        let aggregator = Aggregator::new(Duration::from_secs(60));
+        assert_eq!(aggregator.rank(Duration::from_millis(10)), None);
+
+        for ms in 1..=100 {
+            aggregator.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(aggregator.rank(Duration::ZERO), Some(0));
+        assert!(matches!(
+            aggregator.rank(Duration::from_millis(50)),
+            Some(40..=60)
+        ));
+        assert!(matches!(
+            aggregator.rank(Duration::from_millis(99)),
+            Some(90..)
+        ));
+        assert_eq!(aggregator.rank(Duration::from_secs(10)), Some(100));
+    }
+
+    #[test]
+    fn expire() {
+        let aggregator = Aggregator::new(Duration::from_secs(60));
+        let origin = aggregator.inner.origin;
+
+        aggregator.record_at(origin, Duration::from_millis(100));
+        aggregator.record_at(origin + Duration::from_secs(30), Duration::from_millis(1));
+        assert_eq!(
+            aggregator.rank_at(origin + Duration::from_secs(45), Duration::from_millis(10)),
+            Some(50)
+        );
+
+        // The first sample has expired.
+        assert_eq!(
+            aggregator.rank_at(origin + Duration::from_secs(65), Duration::from_millis(10)),
+            Some(100)
+        );
+        assert_eq!(
+            aggregator.rank_at(origin + Duration::from_secs(100), Duration::from_millis(10)),
+            None
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Bytes allocated while processing requests.
+//!
+//! The middleware doesn't install an allocator itself: it calls the counter
+//! given to [`ServerTimingLayer::with_alloc_counter`] around every poll of the
+//! inner service, which only sees the allocations made by the request itself
+//! as long as the counter is per thread, e.g. with a tracking allocator:
+//!
+//! ```rust
+//! use std::{
+//!     alloc::{GlobalAlloc, Layout, System},
+//!     cell::Cell,
+//! };
+//!
+//! use miku_server_timing::ServerTimingLayer;
+//!
+//! thread_local! {
+//!     static ALLOCATED: Cell<u64> = const { Cell::new(0) };
+//! }
+//!
+//! struct Tracking;
+//!
+//! unsafe impl GlobalAlloc for Tracking {
+//!     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+//!         let _ = ALLOCATED.try_with(|n| n.set(n.get() + layout.size() as u64));
+//!         System.alloc(layout)
+//!     }
+//!
+//!     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+//!         System.dealloc(ptr, layout)
+//!     }
+//! }
+//!
+//! #[global_allocator]
+//! static GLOBAL: Tracking = Tracking;
+//!
+//! let layer = ServerTimingLayer::new("app")
+//!     .with_alloc_counter(|| ALLOCATED.try_with(|n| n.get()).unwrap_or(0));
+//! ```
+//!
+//! Allocations made by tasks spawned from the handler are not counted.
+//!
+//! [`ServerTimingLayer::with_alloc_counter`]: crate::ServerTimingLayer::with_alloc_counter
+
+use std::io::Write;
+
+/// Appends the `alloc` param, e.g. `;alloc=152kb`.
+pub(crate) fn encode(buf: &mut Vec<u8>, bytes: u64) {
+    // Writing to a `Vec` never fails.
+    let _ = match bytes {
+        0..=1023 => write!(buf, ";alloc={bytes}b"),
+        1024..=0xF_FFFF => write!(buf, ";alloc={}kb", bytes / 1024),
+        _ => write!(buf, ";alloc={}mb", bytes / (1024 * 1024)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+
+    #[test]
+    fn units() {
+        for (bytes, expected) in [
+            (0, ";alloc=0b"),
+            (1023, ";alloc=1023b"),
+            (152 * 1024 + 100, ";alloc=152kb"),
+            (3 * 1024 * 1024, ";alloc=3mb"),
+        ] {
+            let mut buf = Vec::new();
+            encode(&mut buf, bytes);
+            assert_eq!(buf, expected.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Integration with `tower::buffer`.
+//!
+//! Install [`BufferWaitLayer`] under the buffer layer to report how long the
+//! request waited in the buffer as a `buffer;dur=` entry:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::new()
+//!     .layer(ServerTimingLayer::new("app"))
+//!     .buffer(1024)
+//!     .layer(BufferWaitLayer)
+//!     .service(inner);
+//! ```
+//!
+//! The wait time is measured from when the request was received by
+//! [`ServerTimingService`](crate::ServerTimingService), so anything between
+//! the two layers counts.
+
+use std::{
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use http::Request;
+
+use crate::{ServerTimingHandle, TimingMetric};
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A layer recording the time a request waited in the buffer, to be installed
+/// under the buffer layer.
+pub struct BufferWaitLayer;
+
+impl<S> tower_layer::Layer<S> for BufferWaitLayer {
+    type Service = BufferWaitService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        BufferWaitService { service }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A service recording the time a request waited in the buffer.
+pub struct BufferWaitService<S> {
+    /// The service to wrap.
+    service: S,
+}
+
+impl<S, ReqBody> tower_service::Service<Request<ReqBody>> for BufferWaitService<S>
+where
+    S: tower_service::Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if let Some(handle) = req.extensions().get::<ServerTimingHandle>() {
+            handle.record(
+                TimingMetric::new("buffer")
+                    .with_duration(Instant::now().saturating_duration_since(handle.start())),
+            );
+        }
+
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use http::{Request, Response};
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    use super::BufferWaitLayer;
+    use crate::ServerTimingLayer;
+
+    /// Returns the `buffer` duration of the given response.
+    fn buffer_wait(response: &Response<()>) -> f64 {
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let (_, wait) = hdr.split_once(", buffer;dur=").unwrap();
+        wait.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn record_buffer_wait() {
+        // The second request waits in the buffer behind the slow first one.
+        let service = ServiceBuilder::new()
+            .layer(ServerTimingLayer::new("svc1"))
+            .buffer(1)
+            .layer(BufferWaitLayer)
+            .concurrency_limit(1)
+            .service(service_fn(|_req: Request<()>| async move {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let (first, second) = tokio::join!(
+            service.clone().oneshot(Request::new(())),
+            service.clone().oneshot(Request::new(())),
+        );
+
+        let first = buffer_wait(&first.unwrap());
+        let second = buffer_wait(&second.unwrap());
+        assert!(first < 30.0, "Invalid first wait: {first}");
+        assert!(second >= 30.0, "Invalid second wait: {second}");
+    }
+}
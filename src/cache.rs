@@ -0,0 +1,142 @@
+//! Edge cache diagnostics.
+
+use http::{header, HeaderMap};
+
+use crate::TimingMetric;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// The cache status of a response, to be inserted into the response extensions
+/// by cache middlewares.
+///
+/// The middleware then adds a `cache;desc="hit"` entry, and leaves out the
+/// `handler` entry of [`ServerTimingLayer::with_phases`] for hits, so that
+/// cached responses are obviously distinguishable:
+///
+/// ```rust
+/// # use http::Response;
+/// # use miku_server_timing::CacheStatus;
+/// let mut response = Response::new(());
+/// response.extensions_mut().insert(CacheStatus::Hit);
+/// ```
+///
+/// [`ServerTimingLayer::with_phases`]: crate::ServerTimingLayer::with_phases
+pub enum CacheStatus {
+    /// Served from the cache.
+    Hit,
+
+    /// Not in the cache, served by the handler.
+    Miss,
+
+    /// Served from the cache while being revalidated.
+    Stale,
+
+    /// The cache was not used, e.g. for uncacheable requests.
+    Bypass,
+}
+
+impl CacheStatus {
+    #[inline]
+    /// Returns the status as a `desc`, e.g. `hit`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+            Self::Stale => "stale",
+            Self::Bypass => "bypass",
+        }
+    }
+
+    #[inline]
+    /// Returns the `cache` marker.
+    pub(crate) fn marker(self) -> TimingMetric {
+        TimingMetric::new("cache").with_description(self.as_str())
+    }
+}
+
+/// The headers telling the cache status, by precedence.
+const STATUS_HEADERS: [&str; 3] = ["cf-cache-status", "x-cache", "x-cache-status"];
+
+/// Converts the cache headers of the response into markers, e.g.
+/// `cdn-cache;desc="HIT"` from `CF-Cache-Status`, `X-Cache` or
+/// `X-Cache-Status`, and `cdn-age;desc="120"` from `Age`.
+pub(crate) fn cache_markers(headers: &HeaderMap) -> Vec<TimingMetric> {
+    let mut markers = Vec::new();
+
+    if let Some(status) = STATUS_HEADERS
+        .iter()
+        .find_map(|name| status(headers.get(*name)?.to_str().ok()?))
+    {
+        markers.push(TimingMetric::new("cdn-cache").with_description(status));
+    }
+
+    if let Some(age) = headers
+        .get(header::AGE)
+        .and_then(|age| age.to_str().ok()?.trim().parse::<u64>().ok())
+    {
+        markers.push(TimingMetric::new("cdn-age").with_description(age.to_string()));
+    }
+
+    markers
+}
+
+/// Normalizes a cache status, e.g. `HIT` from `Hit from cloudfront`, or from
+/// `MISS, HIT` listing the status of every cache layer, the last one being
+/// the closest to the client.
+fn status(value: &str) -> Option<String> {
+    let last = value.rsplit(',').next()?.trim();
+    let status = last.split_whitespace().next()?;
+
+    status
+        .bytes()
+        .all(|b| b.is_ascii_alphabetic() || b == b'_' || b == b'-')
+        .then(|| status.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue};
+
+    use super::{cache_markers, status, CacheStatus};
+
+    #[test]
+    fn cache_status() {
+        let marker = CacheStatus::Stale.marker();
+        assert_eq!(marker.name(), "cache");
+        assert_eq!(marker.description(), Some("stale"));
+        assert_eq!(CacheStatus::Bypass.as_str(), "bypass");
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(status("HIT").as_deref(), Some("HIT"));
+        assert_eq!(status("Hit from cloudfront").as_deref(), Some("HIT"));
+        assert_eq!(status("MISS, HIT").as_deref(), Some("HIT"));
+        assert_eq!(status(" dynamic ").as_deref(), Some("DYNAMIC"));
+        assert_eq!(status("").as_deref(), None);
+        assert_eq!(status("\"hit\"").as_deref(), None);
+    }
+
+    #[test]
+    fn markers() {
+        assert!(cache_markers(&HeaderMap::new()).is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-cache", HeaderValue::from_static("Miss from cloudfront"));
+        headers.insert("cf-cache-status", HeaderValue::from_static("EXPIRED"));
+        headers.insert("age", HeaderValue::from_static("120"));
+
+        let markers = cache_markers(&headers);
+        assert_eq!(markers.len(), 2);
+        assert_eq!(markers[0].name(), "cdn-cache");
+        assert_eq!(markers[0].description(), Some("EXPIRED"));
+        assert_eq!(markers[1].name(), "cdn-age");
+        assert_eq!(markers[1].description(), Some("120"));
+
+        headers.remove("cf-cache-status");
+        headers.insert("age", HeaderValue::from_static("soon"));
+        let markers = cache_markers(&headers);
+        assert_eq!(markers.len(), 1);
+        assert_eq!(markers[0].description(), Some("MISS"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Emission depending on the content type of the response.
+
+use http::HeaderValue;
+
+#[derive(Debug, Clone, Default)]
+/// The media ranges of the responses getting the header, all if empty.
+pub(crate) struct ContentTypes(Vec<MediaRange>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A lowercase media range, e.g. `text/html`, `image/*` or `*/*`.
+struct MediaRange {
+    /// The type, `*` for any.
+    ty: String,
+
+    /// The subtype, `*` for any.
+    subtype: String,
+}
+
+impl ContentTypes {
+    #[inline]
+    pub(crate) const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Adds the given media range, a whole type if without subtype, e.g.
+    /// `text`.
+    pub(crate) fn push(&mut self, range: &str) {
+        let range = range.trim().to_ascii_lowercase();
+        let (ty, subtype) = range.split_once('/').unwrap_or((&range, "*"));
+
+        self.0.push(MediaRange {
+            ty: ty.trim().to_owned(),
+            subtype: subtype.trim().to_owned(),
+        });
+    }
+
+    #[inline]
+    /// Returns whether all the responses get the header.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns whether a response with the given content type gets the
+    /// header, which responses without one do.
+    pub(crate) fn allows(&self, content_type: Option<&HeaderValue>) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+
+        let Some(content_type) = content_type else {
+            return true;
+        };
+
+        let Ok(content_type) = content_type.to_str() else {
+            return false;
+        };
+
+        // Parameters like `charset` don't matter.
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        let (ty, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+
+        self.0.iter().any(|range| {
+            (range.ty == "*" || range.ty.eq_ignore_ascii_case(ty))
+                && (range.subtype == "*" || range.subtype.eq_ignore_ascii_case(subtype.trim()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::ContentTypes;
+
+    #[test]
+    fn allows() {
+        let mut types = ContentTypes::new();
+        assert!(types.allows(Some(&HeaderValue::from_static("image/png"))));
+
+        types.push("text/html");
+        types.push("Application/JSON");
+        types.push("font/*");
+        types.push("audio");
+
+        for (content_type, expected) in [
+            ("text/html", true),
+            ("text/html; charset=utf-8", true),
+            ("TEXT/HTML", true),
+            ("application/json", true),
+            ("font/woff2", true),
+            ("audio/ogg", true),
+            ("text/css", false),
+            ("image/png", false),
+            ("video/mp4", false),
+            ("application/jsonx", false),
+            ("text", false),
+        ] {
+            let content_type = HeaderValue::from_static(content_type);
+            assert_eq!(
+                types.allows(Some(&content_type)),
+                expected,
+                "{content_type:?}"
+            );
+        }
+
+        assert!(types.allows(None));
+
+        let mut types = ContentTypes::new();
+        types.push("*/*");
+        assert!(types.allows(Some(&HeaderValue::from_static("video/mp4"))));
+    }
+}
@@ -0,0 +1,149 @@
+//! Implicit timing context, scoped to the task handling the request.
+//!
+//! Install [`ContextLayer`] under
+//! [`ServerTimingLayer`](crate::ServerTimingLayer), and library code deep in
+//! the call stack can record metrics without threading a [`ServerTimingHandle`]
+//! through every function signature:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::new()
+//!     .layer(ServerTimingLayer::new("app"))
+//!     .layer(ContextLayer)
+//!     .service(inner);
+//!
+//! async fn get_user(id: u64) -> User {
+//!     let start = Instant::now();
+//!     let user = cache.get(id).await;
+//!     miku_server_timing::record("cache", start.elapsed());
+//!     user
+//! }
+//! ```
+//!
+//! The context is a `tokio` task-local value: it's not inherited by spawned
+//! tasks, see [`current`] and [`scope`] to carry it over.
+
+use std::{
+    borrow::Cow,
+    future::Future,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http::Request;
+use tokio::task::futures::TaskLocalFuture;
+
+use crate::{ServerTimingHandle, TimingMetric};
+
+tokio::task_local! {
+    static CURRENT: Option<ServerTimingHandle>;
+}
+
+#[inline]
+/// Records a custom metric with the given duration into the current context.
+///
+/// Does nothing outside of a context.
+pub fn record(name: impl Into<Cow<'static, str>>, dur: Duration) {
+    record_metric(TimingMetric::new(name).with_duration(dur));
+}
+
+#[inline]
+/// Records a custom metric into the current context.
+///
+/// Does nothing outside of a context.
+pub fn record_metric(metric: TimingMetric) {
+    if let Some(handle) = current() {
+        handle.record(metric);
+    }
+}
+
+#[inline]
+/// Returns the handle of the current context, if any.
+pub fn current() -> Option<ServerTimingHandle> {
+    CURRENT.try_with(Clone::clone).ok().flatten()
+}
+
+#[inline]
+/// Runs the given future within the context of the given handle, e.g. for a
+/// spawned task.
+pub fn scope<F: Future>(
+    handle: Option<ServerTimingHandle>,
+    future: F,
+) -> TaskLocalFuture<Option<ServerTimingHandle>, F> {
+    CURRENT.scope(handle, future)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A layer running the inner service within the context of the request, to
+/// be installed under [`ServerTimingLayer`](crate::ServerTimingLayer).
+pub struct ContextLayer;
+
+impl<S> tower_layer::Layer<S> for ContextLayer {
+    type Service = ContextService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ContextService { service }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A service running the inner service within the context of the request.
+pub struct ContextService<S> {
+    /// The service to wrap.
+    service: S,
+}
+
+impl<S, ReqBody> tower_service::Service<Request<ReqBody>> for ContextService<S>
+where
+    S: tower_service::Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TaskLocalFuture<Option<ServerTimingHandle>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let handle = req.extensions().get::<ServerTimingHandle>().cloned();
+
+        scope(handle, self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use http::{Request, Response};
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+
+    use super::{current, record, ContextLayer};
+    use crate::ServerTimingLayer;
+
+    async fn deep_in_the_stack() {
+        record("cache", Duration::from_millis(3));
+    }
+
+    #[tokio::test]
+    async fn record_implicitly() {
+        // Outside of a context.
+        assert!(current().is_none());
+        deep_in_the_stack().await;
+
+        let response = ServerTimingLayer::new("svc1")
+            .layer(
+                ContextLayer.layer(service_fn(|_req: Request<()>| async move {
+                    deep_in_the_stack().await;
+                    Ok::<_, Infallible>(Response::new(()))
+                })),
+            )
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(", cache;dur=3.0"), "{hdr}");
+    }
+}
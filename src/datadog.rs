@@ -0,0 +1,148 @@
+//! Tagging the Datadog APM span of the request with the timings.
+//!
+//! Rust services usually report to Datadog through `tracing` spans, bridged
+//! by `tracing-opentelemetry` to an OpenTelemetry tracer exporting to the
+//! Datadog agent, e.g. with the `opentelemetry-datadog` exporter, so that the
+//! same numbers show in the browser devtools and in the traces:
+//!
+//! ```rust,ignore
+//! let layer = ServerTimingLayer::new("app").with_on_timing(datadog::tag_current_span);
+//! ```
+//!
+//! The callback runs as the response is returned, so the `tracing` span of
+//! the request must be the current one there, e.g. entered by an outer
+//! tracing layer like `tower_http::trace::TraceLayer`.
+//!
+//! Each metric becomes a tag named after [`PREFIX`] and its full name, e.g.
+//! `server_timing.db.query` with the duration in milliseconds, or `true` for
+//! markers without duration, and `server_timing.db.query.desc` with the
+//! description if any.
+
+use opentelemetry::KeyValue;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::TimingReport;
+
+/// The prefix of the tags, grouping them in the span attributes.
+pub const PREFIX: &str = "server_timing";
+
+/// Returns the tags of the given report, in the order of the metrics.
+///
+/// Spans keep the last value set for a key, so a name repeated in the report
+/// ends up with the value of its last metric.
+pub fn attributes(report: &TimingReport) -> Vec<KeyValue> {
+    let mut attributes = Vec::with_capacity(report.metrics().len());
+
+    for metric in report {
+        let key = format!("{PREFIX}.{}", metric.full_name());
+
+        if let Some(description) = metric.description() {
+            attributes.push(KeyValue::new(format!("{key}.desc"), description.to_owned()));
+        }
+
+        attributes.push(match metric.millis() {
+            Some(millis) => KeyValue::new(key, millis),
+            None => KeyValue::new(key, true),
+        });
+    }
+
+    attributes
+}
+
+/// Sets the tags of the given report on the OpenTelemetry span of the given
+/// `tracing` span.
+///
+/// Does nothing if the span is disabled, or without the
+/// `tracing-opentelemetry` layer.
+pub fn tag_span(span: &tracing::Span, report: &TimingReport) {
+    for KeyValue { key, value, .. } in attributes(report) {
+        span.set_attribute(key, value);
+    }
+}
+
+/// Sets the tags of the given report on the current `tracing` span, see
+/// [`tag_span`], to be passed to [`ServerTimingLayer::with_on_timing`].
+///
+/// [`ServerTimingLayer::with_on_timing`]: crate::ServerTimingLayer::with_on_timing
+pub fn tag_current_span(report: &TimingReport) {
+    tag_span(&tracing::Span::current(), report);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http::{Request, Response};
+    use opentelemetry::{trace::TracerProvider as _, KeyValue, Value};
+    use opentelemetry_sdk::{testing::trace::InMemorySpanExporter, trace::TracerProvider};
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{attributes, tag_current_span};
+    use crate::{ServerTimingHandle, ServerTimingLayer, TimingMetric, TimingReport};
+
+    #[test]
+    fn tags() {
+        let report = TimingReport::new()
+            .with(TimingMetric::new("app").with_millis(12.5))
+            .with(
+                TimingMetric::new("query")
+                    .with_parent("db")
+                    .with_millis(3.0)
+                    .with_description("users"),
+            )
+            .with(TimingMetric::new("miss"));
+
+        let attributes = attributes(&report);
+        let pairs: Vec<_> = attributes
+            .iter()
+            .map(|KeyValue { key, value, .. }| (key.as_str(), value.clone()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("server_timing.app", Value::F64(12.5)),
+                ("server_timing.db.query.desc", Value::from("users")),
+                ("server_timing.db.query", Value::F64(3.0)),
+                ("server_timing.miss", Value::Bool(true)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tag_request_span() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        ServerTimingLayer::new("app")
+            .with_on_timing(tag_current_span)
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db").with_millis(3.0));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .instrument(tracing::info_span!("request"))
+            .await
+            .unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        let tag = |name: &str| {
+            spans[0]
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == name)
+                .map(|attribute| attribute.value.clone())
+        };
+        assert!(matches!(tag("server_timing.app"), Some(Value::F64(_))));
+        assert_eq!(tag("server_timing.db"), Some(Value::F64(3.0)));
+    }
+}
@@ -0,0 +1,216 @@
+//! A debug endpoint showing the slowest recent requests.
+//!
+//! ```rust,ignore
+//! let recorder = MemoryRecorder::new(10_000);
+//!
+//! let app = Router::new()
+//!     .route("/", get(handler))
+//!     .layer(
+//!         ServerTimingLayer::new("app")
+//!             .with_exporter(BatchConfig::new().spawn(recorder.clone())?),
+//!     )
+//!     .nest(
+//!         "/debug/timing",
+//!         debug_routes(recorder, ValidateRequestHeaderLayer::bearer("secret")),
+//!     );
+//! ```
+//!
+//! - `GET /` renders an HTML table of the slowest recent requests with their
+//!   full metric breakdown.
+//! - `GET /json` returns the same as a JSON array, see
+//!   [`TimingReport::to_json`].
+//! - `GET /?id={id}` renders the waterfall of a single request, linked from the
+//!   table, see [`render_waterfall`].
+//!
+//! The endpoint exposes request paths and timings, so an auth layer must be
+//! supplied.
+
+use std::{convert::Infallible, fmt::Write};
+
+use axum::{
+    extract::{Request, State},
+    response::{Html, IntoResponse},
+    routing::{get, Route},
+    Router,
+};
+use http::{header, StatusCode, Uri};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    export::memory::MemoryRecorder, render_waterfall, waterfall::push_escaped, TimingReport,
+};
+
+/// How many requests are shown.
+const SLOWEST: usize = 50;
+
+/// Creates a router serving the slowest requests kept by the given recorder,
+/// protected by the given auth layer.
+pub fn debug_routes<L>(recorder: MemoryRecorder, auth: L) -> Router
+where
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+{
+    Router::new()
+        .route("/", get(html))
+        .route("/json", get(json))
+        .with_state(recorder)
+        .layer(auth)
+}
+
+async fn html(
+    State(recorder): State<MemoryRecorder>,
+    uri: Uri,
+) -> Result<Html<String>, StatusCode> {
+    if let Some(id) = uri
+        .query()
+        .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("id=")))
+    {
+        return id
+            .parse()
+            .ok()
+            .and_then(|id| recorder.get(id))
+            .map(|report| Html(render_waterfall(&report)))
+            .ok_or(StatusCode::NOT_FOUND);
+    }
+
+    let mut page = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Slowest \
+         requests</title><style>body{font-family:monospace}td,th{padding:2px \
+         8px;text-align:left;vertical-align:top}</style></head><body><h1>Slowest \
+         requests</h1><table><tr><th>dur \
+         (ms)</th><th>status</th><th>method</th><th>route</th><th>metrics</th></tr>",
+    );
+
+    for (id, report) in recorder.slowest(SLOWEST) {
+        render_row(&mut page, id, &report);
+    }
+
+    page.push_str("</table></body></html>");
+    Ok(Html(page))
+}
+
+async fn json(State(recorder): State<MemoryRecorder>) -> impl IntoResponse {
+    let mut body = String::from("[");
+    for (i, (_, report)) in recorder.slowest(SLOWEST).iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        body.push_str(&report.to_json());
+    }
+    body.push(']');
+
+    ([(header::CONTENT_TYPE, "application/json")], body)
+}
+
+/// Appends a table row for the report, linking to its waterfall.
+fn render_row(page: &mut String, id: u64, report: &TimingReport) {
+    let (entry, metrics) = match report.metrics() {
+        [entry, metrics @ ..] => (Some(entry), metrics),
+        [] => (None, &[][..]),
+    };
+
+    // Writing to a `String` never fails.
+    let _ = write!(
+        page,
+        "<tr><td><a href=\"?id={id}\">{:.1}</a></td><td>{}</td><td>",
+        entry.and_then(|entry| entry.millis()).unwrap_or_default(),
+        report.status().map_or(0, |status| status.as_u16()),
+    );
+    push_escaped(page, report.method().map_or("", |method| method.as_str()));
+    page.push_str("</td><td>");
+    push_escaped(page, report.route().unwrap_or_default());
+    page.push_str("</td><td>");
+
+    for metric in metrics {
+        push_escaped(page, &metric.full_name());
+        if let Some(dur) = metric.millis() {
+            let _ = write!(page, " {dur:.1}ms");
+        }
+        if let Some(desc) = metric.description() {
+            page.push_str(" (");
+            push_escaped(page, desc);
+            page.push(')');
+        }
+        page.push_str("<br>");
+    }
+
+    page.push_str("</td></tr>");
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use http::{Method, Request, StatusCode};
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::layer_fn;
+
+    use super::{debug_routes, render_row};
+    use crate::{export::memory::MemoryRecorder, TimingMetric, TimingReport};
+
+    #[test]
+    fn row() {
+        let report = TimingReport::new()
+            .with(TimingMetric::new("app").with_millis(12.34))
+            .with(
+                TimingMetric::new("db")
+                    .with_millis(3.0)
+                    .with_description("<users>"),
+            )
+            .with_method(Method::GET)
+            .with_route("/users")
+            .with_status(StatusCode::OK);
+
+        let mut page = String::new();
+        render_row(&mut page, 7, &report);
+        assert_eq!(
+            page,
+            "<tr><td><a href=\"?id=7\">12.3</a></td><td>200</td><td>GET</td><td>/users</td><td>db \
+             3.0ms (&lt;users&gt;)<br></td></tr>"
+        );
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let recorder = MemoryRecorder::new(10);
+        recorder.record(TimingReport::new().with(TimingMetric::new("app").with_millis(1.0)));
+
+        // Rejects requests without the `x-admin` header.
+        let auth = layer_fn(|inner: axum::routing::Route| {
+            service_fn(move |req: Request<Body>| {
+                let inner = inner.clone();
+                async move {
+                    if req.headers().contains_key("x-admin") {
+                        inner.oneshot(req).await
+                    } else {
+                        let mut response = axum::response::Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::UNAUTHORIZED;
+                        Ok(response)
+                    }
+                }
+            })
+        });
+        let app = debug_routes(recorder, auth);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/json").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(
+                Request::get("/json")
+                    .header("x-admin", "1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
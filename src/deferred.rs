@@ -0,0 +1,163 @@
+//! Metrics resolved asynchronously alongside the inner service.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use http::Request;
+
+use crate::{report::inspect, TimingMetric};
+
+/// The future returned by the hook.
+type Pending = Pin<Box<dyn Future<Output = Vec<TimingMetric>> + Send>>;
+
+/// The signature of [`AsyncMetrics`].
+type Resolver = dyn Fn(&Request<()>) -> Pending + Send + Sync;
+
+#[derive(Clone)]
+/// A hook returning a future resolving extra metrics of the request.
+pub(crate) struct AsyncMetrics {
+    /// How long the future may run, from when the request was received.
+    budget: Duration,
+
+    resolver: Arc<Resolver>,
+}
+
+impl AsyncMetrics {
+    #[inline]
+    pub(crate) fn new<F, Fut>(budget: Duration, f: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Vec<TimingMetric>> + Send + 'static,
+    {
+        Self {
+            budget,
+            resolver: Arc::new(move |req| Box::pin(f(req))),
+        }
+    }
+
+    #[inline]
+    /// Starts resolving the metrics of the given request.
+    pub(crate) fn start<B>(&self, req: Request<B>, start: Instant) -> (Request<B>, Deferred) {
+        let (req, pending) = inspect(req, |view| (self.resolver)(view));
+
+        let deferred = Deferred {
+            // The future is only ever accessed through `&mut`, the mutex
+            // merely makes the response future `Sync`.
+            pending: Some(Mutex::new(pending)),
+            deadline: start + self.budget,
+            metrics: Vec::new(),
+        };
+
+        (req, deferred)
+    }
+}
+
+impl fmt::Debug for AsyncMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncMetrics")
+            .field("budget", &self.budget)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The metrics of one request being resolved.
+pub(crate) struct Deferred {
+    /// The future, until it completes or runs out of time.
+    pending: Option<Mutex<Pending>>,
+
+    /// When the future is given up on.
+    deadline: Instant,
+
+    /// The resolved metrics.
+    metrics: Vec<TimingMetric>,
+}
+
+impl Deferred {
+    /// Polls the future, giving up on it once past the deadline.
+    pub(crate) fn poll(&mut self, cx: &mut Context<'_>) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+
+        if Instant::now() >= self.deadline {
+            self.pending = None;
+            return;
+        }
+
+        let pending = pending.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Poll::Ready(metrics) = pending.as_mut().poll(cx) {
+            self.metrics = metrics;
+            self.pending = None;
+        }
+    }
+
+    /// Takes the resolved metrics, giving up on the future if still pending.
+    pub(crate) fn take(&mut self) -> Vec<TimingMetric> {
+        self.pending = None;
+        std::mem::take(&mut self.metrics)
+    }
+}
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deferred")
+            .field("pending", &self.pending.is_some())
+            .field("deadline", &self.deadline)
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::{pending, ready},
+        sync::Arc,
+        task::{Context, Wake, Waker},
+        time::{Duration, Instant},
+    };
+
+    use http::Request;
+
+    use super::AsyncMetrics;
+    use crate::TimingMetric;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn budget() {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+
+        let done = AsyncMetrics::new(Duration::from_secs(1), |req| {
+            ready(vec![
+                TimingMetric::new("flag").with_description(req.uri().path().to_owned())
+            ])
+        });
+        let (_, mut deferred) = done.start(Request::new(()), Instant::now());
+        deferred.poll(&mut cx);
+        let metrics = deferred.take();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].description(), Some("/"));
+
+        let late = AsyncMetrics::new(Duration::ZERO, |_| ready(vec![TimingMetric::new("flag")]));
+        let (_, mut deferred) = late.start(Request::new(()), Instant::now());
+        deferred.poll(&mut cx);
+        assert!(deferred.take().is_empty());
+
+        let stuck = AsyncMetrics::new(Duration::from_secs(1), |_| pending());
+        let (_, mut deferred) = stuck.start(Request::new(()), Instant::now());
+        deferred.poll(&mut cx);
+        assert!(deferred.take().is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+//! The developer mode switching requests to verbose emission.
+
+use std::time::Duration;
+
+use http::Uri;
+use server_timing_core::MAX_PRECISION;
+
+use crate::{policy::MinDuration, Config, Preset};
+
+/// The query pair switching a request to verbose emission.
+const VERBOSE: &str = "server_timing=verbose";
+
+/// Returns whether the query of the given URI asks for verbose emission.
+pub(crate) fn requested(uri: &Uri) -> bool {
+    uri.query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == VERBOSE))
+}
+
+/// Returns the options of verbose emission: the ones of [`Preset::Verbose`]
+/// at full precision, for every response and without size limits.
+pub(crate) fn verbose(config: &Config) -> Config {
+    let mut config = config.clone();
+    Preset::Verbose.apply(&mut config);
+
+    config.precision = MAX_PRECISION;
+    config.policies.min_duration = MinDuration(Duration::ZERO);
+    config.not_found = true;
+    config.policies.sampling.clear();
+    config.policies.rate_limit = None;
+    config.max_header_size = None;
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::Uri;
+
+    use super::{requested, verbose};
+    use crate::{Config, Sampling};
+
+    #[test]
+    fn query() {
+        for (uri, expected) in [
+            ("/", false),
+            ("/?server_timing=verbose", true),
+            ("/?a=1&server_timing=verbose&b=2", true),
+            ("/?server_timing=verbose2", false),
+            ("/?server_timing=minimal", false),
+        ] {
+            assert_eq!(requested(&uri.parse::<Uri>().unwrap()), expected, "{uri}");
+        }
+    }
+
+    #[test]
+    fn options() {
+        let mut config = Config::new("app")
+            .with_min_duration(Duration::from_secs(1))
+            .with_max_header_size(100);
+        config.policies.sampling.default = Some(Sampling::new(0.0));
+
+        let config = verbose(&config);
+        assert_eq!(config.precision, 6);
+        assert_eq!(config.policies.min_duration.0, Duration::ZERO);
+        assert!(config.policies.sampling.default.is_none());
+        assert!(config.max_header_size.is_none());
+        assert!(config.phases);
+    }
+}
@@ -0,0 +1,149 @@
+//! Comparison of two reports.
+
+use crate::TimingReport;
+
+#[derive(Debug, Clone, PartialEq)]
+/// The durations of a metric in two reports, see [`TimingReport::diff`].
+pub struct MetricDelta {
+    /// The full name of the metric, e.g. `db.query1`.
+    name: String,
+
+    /// The duration in the first report, in milliseconds.
+    before: Option<f64>,
+
+    /// The duration in the second report, in milliseconds.
+    after: Option<f64>,
+}
+
+impl MetricDelta {
+    #[inline]
+    /// Returns the full name of the metric, e.g. `db.query1`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    /// Returns the duration in the first report in milliseconds, if any.
+    pub fn before(&self) -> Option<f64> {
+        self.before
+    }
+
+    #[inline]
+    /// Returns the duration in the second report in milliseconds, if any.
+    pub fn after(&self) -> Option<f64> {
+        self.after
+    }
+
+    #[inline]
+    /// Returns how many milliseconds the metric took longer in the second
+    /// report, negative if faster, or `None` unless timed in both.
+    pub fn delta(&self) -> Option<f64> {
+        Some(self.after? - self.before?)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The per-metric deltas between two reports, see [`TimingReport::diff`].
+pub struct TimingDiff {
+    /// The deltas, in the order of the first report, then the metrics only in
+    /// the second one.
+    deltas: Vec<MetricDelta>,
+}
+
+impl TimingDiff {
+    #[inline]
+    /// Returns the deltas, in the order of the first report, then the metrics
+    /// only in the second one.
+    pub fn deltas(&self) -> &[MetricDelta] {
+        &self.deltas
+    }
+
+    #[inline]
+    /// Returns the delta of the metric with the given full name.
+    pub fn get(&self, name: &str) -> Option<&MetricDelta> {
+        self.deltas.iter().find(|delta| delta.name == name)
+    }
+
+    /// Returns the metrics which took longer than the given number of
+    /// milliseconds more in the second report.
+    pub fn regressions(&self, millis: f64) -> impl Iterator<Item = &MetricDelta> + '_ {
+        self.deltas
+            .iter()
+            .filter(move |delta| delta.delta().is_some_and(|delta| delta > millis))
+    }
+}
+
+/// Compares the durations of the metrics of the reports, see
+/// [`TimingReport::diff`].
+pub(crate) fn diff(before: &TimingReport, after: &TimingReport) -> TimingDiff {
+    let mut deltas: Vec<MetricDelta> = Vec::new();
+
+    for (metric, is_after) in before
+        .metrics()
+        .iter()
+        .map(|metric| (metric, false))
+        .chain(after.metrics().iter().map(|metric| (metric, true)))
+    {
+        let name = metric.full_name();
+        let index = match deltas.iter().position(|delta| delta.name == name) {
+            Some(index) => index,
+            None => {
+                deltas.push(MetricDelta {
+                    name: name.into_owned(),
+                    before: None,
+                    after: None,
+                });
+                deltas.len() - 1
+            }
+        };
+
+        let delta = &mut deltas[index];
+        let dur = if is_after {
+            &mut delta.after
+        } else {
+            &mut delta.before
+        };
+        if let Some(millis) = metric.millis() {
+            *dur = Some(dur.unwrap_or(0.0) + millis);
+        }
+    }
+
+    TimingDiff { deltas }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{TimingMetric, TimingReport};
+
+    #[test]
+    fn diff() {
+        let before = TimingReport::new()
+            .with(TimingMetric::new("db").with_millis(2.0))
+            .with(TimingMetric::new("db").with_millis(3.0))
+            .with(TimingMetric::new("cache").with_description("hit"))
+            .with(TimingMetric::new("removed").with_millis(1.0));
+        let after = TimingReport::new()
+            .with(TimingMetric::new("added").with_millis(4.0))
+            .with(TimingMetric::new("db").with_millis(4.0))
+            .with(TimingMetric::new("cache").with_description("miss"));
+
+        let diff = before.diff(&after);
+        let names: Vec<_> = diff.deltas().iter().map(|delta| delta.name()).collect();
+        assert_eq!(names, ["db", "cache", "removed", "added"]);
+
+        let db = diff.get("db").unwrap();
+        assert_eq!(db.before(), Some(5.0));
+        assert_eq!(db.after(), Some(4.0));
+        assert_eq!(db.delta(), Some(-1.0));
+
+        assert_eq!(diff.get("cache").unwrap().delta(), None);
+        assert_eq!(diff.get("removed").unwrap().after(), None);
+        assert_eq!(diff.get("added").unwrap().before(), None);
+        assert_eq!(diff.regressions(0.0).count(), 0);
+
+        let diff = after.diff(&before);
+        let regressions: Vec<_> = diff.regressions(0.5).map(|delta| delta.name()).collect();
+        assert_eq!(regressions, ["db"]);
+        assert!(before.diff(&before).regressions(0.0).next().is_none());
+    }
+}
@@ -0,0 +1,208 @@
+//! Errors of the Server-Timing middleware.
+
+#[cfg(feature = "feat-layer")]
+use std::sync::Arc;
+use std::{borrow::Cow, fmt};
+
+use http::header::{InvalidHeaderValue, MaxSizeReached};
+
+use crate::{parse::ParseError, TimingMetric, TimingReport};
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// Errors preventing the `Server-Timing` header from being added to the
+/// response, or degrading it.
+///
+/// They never fail the response: the header is just skipped, or added
+/// without the faulty parts. Use
+/// [`ServerTimingLayer::with_on_error`](crate::ServerTimingLayer::with_on_error)
+/// to observe them.
+pub enum ServerTimingError {
+    /// The header map of the response is full.
+    MaxSizeReached(MaxSizeReached),
+
+    /// The formatted header value is invalid, e.g. the description contains
+    /// control characters.
+    InvalidHeaderValue(InvalidHeaderValue),
+
+    /// A `Server-Timing` header set by an inner service is malformed, see
+    /// [`ServerTimingLayer::with_upstream_parsing`](crate::ServerTimingLayer::with_upstream_parsing).
+    ///
+    /// The header is still added, without the malformed entries.
+    MalformedUpstream(ParseError),
+
+    /// The header value built by the middleware doesn't comply with the
+    /// spec, e.g. because of a metric name with spaces, see
+    /// [`ServerTimingLayer::with_strict`](crate::ServerTimingLayer::with_strict).
+    ///
+    /// The header is skipped.
+    NonCompliant(ParseError),
+
+    /// The given number of custom metrics had a negative duration or start
+    /// offset, e.g. because of clock anomalies, which were clamped to 0.
+    ///
+    /// The header is still added.
+    ClampedDurations(usize),
+
+    /// Adding the header would have made the headers of the response reach
+    /// the given size, beyond the max one, see
+    /// [`ServerTimingLayer::with_max_header_size`](crate::ServerTimingLayer::with_max_header_size).
+    ///
+    /// Only the entry of the service is added, or nothing if it doesn't fit
+    /// either, see [`ServerTimingError::HeaderDropped`].
+    HeaderTooLarge(usize),
+
+    /// Even the entry of the service alone would have made the headers of the
+    /// response reach the given size, beyond the max one, see
+    /// [`ServerTimingLayer::with_max_header_size`](crate::ServerTimingLayer::with_max_header_size).
+    ///
+    /// The header is skipped.
+    HeaderDropped(usize),
+
+    /// The inner service failed, leaving no response to add the header to.
+    ///
+    /// The report carries the entry of the service with the time until the
+    /// failure, the custom metrics recorded so far, and the metadata of the
+    /// request, as passed to
+    /// [`ServerTimingLayer::with_on_timing`](crate::ServerTimingLayer::with_on_timing).
+    ServiceFailed(Box<TimingReport>),
+}
+
+impl fmt::Display for ServerTimingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxSizeReached(_) => f.write_str("too many headers in the response"),
+            Self::InvalidHeaderValue(_) => f.write_str("invalid `server-timing` header value"),
+            Self::MalformedUpstream(_) => f.write_str("malformed upstream `server-timing` header"),
+            Self::NonCompliant(_) => f.write_str("non-compliant `server-timing` header value"),
+            Self::ClampedDurations(n) => write!(f, "{n} negative metric durations clamped to 0"),
+            Self::HeaderTooLarge(n) => write!(f, "response headers would reach {n} bytes"),
+            Self::HeaderDropped(n) => {
+                write!(f, "response headers would reach {n} bytes, header skipped")
+            }
+            Self::ServiceFailed(report) => {
+                match report.metrics().first().and_then(TimingMetric::millis) {
+                    Some(millis) => write!(f, "inner service failed after {millis:.1}ms"),
+                    None => f.write_str("inner service failed"),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServerTimingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::MaxSizeReached(e) => Some(e),
+            Self::InvalidHeaderValue(e) => Some(e),
+            Self::MalformedUpstream(e) | Self::NonCompliant(e) => Some(e),
+            Self::ClampedDurations(_)
+            | Self::HeaderTooLarge(_)
+            | Self::HeaderDropped(_)
+            | Self::ServiceFailed(_) => None,
+        }
+    }
+}
+
+impl From<MaxSizeReached> for ServerTimingError {
+    fn from(e: MaxSizeReached) -> Self {
+        Self::MaxSizeReached(e)
+    }
+}
+
+impl From<ParseError> for ServerTimingError {
+    fn from(e: ParseError) -> Self {
+        Self::MalformedUpstream(e)
+    }
+}
+
+impl From<InvalidHeaderValue> for ServerTimingError {
+    fn from(e: InvalidHeaderValue) -> Self {
+        Self::InvalidHeaderValue(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The service name given to
+/// [`ServerTimingLayer::try_new`](crate::ServerTimingLayer::try_new) is not a
+/// valid `Server-Timing` token, i.e. it's empty or contains characters other
+/// than alphanumerics and ``!#$%&'*+-.^_`|~``.
+pub struct InvalidName(pub(crate) Cow<'static, str>);
+
+impl InvalidName {
+    #[inline]
+    /// Returns the invalid name.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InvalidName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid `server-timing` name: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidName {}
+
+#[cfg(feature = "feat-layer")]
+#[derive(Clone)]
+/// Callback invoked with errors which would be otherwise swallowed.
+pub(crate) struct OnError(Arc<dyn Fn(&ServerTimingError) + Send + Sync>);
+
+#[cfg(feature = "feat-layer")]
+impl OnError {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&ServerTimingError) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    #[inline]
+    pub(crate) fn call(&self, e: &ServerTimingError) {
+        (self.0)(e);
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+impl fmt::Debug for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnError(..)")
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+#[cfg(test)]
+mod tests {
+    use http::header::HeaderValue;
+
+    use super::{InvalidName, ServerTimingError};
+    use crate::{TimingMetric, TimingReport};
+
+    #[test]
+    fn display() {
+        let e: ServerTimingError = HeaderValue::from_bytes(b"\n").unwrap_err().into();
+        assert_eq!(e.to_string(), "invalid `server-timing` header value");
+
+        let e = ServerTimingError::ClampedDurations(2);
+        assert_eq!(e.to_string(), "2 negative metric durations clamped to 0");
+
+        let e = ServerTimingError::HeaderTooLarge(8200);
+        assert_eq!(e.to_string(), "response headers would reach 8200 bytes");
+
+        let e = ServerTimingError::HeaderDropped(8100);
+        assert_eq!(
+            e.to_string(),
+            "response headers would reach 8100 bytes, header skipped"
+        );
+
+        let report = TimingReport::new().with(TimingMetric::new("app").with_millis(12.34));
+        let e = ServerTimingError::ServiceFailed(Box::new(report));
+        assert_eq!(e.to_string(), "inner service failed after 12.3ms");
+
+        let e = InvalidName("my app".into());
+        assert_eq!(e.to_string(), "invalid `server-timing` name: \"my app\"");
+    }
+}
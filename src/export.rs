@@ -0,0 +1,283 @@
+//! Exporting timing reports in batches.
+//!
+//! Implement [`Exporter`] for the destination, and [`BatchConfig::spawn`] a
+//! background worker taking care of buffering:
+//!
+//! ```rust,ignore
+//! let exporter = BatchConfig::new()
+//!     .with_max_batch(512)
+//!     .with_flush_interval(Duration::from_secs(5))
+//!     .spawn(MyExporter::new())?;
+//!
+//! let layer = ServerTimingLayer::new("app").with_exporter(exporter);
+//! ```
+//!
+//! The worker runs on a dedicated thread, so exporters may block. It flushes
+//! the pending reports and stops once all the [`BatchExporter`] clones are
+//! dropped.
+
+#[cfg(feature = "feat-clickhouse")]
+pub mod clickhouse;
+pub mod file;
+#[cfg(feature = "feat-kafka")]
+pub mod kafka;
+pub mod memory;
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::TimingReport;
+
+/// A destination of timing reports, e.g. a database or a message queue.
+pub trait Exporter: Send + 'static {
+    /// Exports a batch of reports.
+    ///
+    /// Errors should be handled (e.g. logged) by the exporter itself, the
+    /// batch is never retried.
+    fn export(&self, batch: &[TimingReport]);
+}
+
+impl<F> Exporter for F
+where
+    F: Fn(&[TimingReport]) + Send + 'static,
+{
+    fn export(&self, batch: &[TimingReport]) {
+        self(batch);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What happens to reports when the queue is full.
+pub enum DropPolicy {
+    /// Drops the new report, never blocking the response.
+    DropNewest,
+
+    /// Blocks the response until there's room in the queue.
+    Block,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Options of the batching worker.
+pub struct BatchConfig {
+    /// The max number of reports waiting in the queue.
+    capacity: usize,
+
+    /// The max number of reports per batch.
+    max_batch: usize,
+
+    /// How often pending reports are flushed.
+    flush_interval: Duration,
+
+    /// What happens to reports when the queue is full.
+    drop_policy: DropPolicy,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchConfig {
+    #[inline]
+    /// Creates a new [`BatchConfig`]: a queue of 4096 reports, batches of
+    /// 256 reports flushed every second, dropping new reports when full.
+    pub const fn new() -> Self {
+        Self {
+            capacity: 4096,
+            max_batch: 256,
+            flush_interval: Duration::from_secs(1),
+            drop_policy: DropPolicy::DropNewest,
+        }
+    }
+
+    #[inline]
+    /// Sets the max number of reports waiting in the queue.
+    pub const fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    #[inline]
+    /// Sets the max number of reports per batch, at least 1.
+    pub const fn with_max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = if max_batch > 0 { max_batch } else { 1 };
+        self
+    }
+
+    #[inline]
+    /// Sets how often pending reports are flushed.
+    pub const fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    #[inline]
+    /// Sets what happens to reports when the queue is full.
+    pub const fn with_drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Spawns the worker thread feeding the given exporter.
+    ///
+    /// # Errors
+    ///
+    /// When the thread cannot be spawned.
+    pub fn spawn<E: Exporter>(self, exporter: E) -> io::Result<BatchExporter> {
+        let (sender, receiver) = mpsc::sync_channel::<TimingReport>(self.capacity);
+
+        thread::Builder::new()
+            .name("server-timing-export".to_owned())
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(self.max_batch);
+                let mut deadline = Instant::now() + self.flush_interval;
+
+                loop {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    let disconnected = match receiver.recv_timeout(timeout) {
+                        Ok(report) => {
+                            batch.push(report);
+                            if batch.len() < self.max_batch {
+                                continue;
+                            }
+                            false
+                        }
+                        Err(RecvTimeoutError::Timeout) => {
+                            deadline = Instant::now() + self.flush_interval;
+                            false
+                        }
+                        Err(RecvTimeoutError::Disconnected) => true,
+                    };
+
+                    if !batch.is_empty() {
+                        exporter.export(&batch);
+                        batch.clear();
+                    }
+
+                    if disconnected {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(BatchExporter {
+            sender,
+            drop_policy: self.drop_policy,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A handle to the batching worker, see [`BatchConfig::spawn`].
+pub struct BatchExporter {
+    /// The sending half of the queue.
+    sender: SyncSender<TimingReport>,
+
+    /// What happens to reports when the queue is full.
+    drop_policy: DropPolicy,
+
+    /// The number of dropped reports, shared by all the clones.
+    dropped: Arc<AtomicU64>,
+}
+
+impl BatchExporter {
+    /// Queues a report.
+    pub fn push(&self, report: TimingReport) {
+        let sent = match self.drop_policy {
+            DropPolicy::DropNewest => match self.sender.try_send(report) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+            },
+            DropPolicy::Block => self.sender.send(report).is_ok(),
+        };
+
+        if !sent {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    /// Returns the number of reports dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::BatchConfig;
+    use crate::{TimingMetric, TimingReport};
+
+    fn report(name: &'static str) -> TimingReport {
+        TimingReport::new().with(TimingMetric::new(name))
+    }
+
+    #[test]
+    fn batch() {
+        let (sender, receiver) = mpsc::channel();
+        let exporter = BatchConfig::new()
+            .with_max_batch(2)
+            .with_flush_interval(Duration::from_secs(60))
+            .spawn(move |batch: &[TimingReport]| sender.send(batch.len()).unwrap())
+            .unwrap();
+
+        for name in ["a", "b", "c"] {
+            exporter.push(report(name));
+        }
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(2));
+
+        // The pending report is flushed when the exporter is dropped.
+        drop(exporter);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(1));
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap_err();
+    }
+
+    #[test]
+    fn flush_interval() {
+        let (sender, receiver) = mpsc::channel();
+        let exporter = BatchConfig::new()
+            .with_flush_interval(Duration::from_millis(10))
+            .spawn(move |batch: &[TimingReport]| sender.send(batch.len()).unwrap())
+            .unwrap();
+
+        exporter.push(report("a"));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (started, wait_started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+
+        let exporter = BatchConfig::new()
+            .with_capacity(1)
+            .with_max_batch(1)
+            .spawn(move |_: &[TimingReport]| {
+                let _ = started.send(());
+                let _ = released.recv();
+            })
+            .unwrap();
+
+        // The worker is stuck exporting the first report.
+        exporter.push(report("a"));
+        wait_started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        exporter.push(report("b"));
+        exporter.push(report("c"));
+        assert_eq!(exporter.dropped(), 1);
+
+        drop(release);
+    }
+}
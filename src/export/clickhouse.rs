@@ -0,0 +1,200 @@
+//! Writing timing reports into ClickHouse, through its HTTP interface.
+//!
+//! ```rust,ignore
+//! let exporter = BatchConfig::new().spawn(
+//!     ClickHouseExporter::new("http://localhost:8123", "server_timing")
+//!         .with_credentials("default", ""),
+//! )?;
+//! ```
+//!
+//! Each batch is inserted with a single `INSERT ... FORMAT JSONEachRow`
+//! query, into a table like:
+//!
+//! ```sql
+//! CREATE TABLE server_timing (
+//!     time DateTime64(3) DEFAULT now64(3),
+//!     route String,
+//!     method LowCardinality(String),
+//!     status UInt16,
+//!     duration Float64,
+//!     metrics Nested(name LowCardinality(String), dur Nullable(Float64), desc String)
+//! ) ENGINE = MergeTree ORDER BY time;
+//! ```
+//!
+//! `duration` is the one of the first metric, i.e. the entry of the service,
+//! and the other metrics go into `metrics`.
+
+use std::{fmt::Write, time::Duration};
+
+use super::Exporter;
+use crate::{report::push_json_str, TimingReport};
+
+/// The timeout of insert queries.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+/// An [`Exporter`] inserting reports into a ClickHouse table.
+pub struct ClickHouseExporter {
+    /// The URL of the insert query.
+    url: String,
+
+    /// Optional user and password.
+    credentials: Option<(String, String)>,
+}
+
+impl ClickHouseExporter {
+    /// Creates a new [`ClickHouseExporter`] inserting into the given table,
+    /// e.g. `http://localhost:8123` and `db.server_timing`.
+    pub fn new(endpoint: &str, table: &str) -> Self {
+        let mut url = endpoint.trim_end_matches('/').to_owned();
+        url.push_str("/?query=");
+        for b in format!("INSERT INTO {table} FORMAT JSONEachRow").bytes() {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                url.push(b as char);
+            } else {
+                let _ = write!(url, "%{b:02X}");
+            }
+        }
+
+        Self {
+            url,
+            credentials: None,
+        }
+    }
+
+    #[inline]
+    /// Sets the user and password.
+    pub fn with_credentials(
+        mut self,
+        user: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+}
+
+impl Exporter for ClickHouseExporter {
+    fn export(&self, batch: &[TimingReport]) {
+        let mut body = String::new();
+        for report in batch {
+            encode_row(&mut body, report);
+        }
+
+        let mut request = minreq::post(&self.url)
+            .with_body(body)
+            .with_timeout(TIMEOUT.as_secs());
+        if let Some((user, password)) = &self.credentials {
+            request = request
+                .with_header("X-ClickHouse-User", user)
+                .with_header("X-ClickHouse-Key", password);
+        }
+
+        match request.send() {
+            Ok(response) if (200..300).contains(&response.status_code) => {}
+            Ok(_response) => {
+                #[cfg(feature = "feat-tracing")]
+                tracing::error!(
+                    "Failed to insert timing reports: {} {}",
+                    _response.status_code,
+                    _response.as_str().unwrap_or_default()
+                );
+            }
+            Err(_e) => {
+                #[cfg(feature = "feat-tracing")]
+                tracing::error!("Failed to insert timing reports: {_e:?}");
+            }
+        }
+    }
+}
+
+/// Appends the report as a `JSONEachRow` row.
+fn encode_row(buf: &mut String, report: &TimingReport) {
+    let (entry, metrics) = match report.metrics() {
+        [entry, metrics @ ..] => (Some(entry), metrics),
+        [] => (None, &[][..]),
+    };
+
+    buf.push_str("{\"route\":");
+    push_json_str(buf, report.route().unwrap_or_default());
+    buf.push_str(",\"method\":");
+    push_json_str(buf, report.method().map_or("", |method| method.as_str()));
+
+    // Writing to a `String` never fails.
+    let _ = write!(
+        buf,
+        ",\"status\":{},\"duration\":{}",
+        report.status().map_or(0, |status| status.as_u16()),
+        entry
+            .and_then(|entry| entry.millis())
+            .filter(|dur| dur.is_finite())
+            .unwrap_or_default()
+    );
+
+    buf.push_str(",\"metrics.name\":[");
+    for (i, metric) in metrics.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        push_json_str(buf, &metric.full_name());
+    }
+
+    buf.push_str("],\"metrics.dur\":[");
+    for (i, metric) in metrics.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        match metric.millis().filter(|dur| dur.is_finite()) {
+            Some(dur) => {
+                let _ = write!(buf, "{dur}");
+            }
+            None => buf.push_str("null"),
+        }
+    }
+
+    buf.push_str("],\"metrics.desc\":[");
+    for (i, metric) in metrics.iter().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        push_json_str(buf, metric.description().unwrap_or_default());
+    }
+
+    buf.push_str("]}\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+
+    use super::{encode_row, ClickHouseExporter};
+    use crate::{TimingMetric, TimingReport};
+
+    #[test]
+    fn url() {
+        let exporter = ClickHouseExporter::new("http://localhost:8123/", "db.timing");
+        assert_eq!(
+            exporter.url,
+            "http://localhost:8123/?query=INSERT%20INTO%20db.timing%20FORMAT%20JSONEachRow"
+        );
+    }
+
+    #[test]
+    fn row() {
+        let report = TimingReport::new()
+            .with(TimingMetric::new("app").with_millis(12.5))
+            .with(TimingMetric::new("db").with_millis(3.0))
+            .with(TimingMetric::new("shed").with_description("yes"))
+            .with_method(Method::POST)
+            .with_route("/users")
+            .with_status(StatusCode::CREATED);
+
+        let mut buf = String::new();
+        encode_row(&mut buf, &report);
+        assert_eq!(
+            buf,
+            "{\"route\":\"/users\",\"method\":\"POST\",\"status\":201,\"duration\":12.5,\"metrics.\
+             name\":[\"db\",\"shed\"],\"metrics.dur\":[3,null],\"metrics.desc\":[\"\",\"yes\"]}\n"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Keeping the last timing reports in a local file, for debugging.
+//!
+//! ```rust,ignore
+//! let exporter = BatchConfig::new().spawn(FileRecorder::open("timings.jsonl", 10_000)?)?;
+//! ```
+//!
+//! Each report is appended as a JSON line, see [`TimingReport::to_json`],
+//! with the time it was recorded in milliseconds since the Unix epoch, e.g.
+//! `{"time":1712345678901,"method":"GET",...}`. Recent requests can then be
+//! queried without any external infra:
+//!
+//! ```sh
+//! tail -n 100 timings.jsonl | jq 'select(.metrics[0].dur > 500)'
+//! ```
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use super::Exporter;
+use crate::TimingReport;
+
+#[derive(Debug)]
+/// An [`Exporter`] keeping the last reports in a JSON lines file.
+///
+/// The file is compacted to the last `capacity` reports once it holds twice
+/// as many, so it never grows unbounded.
+pub struct FileRecorder {
+    /// The path of the file.
+    path: PathBuf,
+
+    /// The number of reports kept on compaction.
+    capacity: usize,
+
+    /// The opened file, and the number of lines in it.
+    state: Mutex<(File, usize)>,
+}
+
+impl FileRecorder {
+    /// Opens the given file, creating it if needed, keeping the last
+    /// `capacity` reports.
+    ///
+    /// # Errors
+    ///
+    /// When the file cannot be opened or read.
+    pub fn open(path: impl Into<PathBuf>, capacity: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let lines = BufReader::new(File::open(&path)?).lines().count();
+
+        Ok(Self {
+            path,
+            capacity: capacity.max(1),
+            state: Mutex::new((file, lines)),
+        })
+    }
+
+    /// Appends the reports, compacting the file if needed.
+    fn append(&self, batch: &[TimingReport]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (file, lines) = &mut *state;
+
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        let mut buf = String::new();
+        for report in batch {
+            let json = report.to_json();
+            buf.push_str("{\"time\":");
+            buf.push_str(&time.to_string());
+            buf.push(',');
+            buf.push_str(&json[1..]);
+            buf.push('\n');
+        }
+
+        file.write_all(buf.as_bytes())?;
+        *lines += batch.len();
+
+        if *lines >= self.capacity * 2 {
+            *file = self.compact()?;
+            *lines = self.capacity;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the file with the last `capacity` lines only.
+    fn compact(&self) -> io::Result<File> {
+        let lines = BufReader::new(File::open(&self.path)?)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        let mut buf = String::new();
+        for line in &lines[lines.len().saturating_sub(self.capacity)..] {
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        fs::write(&tmp, buf)?;
+        fs::rename(&tmp, &self.path)?;
+
+        OpenOptions::new().append(true).open(&self.path)
+    }
+}
+
+impl Exporter for FileRecorder {
+    fn export(&self, batch: &[TimingReport]) {
+        if let Err(_e) = self.append(batch) {
+            #[cfg(feature = "feat-tracing")]
+            tracing::error!("Failed to record timing reports: {_e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::FileRecorder;
+    use crate::{export::Exporter, TimingMetric, TimingReport};
+
+    #[test]
+    fn ring_buffer() {
+        let path =
+            std::env::temp_dir().join(format!("miku-server-timing-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let recorder = FileRecorder::open(&path, 2).unwrap();
+        for name in ["a", "b", "c"] {
+            recorder.export(&[TimingReport::new().with(TimingMetric::new(name))]);
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("{\"time\":"));
+        assert!(lines[0].ends_with(",\"metrics\":[{\"name\":\"a\"}]}"));
+
+        // Compacted to the last 2 reports.
+        recorder.export(&[TimingReport::new().with(TimingMetric::new("d"))]);
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("\"d\""));
+
+        // Reopening counts the existing lines.
+        drop(recorder);
+        let recorder = FileRecorder::open(&path, 2).unwrap();
+        recorder.export(&[TimingReport::new().with(TimingMetric::new("e"))]);
+        recorder.export(&[TimingReport::new().with(TimingMetric::new("f"))]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        let _ = fs::remove_file(&path);
+    }
+}
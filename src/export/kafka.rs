@@ -0,0 +1,92 @@
+//! Publishing timing reports to Kafka.
+//!
+//! ```rust,ignore
+//! let exporter = BatchConfig::new().spawn(KafkaExporter::new("localhost:9092", "timings")?)?;
+//! ```
+//!
+//! Each report is published as a JSON message, see [`TimingReport::to_json`].
+
+use std::time::Duration;
+
+use rdkafka::{
+    config::ClientConfig,
+    error::KafkaResult,
+    producer::{BaseProducer, BaseRecord, Producer},
+};
+
+use super::Exporter;
+use crate::TimingReport;
+
+/// How long pending messages are waited for when the exporter is dropped.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An [`Exporter`] publishing each report as a JSON message to a Kafka topic.
+pub struct KafkaExporter {
+    /// The underlying producer.
+    producer: BaseProducer,
+
+    /// The topic to publish to.
+    topic: String,
+}
+
+impl std::fmt::Debug for KafkaExporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaExporter")
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl KafkaExporter {
+    /// Creates a new [`KafkaExporter`] connecting to the given brokers.
+    ///
+    /// # Errors
+    ///
+    /// When the producer cannot be created.
+    pub fn new(brokers: &str, topic: impl Into<String>) -> KafkaResult<Self> {
+        Ok(Self::from_producer(
+            ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .create()?,
+            topic,
+        ))
+    }
+
+    #[inline]
+    /// Creates a new [`KafkaExporter`] with the given producer, e.g. to tune
+    /// the client config.
+    pub fn from_producer(producer: BaseProducer, topic: impl Into<String>) -> Self {
+        Self {
+            producer,
+            topic: topic.into(),
+        }
+    }
+}
+
+impl Exporter for KafkaExporter {
+    fn export(&self, batch: &[TimingReport]) {
+        for report in batch {
+            let payload = report.to_json();
+
+            if let Err((_e, _)) = self
+                .producer
+                .send(BaseRecord::<(), str>::to(&self.topic).payload(&payload))
+            {
+                #[cfg(feature = "feat-tracing")]
+                tracing::error!("Failed to publish timing report: {_e:?}");
+            }
+        }
+
+        // Serves delivery callbacks.
+        self.producer.poll(Duration::ZERO);
+    }
+}
+
+impl Drop for KafkaExporter {
+    fn drop(&mut self) {
+        if let Err(_e) = self.producer.flush(FLUSH_TIMEOUT) {
+            #[cfg(feature = "feat-tracing")]
+            tracing::error!("Failed to flush timing reports: {_e:?}");
+        }
+    }
+}
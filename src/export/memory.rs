@@ -0,0 +1,151 @@
+//! Keeping the last timing reports in memory.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use super::Exporter;
+use crate::TimingReport;
+
+#[derive(Debug, Clone)]
+/// An [`Exporter`] keeping the last reports in memory, shared by all the
+/// clones, e.g. for a debug endpoint.
+///
+/// ```rust
+/// # use miku_server_timing::{export::{BatchConfig, memory::MemoryRecorder}, ServerTimingLayer};
+/// let recorder = MemoryRecorder::new(1000);
+/// let layer = ServerTimingLayer::new("app")
+///     .with_exporter(BatchConfig::new().spawn(recorder.clone()).unwrap());
+/// ```
+pub struct MemoryRecorder {
+    /// The number of reports kept.
+    capacity: usize,
+
+    /// The recorded reports.
+    reports: Arc<Mutex<Reports>>,
+}
+
+#[derive(Debug, Default)]
+struct Reports {
+    /// The id of the next report.
+    next_id: u64,
+
+    /// The last reports with their ids, the newest last.
+    reports: VecDeque<(u64, TimingReport)>,
+}
+
+impl MemoryRecorder {
+    #[inline]
+    /// Creates a new [`MemoryRecorder`] keeping the last `capacity` reports.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            reports: Arc::new(Mutex::new(Reports {
+                next_id: 0,
+                reports: VecDeque::with_capacity(capacity),
+            })),
+        }
+    }
+
+    /// Records a report, evicting the oldest one if full, and returns its id.
+    pub fn record(&self, report: TimingReport) -> u64 {
+        let mut reports = self.reports.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let id = reports.next_id;
+        reports.next_id += 1;
+
+        if self.capacity > 0 {
+            if reports.reports.len() == self.capacity {
+                reports.reports.pop_front();
+            }
+            reports.reports.push_back((id, report));
+        }
+
+        id
+    }
+
+    /// Returns the report with the given id, if still kept.
+    pub fn get(&self, id: u64) -> Option<TimingReport> {
+        let reports = self.reports.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Ids are increasing, without gaps.
+        let first = reports.reports.front()?.0;
+        reports
+            .reports
+            .get(usize::try_from(id.checked_sub(first)?).ok()?)
+            .map(|(_, report)| report.clone())
+    }
+
+    /// Returns the recorded reports with their ids, the newest first.
+    pub fn recent(&self) -> Vec<(u64, TimingReport)> {
+        self.reports
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .reports
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the `n` slowest recorded reports with their ids, the slowest
+    /// first.
+    ///
+    /// Reports are compared by the duration of their first metric, i.e. the
+    /// entry of the service.
+    pub fn slowest(&self, n: usize) -> Vec<(u64, TimingReport)> {
+        let mut reports = self.recent();
+        reports.sort_by(|(_, a), (_, b)| total(b).total_cmp(&total(a)));
+        reports.truncate(n);
+        reports
+    }
+}
+
+impl Exporter for MemoryRecorder {
+    fn export(&self, batch: &[TimingReport]) {
+        for report in batch {
+            self.record(report.clone());
+        }
+    }
+}
+
+#[inline]
+/// The duration of the first metric, 0 if none.
+fn total(report: &TimingReport) -> f64 {
+    report
+        .metrics()
+        .first()
+        .and_then(|metric| metric.millis())
+        .filter(|dur| !dur.is_nan())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryRecorder;
+    use crate::{TimingMetric, TimingReport};
+
+    fn report(millis: f64) -> TimingReport {
+        TimingReport::new().with(TimingMetric::new("app").with_millis(millis))
+    }
+
+    #[test]
+    fn slowest() {
+        let recorder = MemoryRecorder::new(3);
+        for millis in [5.0, 50.0, 1.0, 20.0] {
+            recorder.record(report(millis));
+        }
+
+        // The first one is evicted.
+        assert_eq!(
+            recorder.recent(),
+            [(3, report(20.0)), (2, report(1.0)), (1, report(50.0))]
+        );
+        assert_eq!(recorder.slowest(2), [(1, report(50.0)), (3, report(20.0))]);
+
+        assert_eq!(recorder.get(0), None);
+        assert_eq!(recorder.get(2), Some(report(1.0)));
+        assert_eq!(recorder.get(4), None);
+    }
+}
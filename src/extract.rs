@@ -0,0 +1,137 @@
+//! Integration with axum extractors.
+//!
+//! [`ServerTimingHandle`] can be accepted by handlers as a typed argument:
+//!
+//! ```rust,ignore
+//! async fn handler(timing: ServerTimingHandle) -> &'static str {
+//!     let start = Instant::now();
+//!     // query the database...
+//!     timing.record(TimingMetric::new("db").with_duration(start.elapsed()));
+//!     "Hello, World!"
+//! }
+//! ```
+//!
+//! Extracting it fails with [`MissingServerTimingHandle`] when
+//! [`ServerTimingLayer`](crate::ServerTimingLayer) is not installed, use
+//! `Option<ServerTimingHandle>` to make it optional.
+
+use std::{convert::Infallible, fmt};
+
+use axum_core::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    response::{IntoResponse, Response},
+};
+use http::{request::Parts, StatusCode};
+
+use crate::ServerTimingHandle;
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Rejection used for [`ServerTimingHandle`] when
+/// [`ServerTimingLayer`](crate::ServerTimingLayer) is not installed.
+///
+/// Responds with `500 Internal Server Error`, since it's a bug of the server.
+pub struct MissingServerTimingHandle;
+
+impl fmt::Display for MissingServerTimingHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("missing `ServerTimingHandle`, is `ServerTimingLayer` installed?")
+    }
+}
+
+impl std::error::Error for MissingServerTimingHandle {}
+
+impl IntoResponse for MissingServerTimingHandle {
+    fn into_response(self) -> Response {
+        #[cfg(feature = "feat-tracing")]
+        tracing::error!("{self}");
+
+        (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+    }
+}
+
+impl<S> FromRequestParts<S> for ServerTimingHandle
+where
+    S: Send + Sync,
+{
+    type Rejection = MissingServerTimingHandle;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Self>()
+            .cloned()
+            .ok_or(MissingServerTimingHandle)
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for ServerTimingHandle
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<Self>().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get, Router};
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{ServerTimingHandle, ServerTimingLayer, TimingMetric};
+
+    #[tokio::test]
+    async fn extract_handle() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|timing: ServerTimingHandle| async move {
+                    timing.record(TimingMetric::new("db").with_millis(12.0));
+                    ""
+                }),
+            )
+            .layer(ServerTimingLayer::new("svc1"));
+
+        let response = app.oneshot(Request::new(Body::empty())).await.unwrap();
+
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            hdr.starts_with("svc1;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(
+            hdr.ends_with(", db;dur=12.0"),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_missing_handle() {
+        let app = Router::new().route("/", get(|_timing: ServerTimingHandle| async { "" }));
+
+        let response = app.oneshot(Request::new(Body::empty())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let app = Router::new().route(
+            "/",
+            get(|timing: Option<ServerTimingHandle>| async move {
+                assert!(timing.is_none());
+                ""
+            }),
+        );
+
+        let response = app.oneshot(Request::new(Body::empty())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,256 @@
+//! Per-request enablement from feature flag providers.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::Request;
+
+use crate::report::inspect;
+
+/// A feature flag deciding whether the middleware is enabled for a request,
+/// so that the rollout of the header can be controlled at runtime from
+/// providers like Unleash or Launch Darkly, without redeploys.
+///
+/// Disabled requests skip the middleware altogether: no header, no
+/// [`on_timing`] callback, and no [`ServerTimingHandle`] in the request
+/// extensions.
+///
+/// The flag is evaluated before the request is passed on, without its body,
+/// so it must not block: providers evaluating flags remotely should rely on
+/// the locally cached state their SDKs keep in sync in the background, or be
+/// awaited with an [`AsyncFeatureFlag`] instead.
+///
+/// Implemented for closures:
+///
+/// ```rust
+/// # use std::sync::atomic::{AtomicBool, Ordering};
+/// # use miku_server_timing::ServerTimingLayer;
+/// static ROLLOUT: AtomicBool = AtomicBool::new(false);
+///
+/// let layer = ServerTimingLayer::new("app").with_feature_flag(|req: &http::Request<()>| {
+///     ROLLOUT.load(Ordering::Relaxed) || req.headers().contains_key("x-debug")
+/// });
+/// ```
+///
+/// [`on_timing`]: crate::ServerTimingLayer::with_on_timing
+/// [`ServerTimingHandle`]: crate::ServerTimingHandle
+pub trait FeatureFlag: Send + Sync + 'static {
+    /// Returns whether the middleware is enabled for the given request.
+    fn enabled(&self, req: &Request<()>) -> bool;
+}
+
+impl<F> FeatureFlag for F
+where
+    F: Fn(&Request<()>) -> bool + Send + Sync + 'static,
+{
+    #[inline]
+    fn enabled(&self, req: &Request<()>) -> bool {
+        self(req)
+    }
+}
+
+/// A feature flag resolved asynchronously, e.g. by a remote provider, see
+/// [`AsyncFlagLayer`].
+///
+/// Implemented for closures returning futures:
+///
+/// ```rust
+/// # use miku_server_timing::AsyncFlagLayer;
+/// async fn lookup(user: Option<String>) -> bool {
+///     user.is_some()
+/// }
+///
+/// let layer = AsyncFlagLayer::new(|req: &http::Request<()>| {
+///     let user = req
+///         .headers()
+///         .get("x-user")
+///         .and_then(|user| user.to_str().ok())
+///         .map(str::to_owned);
+///     lookup(user)
+/// });
+/// ```
+pub trait AsyncFeatureFlag: Send + Sync + 'static {
+    /// Returns whether the middleware is enabled for the given request.
+    fn enabled(&self, req: &Request<()>) -> impl Future<Output = bool> + Send + 'static;
+}
+
+impl<F, Fut> AsyncFeatureFlag for F
+where
+    F: Fn(&Request<()>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    #[inline]
+    fn enabled(&self, req: &Request<()>) -> impl Future<Output = bool> + Send + 'static {
+        self(req)
+    }
+}
+
+/// The future of an [`AsyncFeatureFlag`], boxed to be shared.
+type Pending = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+/// The signature of a shared [`AsyncFeatureFlag`].
+type Resolver = dyn Fn(&Request<()>) -> Pending + Send + Sync;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether the middleware is enabled for the request, inserted into its
+/// extensions by [`AsyncFlagService`].
+pub(crate) struct FlagEnabled(pub(crate) bool);
+
+#[derive(Clone)]
+/// A layer enabling the middleware per request with an
+/// [`AsyncFeatureFlag`], to be installed right above the
+/// [`ServerTimingLayer`]:
+///
+/// ```rust,ignore
+/// let service = ServiceBuilder::new()
+///     .layer(AsyncFlagLayer::new(flag))
+///     .layer(ServerTimingLayer::new("app"))
+///     .service(inner);
+/// ```
+///
+/// The flag is awaited before the request is passed on, so that its latency
+/// isn't part of the timings. Disabled requests skip the middleware like with
+/// [`ServerTimingLayer::with_feature_flag`].
+///
+/// [`ServerTimingLayer`]: crate::ServerTimingLayer
+/// [`ServerTimingLayer::with_feature_flag`]: crate::ServerTimingLayer::with_feature_flag
+pub struct AsyncFlagLayer {
+    /// The flag.
+    flag: Arc<Resolver>,
+}
+
+impl AsyncFlagLayer {
+    #[inline]
+    /// Creates a new `AsyncFlagLayer` with the given flag.
+    pub fn new(flag: impl AsyncFeatureFlag) -> Self {
+        Self {
+            flag: Arc::new(move |req| Box::pin(flag.enabled(req))),
+        }
+    }
+}
+
+impl fmt::Debug for AsyncFlagLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AsyncFlagLayer(..)")
+    }
+}
+
+impl<S> tower_layer::Layer<S> for AsyncFlagLayer {
+    type Service = AsyncFlagService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AsyncFlagService {
+            service,
+            flag: self.flag.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+/// A service enabling the middleware per request with an
+/// [`AsyncFeatureFlag`], see [`AsyncFlagLayer`].
+pub struct AsyncFlagService<S> {
+    /// The service to wrap.
+    service: S,
+
+    /// The flag.
+    flag: Arc<Resolver>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for AsyncFlagService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncFlagService")
+            .field("service", &self.service)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The future of [`AsyncFlagService`].
+pub type AsyncFlagFuture<R, E> = Pin<Box<dyn Future<Output = Result<R, E>> + Send>>;
+
+impl<S, ReqBody> tower_service::Service<Request<ReqBody>> for AsyncFlagService<S>
+where
+    S: tower_service::Service<Request<ReqBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = AsyncFlagFuture<S::Response, S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // The service polled ready is the one to call.
+        let clone = self.service.clone();
+        let mut service = std::mem::replace(&mut self.service, clone);
+
+        let (mut req, enabled) = inspect(req, |view| (self.flag)(view));
+
+        Box::pin(async move {
+            let enabled = enabled.await;
+            req.extensions_mut().insert(FlagEnabled(enabled));
+            service.call(req).await
+        })
+    }
+}
+
+#[derive(Clone)]
+/// A shared [`FeatureFlag`].
+pub(crate) struct Flag(Arc<dyn FeatureFlag>);
+
+impl Flag {
+    #[inline]
+    pub(crate) fn new(flag: impl FeatureFlag) -> Self {
+        Self(Arc::new(flag))
+    }
+
+    #[inline]
+    /// Evaluates the flag for the given request.
+    pub(crate) fn enabled<B>(&self, req: Request<B>) -> (Request<B>, bool) {
+        inspect(req, |view| self.0.enabled(view))
+    }
+}
+
+impl fmt::Debug for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Flag(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Request;
+
+    use super::{FeatureFlag, Flag};
+
+    struct Header;
+
+    impl FeatureFlag for Header {
+        fn enabled(&self, req: &Request<()>) -> bool {
+            req.headers().contains_key("x-debug")
+        }
+    }
+
+    #[test]
+    fn enabled() {
+        let flag = Flag::new(Header);
+        let (req, enabled) = flag.enabled(Request::new("body"));
+        assert!(!enabled);
+        assert_eq!(*req.body(), "body");
+
+        let req = Request::builder().header("x-debug", "1").body(()).unwrap();
+        assert!(flag.enabled(req).1);
+
+        let flag = Flag::new(|req: &Request<()>| req.uri().path() == "/beta");
+        let req = Request::builder().uri("/beta").body(()).unwrap();
+        assert!(flag.enabled(req).1);
+    }
+}
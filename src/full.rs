@@ -0,0 +1,62 @@
+//! Responses whose header map is full.
+
+use http::{HeaderMap, HeaderName};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+/// What happens when the header map of the response is full, so that the
+/// `Server-Timing` header can't be added.
+pub enum FullPolicy {
+    #[default]
+    /// Skips the header, reporting
+    /// [`ServerTimingError::MaxSizeReached`](crate::ServerTimingError::MaxSizeReached).
+    Report,
+
+    /// Removes the given lower-priority header, e.g. a debug header, to make
+    /// room for the `Server-Timing` one, reporting like
+    /// [`FullPolicy::Report`] if it's missing or there's still no room.
+    Evict(HeaderName),
+
+    #[cfg(feature = "feat-trailer")]
+    /// Emits the header as a trailer after the response body instead, which
+    /// needs the [`TrailerLayer`](crate::TrailerLayer) to wrap the body,
+    /// reporting like [`FullPolicy::Report`] otherwise.
+    Trailer,
+}
+
+impl FullPolicy {
+    /// Makes room in the given full headers, returning whether a header was
+    /// removed.
+    pub(crate) fn evict(&self, headers: &mut HeaderMap) -> bool {
+        match self {
+            Self::Report => false,
+            #[cfg(feature = "feat-trailer")]
+            Self::Trailer => false,
+            Self::Evict(name) => headers.remove(name).is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::FullPolicy;
+
+    #[test]
+    fn evict() {
+        let mut headers = HeaderMap::new();
+        headers.append("x-debug", HeaderValue::from_static("1"));
+        headers.append("x-debug", HeaderValue::from_static("2"));
+        headers.insert("x-request-id", HeaderValue::from_static("3"));
+
+        assert!(!FullPolicy::Report.evict(&mut headers));
+        assert_eq!(headers.len(), 3);
+
+        let policy = FullPolicy::Evict(HeaderName::from_static("x-debug"));
+        assert!(policy.evict(&mut headers));
+        assert!(!headers.contains_key("x-debug"));
+        assert!(headers.contains_key("x-request-id"));
+        assert!(!policy.evict(&mut headers));
+    }
+}
@@ -0,0 +1,178 @@
+//! Request-scoped timing state.
+
+#[cfg(feature = "feat-alloc")]
+use std::sync::atomic::AtomicU64;
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Instant,
+};
+
+use crate::TimingMetric;
+
+#[derive(Debug, Clone)]
+/// A handle to the timing state of the current request.
+///
+/// [`ServerTimingService`](crate::ServerTimingService) inserts it into the
+/// request extensions, so that services deeper in the stack can feed the
+/// `Server-Timing` entry of the response.
+pub struct ServerTimingHandle {
+    inner: Arc<Inner>,
+
+    /// The parent of the metrics recorded through this handle, if any.
+    namespace: Option<Cow<'static, str>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// When the request was received.
+    start: Instant,
+
+    /// How many times the request has been dispatched to the inner service.
+    attempts: AtomicU32,
+
+    /// The recorded custom metrics.
+    metrics: Mutex<Vec<TimingMetric>>,
+
+    /// The bytes allocated while polling the inner service.
+    #[cfg(feature = "feat-alloc")]
+    allocated: AtomicU64,
+}
+
+impl ServerTimingHandle {
+    #[inline]
+    pub(crate) fn new(start: Instant) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                start,
+                attempts: AtomicU32::new(0),
+                metrics: Mutex::new(Vec::new()),
+                #[cfg(feature = "feat-alloc")]
+                allocated: AtomicU64::new(0),
+            }),
+            namespace: None,
+        }
+    }
+
+    #[inline]
+    /// Returns a handle to the same request, prefixing the metrics recorded
+    /// through it with the given namespace, e.g. `db.query` and `db.tx` for
+    /// `query` and `tx` in `db`.
+    ///
+    /// The metrics become children of the namespace, see
+    /// [`TimingMetric::with_parent`], so they are grouped right after it.
+    /// Namespaces nest, e.g. `db.tx.commit`.
+    pub fn namespace(&self, namespace: impl Into<Cow<'static, str>>) -> Self {
+        let namespace = namespace.into();
+
+        Self {
+            inner: self.inner.clone(),
+            namespace: Some(match &self.namespace {
+                Some(outer) => format!("{outer}.{namespace}").into(),
+                None => namespace,
+            }),
+        }
+    }
+
+    #[inline]
+    /// Returns when the request was received.
+    pub fn start(&self) -> Instant {
+        self.inner.start
+    }
+
+    #[inline]
+    /// Returns how many times the request has been dispatched, as counted by
+    /// [`AttemptLayer`](crate::retry::AttemptLayer).
+    ///
+    /// 0 if no `AttemptLayer` is installed.
+    pub fn attempts(&self) -> u32 {
+        self.inner.attempts.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn attempt(&self) {
+        self.inner.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "feat-alloc")]
+    #[inline]
+    /// Returns the bytes allocated so far while processing the request, see
+    /// [`allocation`](crate::allocation).
+    pub fn allocated(&self) -> u64 {
+        self.inner.allocated.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "feat-alloc")]
+    #[inline]
+    pub(crate) fn allocate(&self, bytes: u64) {
+        self.inner.allocated.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[inline]
+    /// Records a custom metric, serialized after the entry of the service.
+    pub fn record(&self, metric: TimingMetric) {
+        self.push(match &self.namespace {
+            Some(namespace) => metric.within(namespace.clone()),
+            None => metric,
+        });
+    }
+
+    #[inline]
+    /// Records a custom metric as is, regardless of the namespace.
+    fn push(&self, metric: TimingMetric) {
+        self.inner
+            .metrics
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(metric);
+    }
+
+    #[inline]
+    /// Records a custom metric with the given number of decimal places,
+    /// overriding the default of the layer, see
+    /// [`TimingMetric::with_precision`].
+    pub fn record_with_precision(&self, metric: TimingMetric, precision: u8) {
+        self.record(metric.with_precision(precision));
+    }
+
+    #[inline]
+    /// Records a custom metric from the given instant to now, with its start
+    /// offset relative to the start of the request.
+    pub fn record_since(&self, name: impl Into<Cow<'static, str>>, started: Instant) {
+        self.record(
+            TimingMetric::new(name)
+                .with_start(started.saturating_duration_since(self.inner.start))
+                .with_duration(started.elapsed()),
+        );
+    }
+
+    #[inline]
+    /// Records an `early_hints` metric for the time from the start of the
+    /// request to now, to be called once the `103 Early Hints` response is
+    /// sent.
+    ///
+    /// Interim responses don't go through the middleware, so the entry of the
+    /// service still covers the final response.
+    ///
+    /// Nothing is recorded automatically: hyper servers can't send interim
+    /// responses, so the `103` is sent by whatever part of the stack supports
+    /// it, e.g. a CDN or a reverse proxy, which the middleware can't observe.
+    pub fn record_early_hints(&self) {
+        self.push(TimingMetric::new("early_hints").with_duration(self.inner.start.elapsed()));
+    }
+
+    #[inline]
+    /// Takes the recorded custom metrics.
+    pub(crate) fn take_metrics(&self) -> Vec<TimingMetric> {
+        std::mem::take(
+            &mut *self
+                .inner
+                .metrics
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+}
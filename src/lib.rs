@@ -1,256 +1,5285 @@
 //! Miku's Server-Timing middleware for Axum
 
+#![cfg_attr(not(any(feature = "feat-std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "feat-layer")]
+mod aggregate;
+#[cfg(feature = "feat-alloc")]
+pub mod allocation;
+#[cfg(feature = "feat-layer")]
+pub mod buffer;
+#[cfg(feature = "feat-layer")]
+mod cache;
+#[cfg(feature = "feat-layer")]
+mod content_type;
+#[cfg(feature = "feat-tokio")]
+pub mod context;
+#[cfg(feature = "feat-datadog")]
+pub mod datadog;
+#[cfg(feature = "feat-debug")]
+pub mod debug;
+#[cfg(feature = "feat-layer")]
+mod deferred;
+#[cfg(feature = "feat-layer")]
+mod dev;
+#[cfg(feature = "feat-std")]
+mod diff;
+#[cfg(feature = "feat-std")]
+mod error;
+#[cfg(feature = "feat-layer")]
+pub mod export;
+#[cfg(feature = "feat-axum")]
+pub mod extract;
+#[cfg(feature = "feat-layer")]
+mod flag;
+#[cfg(feature = "feat-layer")]
+mod full;
+#[cfg(feature = "feat-layer")]
+mod handle;
+#[cfg(feature = "feat-layer")]
+pub mod limit;
+#[cfg(feature = "feat-tower")]
+pub mod load_shed;
+#[cfg(feature = "feat-layer")]
+mod merge;
+#[cfg(feature = "feat-layer")]
+mod method;
+#[cfg(feature = "feat-layer")]
+mod nested;
+#[cfg(feature = "feat-layer")]
+mod panic;
+#[cfg(feature = "feat-layer")]
+mod param;
+#[cfg(feature = "feat-layer")]
+mod path;
+#[cfg(feature = "feat-layer")]
+pub mod policy;
+#[cfg(feature = "feat-layer")]
+mod preset;
+#[cfg(feature = "feat-layer")]
+mod redact;
+#[cfg(feature = "feat-std")]
+mod report;
+#[cfg(feature = "feat-layer")]
+mod reporting;
+#[cfg(feature = "feat-axum")]
+mod response;
+#[cfg(feature = "feat-layer")]
+pub mod retry;
+#[cfg(feature = "feat-router")]
+mod router;
+#[cfg(feature = "feat-layer")]
+mod sample;
+#[cfg(feature = "feat-layer")]
+mod scratch;
+#[cfg(feature = "feat-layer")]
+mod strict;
+#[cfg(feature = "feat-tracing-subscriber")]
+pub mod subscriber;
+#[cfg(feature = "feat-testing")]
+pub mod testing;
+#[cfg(feature = "feat-layer")]
+mod throttle;
+#[cfg(feature = "feat-trailer")]
+mod trailer;
+#[cfg(feature = "feat-headers")]
+mod typed;
+#[cfg(feature = "feat-layer")]
+mod visit;
+#[cfg(feature = "feat-std")]
+mod waterfall;
+
+#[cfg(feature = "feat-layer")]
 use std::{
+    borrow::Cow,
     future::Future,
+    io::Write,
+    ops::Deref,
     pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
     task::{ready, Context, Poll},
-    time::Instant,
+    time::{Duration, Instant, SystemTime},
 };
 
-use http::{header::Entry as HeaderEntry, HeaderName, Request, Response};
-use macro_toolset::string::{NumStr, StringExtT};
+#[cfg(feature = "feat-layer")]
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Version};
+#[cfg(feature = "feat-layer")]
 use pin_project_lite::pin_project;
 
+#[cfg(feature = "feat-tokio")]
+pub use crate::context::record;
+#[cfg(feature = "feat-debug")]
+pub use crate::debug::debug_routes;
+#[cfg(feature = "feat-axum")]
+pub use crate::response::Timed;
+#[cfg(feature = "feat-router")]
+pub use crate::router::{middleware, MiddlewareFuture, RouterExt};
+#[cfg(feature = "feat-trailer")]
+pub use crate::trailer::{TrailerBody, TrailerFuture, TrailerLayer, TrailerService};
+#[cfg(feature = "feat-headers")]
+pub use crate::typed::ServerTiming;
+#[cfg(feature = "feat-layer")]
+pub use crate::{
+    aggregate::Aggregator,
+    cache::CacheStatus,
+    flag::{AsyncFeatureFlag, AsyncFlagFuture, AsyncFlagLayer, AsyncFlagService, FeatureFlag},
+    full::FullPolicy,
+    handle::ServerTimingHandle,
+    merge::DuplicatePolicy,
+    method::MethodPolicy,
+    nested::NestedPolicy,
+    panic::Panicked,
+    preset::Preset,
+    reporting::{ReportFuture, ReportLayer, ReportService},
+    sample::{SampleKey, Sampling},
+    strict::StrictMode,
+    throttle::{HeaderRateLimit, LimitKey},
+};
+#[cfg(feature = "feat-layer")]
+use crate::{
+    cache::cache_markers,
+    deferred::{AsyncMetrics, Deferred},
+    error::OnError,
+    flag::{Flag, FlagEnabled},
+    method::Methods,
+    nested::Depth,
+    panic::poll_catching,
+    param::ParamFn,
+    policy::{Exchange, MinDuration, Parts, Policies, Policy},
+    redact::Redact,
+    report::{AppFn, Enrich, OnTiming, RequestMeta, TenantFn},
+    visit::VisitMut,
+};
+#[cfg(feature = "feat-std")]
+pub use crate::{
+    diff::{MetricDelta, TimingDiff},
+    error::{InvalidName, ServerTimingError},
+    report::TimingReport,
+    waterfall::render_waterfall,
+};
+#[cfg(feature = "feat-std")]
+pub use server_timing_core::SERVER_TIMING;
+pub use server_timing_core::{
+    format_entry, parse, server_timing_name, Millis, NonAsciiPolicy, ParamOrder, ServerTimingName,
+    TimingMetric,
+};
+#[cfg(feature = "feat-layer")]
+use server_timing_core::{
+    parse::{is_token, parse_header, push_param, Entries, ParseMode},
+    push_description, rollup, Style, DEFAULT_PRECISION,
+};
+
+#[cfg(feature = "feat-layer")]
 #[derive(Debug, Clone)]
 /// A middleware that will add a Server-Timing header to the response.
-pub struct ServerTimingLayer<'a> {
-    /// The service name.
-    app: &'a str,
+pub struct ServerTimingLayer {
+    /// The options of the middleware.
+    config: SharedConfig,
+}
 
-    /// An optional description of the service.
-    description: Option<&'a str>,
+#[cfg(feature = "feat-layer")]
+#[derive(Debug, Clone)]
+/// Options shared by the layer, the services and the response futures.
+enum SharedConfig {
+    /// Options living in a `static`.
+    Static(&'static Config),
+
+    /// Reference-counted options.
+    Arc(Arc<Config>),
 }
 
-impl<'a> ServerTimingLayer<'a> {
-    #[inline]
-    /// Creates a new `ServerTimingLayer` with the given service name.
-    pub const fn new(app: &'a str) -> Self {
-        ServerTimingLayer {
-            app,
-            description: None,
-        }
-    }
+#[cfg(feature = "feat-layer")]
+impl SharedConfig {
+    /// Returns the options to modify, copied first if shared or static.
+    fn make_mut(&mut self) -> &mut Config {
+        let config = match self {
+            Self::Static(config) => Arc::new((*config).clone()),
+            Self::Arc(config) => return Arc::make_mut(config),
+        };
 
-    #[inline]
-    /// Adds a description to the service name.
-    pub const fn with_description(mut self, description: &'a str) -> Self {
-        self.description = Some(description);
-        self
+        *self = Self::Arc(config);
+        self.make_mut()
     }
 }
 
-impl<'a, S> tower_layer::Layer<S> for ServerTimingLayer<'a> {
-    type Service = ServerTimingService<'a, S>;
+#[cfg(feature = "feat-layer")]
+impl Deref for SharedConfig {
+    type Target = Config;
 
-    fn layer(&self, service: S) -> Self::Service {
-        ServerTimingService {
-            service,
-            app: self.app,
-            description: self.description,
+    #[inline]
+    fn deref(&self) -> &Config {
+        match self {
+            Self::Static(config) => config,
+            Self::Arc(config) => config,
         }
     }
 }
 
+#[cfg(feature = "feat-layer")]
 #[derive(Debug, Clone)]
-/// A service that will add a Server-Timing header to the response.
-pub struct ServerTimingService<'a, S> {
-    /// The service to wrap.
-    service: S,
-
+/// The options of [`ServerTimingLayer`], shared by the services and response
+/// futures it creates so that cloning them per connection or request is cheap.
+///
+/// Built with the methods of [`ServerTimingLayer`], see
+/// [`ServerTimingLayer::config`], or in `const` contexts with the ones of
+/// [`Config`], see [`ServerTimingLayer::static_config`].
+pub struct Config {
     /// The service name.
-    app: &'a str,
+    app: Cow<'static, str>,
 
     /// An optional description of the service.
-    description: Option<&'a str>,
+    description: Option<Cow<'static, str>>,
+
+    /// An optional hook resolving the service name per request.
+    app_fn: Option<AppFn>,
+
+    /// Whether to append a separate header value instead of merging into the
+    /// existing one.
+    append: bool,
+
+    /// Whether to add the `timeout` param on timeout responses.
+    timeout_marker: bool,
+
+    /// What happens when an outer instance of the middleware is already
+    /// timing the request.
+    nested: NestedPolicy,
+
+    /// Whether `404 Not Found` responses of unmatched routes get the header.
+    not_found: bool,
+
+    /// Whether to add the `seq` param.
+    sequence: bool,
+
+    /// Whether to add the `end` param.
+    end_timestamp: bool,
+
+    /// Whether custom metrics get the `start` param.
+    start_offsets: bool,
+
+    /// Whether custom metrics get the `share` param.
+    share: bool,
+
+    /// The default number of decimal places of durations.
+    precision: u8,
+
+    /// The order of the params within entries.
+    param_order: ParamOrder,
+
+    /// What happens to the non-ASCII characters of descriptions.
+    non_ascii: NonAsciiPolicy,
+
+    /// The max number of characters of the descriptions of custom metrics.
+    max_description: Option<usize>,
+
+    /// How non-compliant header values are handled, not checked if `None`.
+    strict: Option<StrictMode>,
+
+    /// Whether to add the `clamped` param when negative durations are
+    /// clamped.
+    clamp_marker: bool,
+
+    /// How upstream headers are checked, kept as is if `None`.
+    upstream_parsing: Option<ParseMode>,
+
+    /// What happens to upstream entries named like the service.
+    duplicates: DuplicatePolicy,
+
+    /// What happens when the header map of the response is full.
+    full: FullPolicy,
+
+    /// Whether to convert the cache headers of the response into markers.
+    cache_markers: bool,
+
+    /// Whether to add the `proto` param with the HTTP version.
+    protocol: bool,
+
+    /// The max size of the headers of the response, beyond which the header
+    /// is shrunk or skipped.
+    max_header_size: Option<usize>,
+
+    /// An optional `Timing-Allow-Origin` header added along the header.
+    timing_allow_origin: Option<HeaderValue>,
+
+    /// Whether to split the duration into `route` and `handler` entries.
+    phases: bool,
+
+    /// Whether to format the header in a per-thread scratch buffer.
+    scratch_buffer: bool,
+
+    /// Whether to add the `thread` param with the thread completing the
+    /// response.
+    thread: bool,
+
+    /// Whether to respond with `500 Internal Server Error` when the inner
+    /// service panics.
+    catch_panic: bool,
+
+    /// Whether `?server_timing=verbose` switches requests to verbose
+    /// emission, in debug builds.
+    verbose_query: bool,
+
+    /// An optional counter of the bytes allocated by the current thread,
+    /// behind the `alloc` param.
+    #[cfg(feature = "feat-alloc")]
+    alloc_counter: Option<fn() -> u64>,
+
+    /// An optional service version, reported as the `ver` entry.
+    version: Option<Arc<str>>,
+
+    /// An optional instance name, reported as the `host` entry.
+    host: Option<Arc<str>>,
+
+    /// Optional rolling statistics behind the `pct` param.
+    percentile: Option<Aggregator>,
+
+    /// An optional callback returning extra params based on the response.
+    params: Option<ParamFn>,
+
+    /// The options depending on the request method.
+    methods: Methods,
+
+    /// The policies deciding which responses get the header, including the
+    /// built-in filters, sampling and rate limiting.
+    policies: Policies,
+
+    /// An optional flag enabling the middleware per request.
+    flag: Option<Flag>,
+
+    /// An optional hook rewriting the descriptions of metrics.
+    redact: Option<Redact>,
+
+    /// An optional hook editing the metrics before serialization.
+    visit_mut: Option<VisitMut>,
+
+    /// An optional callback observing errors.
+    on_error: Option<OnError>,
+
+    /// An optional callback observing the timings of sampled requests.
+    on_timing: Option<OnTiming>,
+
+    /// Which requests get the callback, the ones getting the header if
+    /// `None`.
+    on_timing_sampling: Option<Sampling>,
+
+    /// The request headers passed to the callback.
+    report_headers: Vec<HeaderName>,
+
+    /// An optional hook returning labels passed to the callback.
+    enrich: Option<Enrich>,
+
+    /// An optional hook resolving extra metrics alongside the inner service.
+    async_metrics: Option<AsyncMetrics>,
+
+    /// An optional hook extracting the tenant of requests.
+    tenant: Option<TenantFn>,
 }
 
-impl<'a, S, ReqBody, ResBody> tower_service::Service<Request<ReqBody>>
-    for ServerTimingService<'a, S>
-where
-    S: tower_service::Service<Request<ReqBody>, Response = Response<ResBody>>,
-    ResBody: Default,
-{
-    type Response = S::Response;
-    type Error = S::Error;
-    type Future = ResponseFuture<'a, S::Future>;
+#[cfg(feature = "feat-layer")]
+impl Config {
+    #[inline]
+    /// Creates new options with the given service name, the defaults of
+    /// [`ServerTimingLayer::new`].
+    pub const fn new(app: &'static str) -> Self {
+        Self::build(app, None)
+    }
 
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+    #[inline]
+    /// Like [`Config::new`], with a description of the service, see
+    /// [`ServerTimingLayer::with_description`].
+    pub const fn described(app: &'static str, description: &'static str) -> Self {
+        Self::build(app, Some(description))
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        ResponseFuture {
-            inner: self.service.call(req),
-            request_time: Instant::now(),
-            app: self.app,
-            description: self.description,
+    /// Creates new options with the defaults.
+    const fn build(app: &'static str, description: Option<&'static str>) -> Self {
+        Self {
+            app: Cow::Borrowed(app),
+            description: match description {
+                Some(description) => Some(Cow::Borrowed(description)),
+                None => None,
+            },
+            app_fn: None,
+            append: false,
+            timeout_marker: false,
+            nested: NestedPolicy::Keep,
+            not_found: true,
+            sequence: false,
+            end_timestamp: false,
+            start_offsets: false,
+            share: false,
+            precision: DEFAULT_PRECISION,
+            param_order: ParamOrder::DescFirst,
+            non_ascii: NonAsciiPolicy::Keep,
+            max_description: Some(DEFAULT_MAX_DESCRIPTION),
+            strict: None,
+            clamp_marker: false,
+            upstream_parsing: None,
+            duplicates: DuplicatePolicy::KeepBoth,
+            full: FullPolicy::Report,
+            cache_markers: false,
+            protocol: false,
+            thread: false,
+            catch_panic: false,
+            verbose_query: false,
+            scratch_buffer: true,
+            phases: false,
+            timing_allow_origin: None,
+            max_header_size: None,
+            #[cfg(feature = "feat-alloc")]
+            alloc_counter: None,
+            version: None,
+            host: None,
+            percentile: None,
+            params: None,
+            methods: Methods::new(),
+            policies: Policies::new(),
+            flag: None,
+            redact: None,
+            visit_mut: None,
+            on_error: None,
+            on_timing: None,
+            on_timing_sampling: None,
+            report_headers: Vec::new(),
+            enrich: None,
+            async_metrics: None,
+            tenant: None,
         }
     }
-}
 
-pin_project! {
-    /// A future that will add a Server-Timing header to the response.
-    pub struct ResponseFuture<'a, F> {
-        #[pin]
-        inner: F,
-        request_time: Instant,
-        app: &'a str,
-        description: Option<&'a str>,
+    #[inline]
+    /// See [`ServerTimingLayer::with_min_duration`].
+    pub const fn with_min_duration(mut self, min_duration: Duration) -> Self {
+        self.policies.min_duration = MinDuration(min_duration);
+        self
     }
-}
 
-const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+    #[inline]
+    /// See [`ServerTimingLayer::with_append`].
+    pub const fn with_append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
 
-impl<F, B, E> Future for ResponseFuture<'_, F>
-where
-    F: Future<Output = Result<Response<B>, E>>,
-    B: Default,
-{
-    type Output = Result<Response<B>, E>;
+    #[inline]
+    /// See [`ServerTimingLayer::with_timeout_marker`].
+    pub const fn with_timeout_marker(mut self, timeout_marker: bool) -> Self {
+        self.timeout_marker = timeout_marker;
+        self
+    }
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
+    #[inline]
+    /// See [`ServerTimingLayer::with_collapse_nested`].
+    pub const fn with_collapse_nested(self, collapse_nested: bool) -> Self {
+        self.with_nested_policy(if collapse_nested {
+            NestedPolicy::Collapse
+        } else {
+            NestedPolicy::Keep
+        })
+    }
 
-        let mut response: Response<B> = ready!(this.inner.poll(cx))?;
-
-        match response.headers_mut().try_entry(SERVER_TIMING) {
-            Ok(entry) => match entry {
-                HeaderEntry::Occupied(mut val) => {
-                    if let Ok(v) = (
-                        this.app.with_suffix(";"),
-                        this.description.with_prefix("desc=\"").with_suffix("\";"),
-                        NumStr::new_default(this.request_time.elapsed().as_secs_f32() * 1000.0)
-                            .set_resize_len::<1>()
-                            .with_prefix("dur="),
-                        val.get().to_str().with_prefix(", "),
-                    )
-                        .to_http_header_value()
-                    {
-                        val.insert(v);
-                    } else {
-                        // unlikely to happen, but if it does, just ignore it.
-                    }
-                }
-                HeaderEntry::Vacant(val) => {
-                    if let Ok(v) = (
-                        this.app.with_suffix(";"),
-                        this.description.with_prefix("desc=\"").with_suffix("\";"),
-                        NumStr::new_default(this.request_time.elapsed().as_secs_f32() * 1000.0)
-                            .set_resize_len::<1>()
-                            .with_prefix("dur="),
-                    )
-                        .to_http_header_value()
-                    {
-                        val.insert(v);
-                    } else {
-                        // unlikely to happen, but if it does, just ignore it.
-                    }
-                }
-            },
-            Err(_e) => {
-                #[cfg(feature = "feat-tracing")]
-                tracing::error!("Failed to add `server-timing` header: {_e:?}");
-                // header name was invalid (it wasn't) or too many headers (just
-                // give up).
-            }
-        };
+    #[inline]
+    /// See [`ServerTimingLayer::with_nested_policy`].
+    pub const fn with_nested_policy(mut self, nested: NestedPolicy) -> Self {
+        self.nested = nested;
+        self
+    }
 
-        Poll::Ready(Ok(response))
+    #[inline]
+    /// See [`ServerTimingLayer::with_not_found`].
+    pub const fn with_not_found(mut self, not_found: bool) -> Self {
+        self.not_found = not_found;
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::time::Duration;
+    #[inline]
+    /// See [`ServerTimingLayer::with_sequence`].
+    pub const fn with_sequence(mut self, sequence: bool) -> Self {
+        self.sequence = sequence;
+        self
+    }
 
-    use axum::{routing::get, Router};
-    use http::{HeaderMap, HeaderValue};
+    #[inline]
+    /// See [`ServerTimingLayer::with_end_timestamp`].
+    pub const fn with_end_timestamp(mut self, end_timestamp: bool) -> Self {
+        self.end_timestamp = end_timestamp;
+        self
+    }
 
-    use super::ServerTimingLayer;
+    #[inline]
+    /// See [`ServerTimingLayer::with_start_offsets`].
+    pub const fn with_start_offsets(mut self, start_offsets: bool) -> Self {
+        self.start_offsets = start_offsets;
+        self
+    }
 
-    #[test]
-    fn service_name() {
-        let name = "svc1";
-        let obj = ServerTimingLayer::new(name);
-        assert_eq!(obj.app, name);
+    #[inline]
+    /// See [`ServerTimingLayer::with_share`].
+    pub const fn with_share(mut self, share: bool) -> Self {
+        self.share = share;
+        self
     }
 
-    #[test]
-    fn service_desc() {
-        let name = "svc1";
-        let desc = "desc1";
-        let obj = ServerTimingLayer::new(name).with_description(desc);
-        assert_eq!(obj.app, name);
-        assert_eq!(obj.description, Some(desc));
+    #[inline]
+    /// See [`ServerTimingLayer::with_precision`].
+    pub const fn with_precision(mut self, precision: u8) -> Self {
+        self.precision = precision;
+        self
     }
 
-    #[tokio::test]
-    async fn axum_test() {
-        let name = "svc1";
-        let app = Router::new()
-            .route(
-                "/",
-                get(|| async move {
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                    ""
-                }),
-            )
-            .layer(ServerTimingLayer::new(name));
+    #[inline]
+    /// See [`ServerTimingLayer::with_param_order`].
+    pub const fn with_param_order(mut self, order: ParamOrder) -> Self {
+        self.param_order = order;
+        self
+    }
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
+    #[inline]
+    /// See [`ServerTimingLayer::with_non_ascii`].
+    pub const fn with_non_ascii(mut self, policy: NonAsciiPolicy) -> Self {
+        self.non_ascii = policy;
+        self
+    }
 
-        tokio::spawn(async move { axum::serve(listener, app.into_make_service()).await });
+    #[inline]
+    /// See [`ServerTimingLayer::with_max_description`].
+    pub const fn with_max_description(mut self, max: Option<usize>) -> Self {
+        self.max_description = max;
+        self
+    }
 
-        let _ = tokio::task::spawn_blocking(|| {
-            let headers = minreq::get("http://localhost:3001/")
-                .send()
-                .unwrap()
-                .headers;
+    #[inline]
+    /// See [`ServerTimingLayer::with_strict`].
+    pub const fn with_strict(mut self, mode: StrictMode) -> Self {
+        self.strict = Some(mode);
+        self
+    }
 
-            let hdr = headers.get("server-timing");
-            assert!(
-                hdr.is_some(),
-                "Cannot find `server-timing` from: {headers:#?}"
-            );
+    #[inline]
+    /// See [`ServerTimingLayer::with_clamp_marker`].
+    pub const fn with_clamp_marker(mut self, clamp_marker: bool) -> Self {
+        self.clamp_marker = clamp_marker;
+        self
+    }
 
-            let val: f32 = hdr.unwrap()[9..].parse().unwrap();
-            assert!(
-                (100f32..300f32).contains(&val),
-                "Invalid `server-timing` from: {headers:#?}"
-            );
-        })
-        .await;
+    #[inline]
+    /// See [`ServerTimingLayer::with_upstream_parsing`].
+    pub const fn with_upstream_parsing(mut self, mode: ParseMode) -> Self {
+        self.upstream_parsing = Some(mode);
+        self
     }
 
-    #[tokio::test]
-    async fn support_existing_header() {
-        let name = "svc1";
-        let app = Router::new()
-            .route(
-                "/",
-                get(|| async move {
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                    let mut hdr = HeaderMap::new();
-                    hdr.insert("server-timing", HeaderValue::from_static("inner;dur=23"));
-                    (hdr, "")
-                }),
-            )
-            .layer(ServerTimingLayer::new(name).with_description("desc1"));
+    #[inline]
+    /// See [`ServerTimingLayer::with_cache_markers`].
+    pub const fn with_cache_markers(mut self, cache_markers: bool) -> Self {
+        self.cache_markers = cache_markers;
+        self
+    }
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3003").await.unwrap();
-        tokio::spawn(async { axum::serve(listener, app.into_make_service()).await });
+    #[inline]
+    /// See [`ServerTimingLayer::with_protocol`].
+    pub const fn with_protocol(mut self, protocol: bool) -> Self {
+        self.protocol = protocol;
+        self
+    }
 
-        let _ = tokio::task::spawn_blocking(|| {
-            let headers = minreq::get("http://localhost:3003/")
-                .send()
-                .unwrap()
-                .headers;
+    #[inline]
+    /// See [`ServerTimingLayer::with_max_header_size`].
+    pub const fn with_max_header_size(mut self, max_header_size: usize) -> Self {
+        self.max_header_size = Some(max_header_size);
+        self
+    }
 
-            let hdr = headers.get("server-timing").unwrap();
-            assert!(hdr.contains("svc1"));
-            assert!(hdr.contains("inner"));
-            println!("{hdr}");
-        })
-        .await;
+    #[inline]
+    /// See [`ServerTimingLayer::with_phases`].
+    pub const fn with_phases(mut self, phases: bool) -> Self {
+        self.phases = phases;
+        self
+    }
+
+    #[inline]
+    /// See [`ServerTimingLayer::with_scratch_buffer`].
+    pub const fn with_scratch_buffer(mut self, scratch_buffer: bool) -> Self {
+        self.scratch_buffer = scratch_buffer;
+        self
+    }
+
+    #[inline]
+    /// See [`ServerTimingLayer::with_thread`].
+    pub const fn with_thread(mut self, thread: bool) -> Self {
+        self.thread = thread;
+        self
+    }
+
+    #[inline]
+    /// See [`ServerTimingLayer::with_catch_panic`].
+    pub const fn with_catch_panic(mut self, catch_panic: bool) -> Self {
+        self.catch_panic = catch_panic;
+        self
+    }
+
+    #[inline]
+    /// See [`ServerTimingLayer::with_verbose_query`].
+    pub const fn with_verbose_query(mut self, verbose_query: bool) -> Self {
+        self.verbose_query = verbose_query;
+        self
+    }
+
+    #[cfg(feature = "feat-alloc")]
+    #[inline]
+    /// See [`ServerTimingLayer::with_alloc_counter`].
+    pub const fn with_alloc_counter(mut self, counter: fn() -> u64) -> Self {
+        self.alloc_counter = Some(counter);
+        self
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+impl ServerTimingLayer {
+    #[inline]
+    /// Creates a new `ServerTimingLayer` with the given service name.
+    pub fn new(app: impl Into<Cow<'static, str>>) -> Self {
+        let mut config = Config::new("");
+        config.app = app.into();
+        Self::from_config(Arc::new(config))
+    }
+
+    #[inline]
+    /// Creates a new `ServerTimingLayer` with the given service name, checking
+    /// that it's a valid `Server-Timing` token right away.
+    ///
+    /// Otherwise, every response would get a malformed header, e.g. ignored
+    /// by browsers for a name with spaces.
+    ///
+    /// ```rust
+    /// # use miku_server_timing::ServerTimingLayer;
+    /// assert!(ServerTimingLayer::try_new("api-gateway").is_ok());
+    /// assert!(ServerTimingLayer::try_new("API gateway").is_err());
+    /// ```
+    pub fn try_new(app: impl Into<Cow<'static, str>>) -> Result<Self, InvalidName> {
+        let app = app.into();
+        if is_token(&app) {
+            Ok(Self::new(app))
+        } else {
+            Err(InvalidName(app))
+        }
+    }
+
+    #[inline]
+    /// Creates a new `ServerTimingLayer` with options living in a `static`,
+    /// e.g. to share the layer across lazily built routers:
+    ///
+    /// ```rust
+    /// # use miku_server_timing::{Config, ServerTimingLayer};
+    /// static CONFIG: Config = Config::new("app").with_timeout_marker(true);
+    /// static LAYER: ServerTimingLayer = ServerTimingLayer::static_config(&CONFIG);
+    /// ```
+    ///
+    /// Options set afterwards apply to a copy.
+    pub const fn static_config(config: &'static Config) -> Self {
+        ServerTimingLayer {
+            config: SharedConfig::Static(config),
+        }
+    }
+
+    #[inline]
+    /// Creates a new `ServerTimingLayer` sharing the given options, e.g. the
+    /// ones of another layer, see [`ServerTimingLayer::config`].
+    ///
+    /// Options set afterwards only apply to this layer, the shared ones are
+    /// copied on write.
+    pub const fn from_config(config: Arc<Config>) -> Self {
+        ServerTimingLayer {
+            config: SharedConfig::Arc(config),
+        }
+    }
+
+    #[inline]
+    /// Returns the options of the layer, which can be shared by multiple
+    /// routers with [`ServerTimingLayer::from_config`].
+    ///
+    /// Static options are copied.
+    pub fn config(&self) -> Arc<Config> {
+        match &self.config {
+            SharedConfig::Static(config) => Arc::new((*config).clone()),
+            SharedConfig::Arc(config) => Arc::clone(config),
+        }
+    }
+
+    #[inline]
+    /// Returns the options to modify, copied first if shared or static.
+    fn config_mut(&mut self) -> &mut Config {
+        let config = self.config.make_mut();
+        config.methods.reset();
+        config
+    }
+
+    /// Creates a new `ServerTimingLayer` with the given service name, and the
+    /// options of the process-wide default set with
+    /// [`ServerTimingLayer::init_default`].
+    ///
+    /// Without a default, the layer has the same options as
+    /// [`ServerTimingLayer::new`].
+    pub fn from_default(app: impl Into<Cow<'static, str>>) -> Self {
+        let Some(layer) = DEFAULT_LAYER.get() else {
+            return Self::new(app);
+        };
+
+        let mut layer = layer.clone();
+        layer.config_mut().app = app.into();
+        layer
+    }
+
+    /// Sets the process-wide default options used by
+    /// [`ServerTimingLayer::from_default`], so that teams can standardize on
+    /// them in one place, e.g. at startup. The service name is ignored.
+    ///
+    /// Returns `false` if a default has already been set, which is kept.
+    pub fn init_default(self) -> bool {
+        DEFAULT_LAYER.set(self).is_ok()
+    }
+
+    #[inline]
+    /// Adds a description to the service name.
+    pub fn with_description(mut self, description: impl Into<Cow<'static, str>>) -> Self {
+        self.config_mut().description = Some(description.into());
+        self
+    }
+
+    #[inline]
+    /// Resolves the service name per request, e.g. from the path or an
+    /// extension, so that one router can report different logical services,
+    /// like `api` for `/api` and `admin` for `/admin`.
+    ///
+    /// The hook is called before the request is passed on, without its body,
+    /// and the name given to [`ServerTimingLayer::new`] is the fallback for
+    /// requests which don't reach it, e.g. nested ones with
+    /// [`ServerTimingLayer::with_collapse_nested`].
+    pub fn with_app_fn<F>(mut self, app: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Cow<'static, str> + Send + Sync + 'static,
+    {
+        self.config_mut().app_fn = Some(AppFn::new(app));
+        self
+    }
+
+    #[inline]
+    /// Skips the header for responses completed faster than the given
+    /// duration.
+    ///
+    /// Useful for health checks or cached hits, which make up most of the
+    /// traffic but are seldom worth inspecting.
+    pub fn with_min_duration(mut self, min_duration: Duration) -> Self {
+        self.config_mut().policies.min_duration = MinDuration(min_duration);
+        self
+    }
+
+    #[inline]
+    /// Appends a separate `Server-Timing` header value instead of merging into
+    /// the existing one.
+    ///
+    /// The spec allows multiple header fields, and appending avoids rewriting
+    /// the value produced by inner services.
+    pub fn with_append(mut self, append: bool) -> Self {
+        self.config_mut().append = append;
+        self
+    }
+
+    #[inline]
+    /// Adds a `timeout=1` param when the response status indicates a timeout,
+    /// i.e. `408 Request Timeout` or `504 Gateway Timeout`.
+    ///
+    /// Timeout responses synthesized by inner layers, like
+    /// `tower_http::timeout` or `tower::timeout` with an error handler, are
+    /// decorated like any other response, and this tells them apart.
+    pub fn with_timeout_marker(mut self, timeout_marker: bool) -> Self {
+        self.config_mut().timeout_marker = timeout_marker;
+        self
+    }
+
+    #[inline]
+    /// Leaves the header to the outer instance of the middleware when it is
+    /// applied more than once in the same stack, e.g. to both a nested router
+    /// and the router it's nested in.
+    ///
+    /// By default, every instance adds its own entry, the outermost one first.
+    /// With this enabled, an inner instance detecting an outer one only joins
+    /// its [`ServerTimingHandle`], so that metrics recorded deeper in the stack
+    /// are reported once, after the entry of the outermost instance.
+    ///
+    /// Shorthand for [`NestedPolicy::Collapse`], see
+    /// [`ServerTimingLayer::with_nested_policy`].
+    pub fn with_collapse_nested(self, collapse_nested: bool) -> Self {
+        self.with_nested_policy(if collapse_nested {
+            NestedPolicy::Collapse
+        } else {
+            NestedPolicy::Keep
+        })
+    }
+
+    #[inline]
+    /// Sets what an instance of the middleware does when it detects an outer
+    /// one already timing the request, default [`NestedPolicy::Keep`].
+    ///
+    /// Applying the same layer more than once is a common accident with
+    /// nested routers.
+    pub fn with_nested_policy(mut self, nested: NestedPolicy) -> Self {
+        self.config_mut().nested = nested;
+        self
+    }
+
+    #[inline]
+    /// Whether `404 Not Found` responses of unmatched routes get the header,
+    /// default `true`.
+    ///
+    /// Bots hammering random paths hit the fallback (unmatched routes) and
+    /// would inflate header bytes for nothing. `404 Not Found` responses of
+    /// matched routes, e.g. "user not found", still get the header.
+    ///
+    /// Routes are matched by axum's `MatchedPath`, so this needs the
+    /// `feat-router` feature and the layer added with `Router::layer`.
+    /// Otherwise the route is unknown, and all `404 Not Found` responses are
+    /// skipped.
+    pub fn with_not_found(mut self, not_found: bool) -> Self {
+        self.config_mut().not_found = not_found;
+        self
+    }
+
+    #[inline]
+    /// Only adds the header to responses whose content type matches the
+    /// given media range or any other given one, e.g. `text/html`,
+    /// `application/json` or `text/*`, since emitting on every image or
+    /// video chunk wastes bytes at scale.
+    ///
+    /// Matching ignores case and parameters like `charset`. Responses without
+    /// a content type still get the header, and all of them do by default.
+    pub fn with_content_type(mut self, range: &str) -> Self {
+        self.config_mut().policies.content_types.push(range);
+        self
+    }
+
+    #[inline]
+    /// Adds a `seq` param carrying a per-process, monotonically increasing
+    /// sequence number, e.g. `app;dur=12.3;seq=42`.
+    ///
+    /// Client-side collectors can use it to detect dropped or duplicated
+    /// beacons and to correlate them with server logs. The counter is shared
+    /// by all instances of the middleware and only advances when the header
+    /// is added.
+    pub fn with_sequence(mut self, sequence: bool) -> Self {
+        self.config_mut().sequence = sequence;
+        self
+    }
+
+    #[inline]
+    /// Adds an `end` param carrying the wall-clock time the header is added
+    /// at, in milliseconds since the Unix epoch, e.g.
+    /// `app;dur=12.3;end=1712345679042`.
+    ///
+    /// Audits can cross-check it against downstream logging systems without
+    /// relying on the clocks of log ingestion.
+    pub fn with_end_timestamp(mut self, end_timestamp: bool) -> Self {
+        self.config_mut().end_timestamp = end_timestamp;
+        self
+    }
+
+    #[inline]
+    /// Adds a `start` param to custom metrics recorded with a start offset
+    /// (see [`TimingMetric::with_start`]), e.g. `db;dur=5.0;start=12.3`, so
+    /// that the waterfall can be reconstructed client-side.
+    ///
+    /// Disabled by default to preserve header size.
+    pub fn with_start_offsets(mut self, start_offsets: bool) -> Self {
+        self.config_mut().start_offsets = start_offsets;
+        self
+    }
+
+    #[inline]
+    /// Adds a `share` param to custom metrics with a duration, carrying their
+    /// share of the whole request as a rounded percentage, e.g.
+    /// `app;dur=120.0, db;dur=40.0;share=33`, making it obvious in devtools
+    /// where the time went.
+    ///
+    /// Shares may add up to more than 100, e.g. with concurrent metrics.
+    pub fn with_share(mut self, share: bool) -> Self {
+        self.config_mut().share = share;
+        self
+    }
+
+    #[inline]
+    /// Sets the number of decimal places of durations, capped at 6, default
+    /// 1, i.e. 0.1ms.
+    ///
+    /// Custom metrics may override it, see [`TimingMetric::with_precision`].
+    pub fn with_precision(mut self, precision: u8) -> Self {
+        self.config_mut().precision = precision;
+        self
+    }
+
+    #[inline]
+    /// Sets the order of the params within entries, e.g. `dur` before `desc`
+    /// for parsers expecting it first, see [`ParamOrder`].
+    pub fn with_param_order(mut self, order: ParamOrder) -> Self {
+        self.config_mut().param_order = order;
+        self
+    }
+
+    #[inline]
+    /// Sets what happens to the non-ASCII characters of descriptions, e.g.
+    /// percent-encoding them so that descriptions in any language can be read
+    /// by clients expecting visible ASCII, see [`NonAsciiPolicy`].
+    ///
+    /// Applies to the description of the service, custom metrics, and the
+    /// `ver` and `host` entries.
+    pub fn with_non_ascii(mut self, policy: NonAsciiPolicy) -> Self {
+        self.config_mut().non_ascii = policy;
+        self
+    }
+
+    #[inline]
+    /// Sets the max number of characters of the descriptions of custom
+    /// metrics, default 100, or `None` for no limit.
+    ///
+    /// Longer descriptions, e.g. containing SQL or URLs, are truncated to end
+    /// with `...`, without splitting escape sequences, so that they don't blow
+    /// up the header size.
+    pub fn with_max_description(mut self, max: Option<usize>) -> Self {
+        self.config_mut().max_description = max;
+        self
+    }
+
+    #[inline]
+    /// Adds a `clamped` param counting the custom metrics whose negative
+    /// duration or start offset was clamped to 0, e.g.
+    /// `app;dur=12.3;clamped=1`.
+    ///
+    /// Negative values, e.g. from clock anomalies or user-provided durations,
+    /// are always clamped and reported to the
+    /// [`with_on_error`](ServerTimingLayer::with_on_error) callback.
+    pub fn with_clamp_marker(mut self, clamp_marker: bool) -> Self {
+        self.config_mut().clamp_marker = clamp_marker;
+        self
+    }
+
+    #[inline]
+    /// Re-parses the final header value before adding it, checking that it
+    /// complies with the spec, e.g. that names and params are tokens and
+    /// descriptions are properly quoted, to catch format regressions, e.g.
+    /// from metric names with spaces or custom params.
+    ///
+    /// With [`StrictMode::Panic`] non-compliant values panic, so that tests
+    /// fail loudly, and with [`StrictMode::Report`] they are skipped, logged
+    /// and reported as [`ServerTimingError::NonCompliant`], e.g.
+    /// `with_strict(if cfg!(test) { StrictMode::Panic } else { StrictMode::Report })`.
+    pub fn with_strict(mut self, mode: StrictMode) -> Self {
+        self.config_mut().strict = Some(mode);
+        self
+    }
+
+    #[inline]
+    /// Checks the `Server-Timing` headers set by inner services before
+    /// merging into them, dropping the malformed entries or whole values
+    /// depending on the [`ParseMode`], and reporting them to the
+    /// [`with_on_error`](ServerTimingLayer::with_on_error) callback.
+    ///
+    /// By default, upstream headers are kept as is, byte for byte: real-world
+    /// headers are frequently sloppy, and a single malformed entry may make
+    /// browsers drop the whole header.
+    pub fn with_upstream_parsing(mut self, mode: ParseMode) -> Self {
+        self.config_mut().upstream_parsing = Some(mode);
+        self
+    }
+
+    #[inline]
+    /// Sets what happens when an upstream `Server-Timing` header already
+    /// contains an entry named like the service, see [`DuplicatePolicy`].
+    ///
+    /// Both entries are kept by default.
+    ///
+    /// # Panics
+    ///
+    /// When the name of [`DuplicatePolicy::RenameOurs`] or the prefix of
+    /// [`DuplicatePolicy::PrefixTheirs`] isn't a valid token, see
+    /// [`DuplicatePolicy::prefix_theirs`].
+    pub fn with_duplicate_policy(mut self, duplicates: DuplicatePolicy) -> Self {
+        duplicates
+            .check()
+            .expect("Invalid `server-timing` duplicate policy");
+        self.config_mut().duplicates = duplicates;
+        self
+    }
+
+    #[inline]
+    /// Sets what happens when the header map of the response is full, so that
+    /// the `Server-Timing` header can't be added, see [`FullPolicy`].
+    ///
+    /// The header is skipped and the error reported by default.
+    pub fn with_full_policy(mut self, full: FullPolicy) -> Self {
+        self.config_mut().full = full;
+        self
+    }
+
+    #[inline]
+    /// Converts the common CDN and cache headers of the response into
+    /// markers, so that cache diagnostics show up in the same devtools panel:
+    ///
+    /// - `cdn-cache;desc="HIT"` from `CF-Cache-Status`, `X-Cache` or
+    ///   `X-Cache-Status`, by precedence, normalized to upper case, e.g. `Hit
+    ///   from cloudfront` becomes `HIT`.
+    /// - `cdn-age;desc="120"` from `Age`, in seconds.
+    pub fn with_cache_markers(mut self, cache_markers: bool) -> Self {
+        self.config_mut().cache_markers = cache_markers;
+        self
+    }
+
+    #[inline]
+    /// Adds a `proto` param with the HTTP version of the request, by its ALPN
+    /// identifier, e.g. `app;dur=12.3;proto=h2`, so that RUM data can be
+    /// sliced by protocol.
+    ///
+    /// The version is the one set by the server, e.g. hyper for HTTP/1.1 and
+    /// HTTP/2, or h3 over quinn for HTTP/3.
+    pub fn with_protocol(mut self, protocol: bool) -> Self {
+        self.config_mut().protocol = protocol;
+        self
+    }
+
+    #[inline]
+    /// Sets the max size of the headers of the response, counted as in
+    /// HTTP/1.1, i.e. `name: value\r\n`, beyond which only the entry of the
+    /// service is added, or nothing if it doesn't fit either.
+    ///
+    /// Proxies commonly reject responses with headers beyond 8 to 16KB: the
+    /// data is dropped rather than breaking the response, and reported as
+    /// [`ServerTimingError::HeaderTooLarge`], followed by
+    /// [`ServerTimingError::HeaderDropped`] if the header is skipped.
+    pub fn with_max_header_size(mut self, max_header_size: usize) -> Self {
+        self.config_mut().max_header_size = Some(max_header_size);
+        self
+    }
+
+    #[inline]
+    /// Applies a bundle of options for a common setup, see [`Preset`].
+    ///
+    /// Options set afterwards override the ones of the preset:
+    ///
+    /// ```rust
+    /// # use miku_server_timing::{Preset, ServerTimingLayer};
+    /// let layer = ServerTimingLayer::new("app")
+    ///     .with_preset(Preset::Browser)
+    ///     .with_precision(0);
+    /// ```
+    pub fn with_preset(mut self, preset: Preset) -> Self {
+        preset.apply(self.config_mut());
+        self
+    }
+
+    #[inline]
+    /// Adds a `Timing-Allow-Origin` header along the header, unless the
+    /// response already has one, e.g. `*`, letting pages of the given origins
+    /// read the timings through the Resource Timing API.
+    ///
+    /// Without it, browsers only expose the header to same-origin pages.
+    pub fn with_timing_allow_origin(mut self, origin: HeaderValue) -> Self {
+        self.config_mut().timing_allow_origin = Some(origin);
+        self
+    }
+
+    #[inline]
+    /// Splits the duration of the service into two entries, e.g.
+    /// `app;dur=12.3, route;dur=0.2, handler;dur=12.1`:
+    ///
+    /// - `route`, until the future of the inner service is first polled,
+    ///   covering what the inner services do synchronously, e.g. matching the
+    ///   route of an axum router.
+    /// - `handler`, from then on, covering the rest, e.g. extractors and the
+    ///   handler itself.
+    pub fn with_phases(mut self, phases: bool) -> Self {
+        self.config_mut().phases = phases;
+        self
+    }
+
+    #[inline]
+    /// Sets whether the header is formatted in a buffer kept per thread and
+    /// reused across responses, default `true`, saving an allocation per
+    /// response.
+    ///
+    /// Disable it where thread-locals are problematic, e.g. with runtimes
+    /// spawning short-lived threads.
+    pub fn with_scratch_buffer(mut self, scratch_buffer: bool) -> Self {
+        self.config_mut().scratch_buffer = scratch_buffer;
+        self
+    }
+
+    #[inline]
+    /// Adds a `thread` param with the name and id of the thread completing
+    /// the response, e.g. `app;dur=12.3;thread=tokio-runtime-worker#7`.
+    ///
+    /// Useful when diagnosing a bottleneck pinned to a single thread, e.g.
+    /// blocking code accidentally running on the runtime.
+    pub fn with_thread(mut self, thread: bool) -> Self {
+        self.config_mut().thread = thread;
+        self
+    }
+
+    #[inline]
+    /// Catches panics of the inner service, like `tower_http::catch_panic`,
+    /// responding with an empty `500 Internal Server Error` instead, so that
+    /// panicking handlers still get the header and the [`on_timing`]
+    /// callback, with a `panic` marker, e.g. `app;dur=12.3, panic`.
+    ///
+    /// See [`Panicked`] when using `tower_http::catch_panic` too.
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    pub fn with_catch_panic(mut self, catch_panic: bool) -> Self {
+        self.config_mut().catch_panic = catch_panic;
+        self
+    }
+
+    #[inline]
+    /// Lets developers switch a request to verbose emission with
+    /// `?server_timing=verbose`: the options of [`Preset::Verbose`] at full
+    /// precision, bypassing sampling, rate limits, size limits and
+    /// [`ServerTimingLayer::with_min_duration`].
+    ///
+    /// Only honored in debug builds, i.e. with `debug_assertions`, so that it
+    /// can never be enabled in production by accident.
+    pub fn with_verbose_query(mut self, verbose_query: bool) -> Self {
+        self.config_mut().verbose_query = verbose_query;
+        self
+    }
+
+    #[cfg(feature = "feat-alloc")]
+    #[inline]
+    /// Adds an `alloc` param with the bytes allocated while processing the
+    /// request, e.g. `app;dur=12.3;alloc=152kb`, to catch per-request
+    /// allocation regressions in hot endpoints.
+    ///
+    /// The counter returns the bytes allocated so far by the current thread,
+    /// usually from a tracking allocator, see [`allocation`].
+    pub fn with_alloc_counter(mut self, counter: fn() -> u64) -> Self {
+        self.config_mut().alloc_counter = Some(counter);
+        self
+    }
+
+    #[inline]
+    /// Adds a `ver` entry reporting the service version or build hash, e.g.
+    /// `ver;desc="1.4.2+abc123"`.
+    ///
+    /// Useful for spotting which deployment a slow sample came from right in
+    /// the devtools.
+    pub fn with_version(mut self, version: impl Into<Arc<str>>) -> Self {
+        self.config_mut().version = Some(version.into());
+        self
+    }
+
+    #[inline]
+    /// Like [`ServerTimingLayer::with_version`], with the version read from
+    /// the given environment variable.
+    ///
+    /// Nothing changes if the variable is not set or not valid unicode.
+    pub fn with_version_from_env(self, key: &str) -> Self {
+        match std::env::var(key) {
+            Ok(version) => self.with_version(version),
+            Err(_) => self,
+        }
+    }
+
+    #[inline]
+    /// Adds a `host` entry reporting the instance serving the request, e.g.
+    /// `host;desc="pod-7f9c"`.
+    ///
+    /// When one replica misbehaves, individual slow responses can be
+    /// attributed to the exact instance.
+    pub fn with_host(mut self, host: impl Into<Arc<str>>) -> Self {
+        self.config_mut().host = Some(host.into());
+        self
+    }
+
+    #[inline]
+    /// Like [`ServerTimingLayer::with_host`], with the instance name read
+    /// from the `HOSTNAME` environment variable.
+    ///
+    /// Nothing changes if the variable is not set or not valid unicode.
+    pub fn with_host_from_env(self) -> Self {
+        match std::env::var("HOSTNAME") {
+            Ok(host) => self.with_host(host),
+            Err(_) => self,
+        }
+    }
+
+    #[inline]
+    /// Adds a `pct` param telling which percentile the latency of the request
+    /// falls into among recent requests, e.g. `app;dur=432.1;pct=99`.
+    ///
+    /// Every response feeds the given [`Aggregator`], including the ones
+    /// skipped by the other options, so a developer looking at one slow
+    /// request immediately knows whether it's an outlier.
+    pub fn with_percentile(mut self, aggregator: Aggregator) -> Self {
+        self.config_mut().percentile = Some(aggregator);
+        self
+    }
+
+    #[inline]
+    /// Sets a callback returning extra params of the entry of the service
+    /// based on the response, e.g. the cache status from a header or the shard
+    /// from the extensions: `app;dur=12.3;cache=hit;shard="eu-1"`.
+    ///
+    /// Values which are not tokens are quoted, and params with invalid names
+    /// are skipped. The response is passed without its body.
+    pub fn with_param_fn<F>(mut self, params: F) -> Self
+    where
+        F: Fn(&Response<()>) -> Vec<(&'static str, String)> + Send + Sync + 'static,
+    {
+        self.config_mut().params = Some(ParamFn::new(params));
+        self
+    }
+
+    #[inline]
+    /// Only adds the header to the requests picked by the given [`Sampling`],
+    /// evaluated after the [`Policy`]s added with
+    /// [`ServerTimingLayer::with_policy`].
+    ///
+    /// The [`ServerTimingDuration`] extension is inserted regardless.
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.config_mut().policies.sampling.default = Some(sampling);
+        self
+    }
+
+    #[inline]
+    /// Enables the middleware per request with the given [`FeatureFlag`],
+    /// e.g. backed by a feature flag provider.
+    ///
+    /// Requests for which the flag is disabled skip the middleware
+    /// altogether, unlike the ones not picked by
+    /// [`ServerTimingLayer::with_sampling`].
+    ///
+    /// Flags resolved asynchronously are awaited by the [`AsyncFlagLayer`]
+    /// instead.
+    pub fn with_feature_flag(mut self, flag: impl FeatureFlag) -> Self {
+        self.config_mut().flag = Some(Flag::new(flag));
+        self
+    }
+
+    #[inline]
+    /// Enables the middleware only for the request paths matching the given
+    /// pattern, or any other included one, e.g. `/api/**`.
+    ///
+    /// Patterns are compiled once and match the whole path:
+    /// - without wildcards, e.g. `/health`, only that path;
+    /// - ending with `/**`, e.g. `/api/**`, the path and the ones below it;
+    /// - otherwise, `*` matches any part of a segment and a `**` segment any
+    ///   number of segments, e.g. `/users/*/avatar` or `/**/*.png`.
+    ///
+    /// Skipped requests skip the middleware altogether, like with
+    /// [`ServerTimingLayer::with_feature_flag`], which is only evaluated for
+    /// the allowed paths.
+    pub fn with_include(mut self, pattern: &str) -> Self {
+        self.config_mut().policies.paths.include(pattern);
+        self
+    }
+
+    #[inline]
+    /// Skips the middleware for the request paths matching the given pattern,
+    /// e.g. `/health`, even if included, see
+    /// [`ServerTimingLayer::with_include`] for the syntax.
+    pub fn with_exclude(mut self, pattern: &str) -> Self {
+        self.config_mut().policies.paths.exclude(pattern);
+        self
+    }
+
+    #[inline]
+    /// Sets what changes for the requests of the given method, see
+    /// [`MethodPolicy`], e.g. skipping `OPTIONS` preflights, whole
+    /// milliseconds for `GET` and the full detail for `POST`, where latency
+    /// matters most:
+    ///
+    /// ```rust
+    /// # use http::Method;
+    /// # use miku_server_timing::{MethodPolicy, Preset, ServerTimingLayer};
+    /// let layer = ServerTimingLayer::new("app")
+    ///     .with_method_policy(Method::OPTIONS, MethodPolicy::Skip)
+    ///     .with_method_policy(Method::GET, MethodPolicy::Precision(0))
+    ///     .with_method_policy(Method::POST, MethodPolicy::Preset(Preset::Verbose));
+    /// ```
+    ///
+    /// Policies for the same method apply in order, on top of the other
+    /// options whenever they are set.
+    pub fn with_method_policy(mut self, method: Method, policy: MethodPolicy) -> Self {
+        self.config_mut().methods.push(method, policy);
+        self
+    }
+
+    #[inline]
+    /// Adds a [`Policy`] deciding which requests are timed and which
+    /// responses get the header, which all policies must allow, see
+    /// [`policy`] for the built-in ones and how to combine them.
+    ///
+    /// Policies are evaluated after the other filters, e.g.
+    /// [`ServerTimingLayer::with_include`], and before sampling and rate
+    /// limiting.
+    pub fn with_policy(mut self, policy: impl Policy) -> Self {
+        self.config_mut().policies.push(policy);
+        self
+    }
+
+    #[inline]
+    /// Sets a hook rewriting the descriptions of metrics before they are
+    /// serialized, e.g. stripping query strings or masking emails, so that
+    /// sensitive data accidentally recorded never reaches the browser nor the
+    /// [`on_timing`] and [`on_error`] callbacks.
+    ///
+    /// Applies to the metrics recorded while handling the request, before
+    /// truncation, see [`ServerTimingLayer::with_max_description`], but not
+    /// to the configured description of the service nor to entries merged
+    /// from upstream headers.
+    ///
+    /// ```rust
+    /// # use miku_server_timing::ServerTimingLayer;
+    /// let layer =
+    ///     ServerTimingLayer::new("app").with_redaction(|desc: &str| match desc.split_once('?') {
+    ///         Some((path, _)) => path.to_owned(),
+    ///         None => desc.to_owned(),
+    ///     });
+    /// ```
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    /// [`on_error`]: ServerTimingLayer::with_on_error
+    pub fn with_redaction<F>(mut self, redact: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.config_mut().redact = Some(Redact::new(redact));
+        self
+    }
+
+    #[inline]
+    /// Sets a hook editing the metrics recorded while handling the request
+    /// right before they are serialized, after redaction, e.g. to filter,
+    /// rename or re-order them.
+    ///
+    /// The edited metrics are also the ones passed to the [`on_timing`] and
+    /// [`on_error`] callbacks.
+    ///
+    /// ```rust
+    /// # use miku_server_timing::{ServerTimingLayer, TimingMetric};
+    /// // Drops the sub-millisecond entries.
+    /// let layer = ServerTimingLayer::new("app").with_visit_mut(|metrics: &mut Vec<TimingMetric>| {
+    ///     metrics.retain(|metric| !metric.millis().is_some_and(|dur| dur < 1.0));
+    /// });
+    /// ```
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    /// [`on_error`]: ServerTimingLayer::with_on_error
+    pub fn with_visit_mut<F>(mut self, visit_mut: F) -> Self
+    where
+        F: Fn(&mut Vec<TimingMetric>) + Send + Sync + 'static,
+    {
+        self.config_mut().visit_mut = Some(VisitMut::new(visit_mut));
+        self
+    }
+
+    #[inline]
+    /// Sets a callback observing errors which prevent the header from being
+    /// added, e.g. to feed metrics.
+    ///
+    /// Such errors never fail the response, the header is just skipped.
+    /// Malformed upstream headers are reported as well, see
+    /// [`ServerTimingLayer::with_upstream_parsing`], and so are failures of
+    /// the inner service with their latency, see
+    /// [`ServerTimingError::ServiceFailed`].
+    pub fn with_on_error<F>(mut self, on_error: F) -> Self
+    where
+        F: Fn(&ServerTimingError) + Send + Sync + 'static,
+    {
+        self.config_mut().on_error = Some(OnError::new(on_error));
+        self
+    }
+
+    #[inline]
+    /// Sets a callback observing the timings of the requests getting the
+    /// header, unless sampled separately with
+    /// [`ServerTimingLayer::with_on_timing_sampling`], e.g. to feed analytics.
+    ///
+    /// The [`TimingReport`] starts with the entry of the service, followed by
+    /// the custom metrics, and tells the request method, path and response
+    /// status, as well as the headers selected with
+    /// [`ServerTimingLayer::with_report_headers`].
+    pub fn with_on_timing<F>(mut self, on_timing: F) -> Self
+    where
+        F: Fn(&TimingReport) + Send + Sync + 'static,
+    {
+        self.config_mut().on_timing = Some(OnTiming::new(on_timing));
+        self
+    }
+
+    #[inline]
+    /// Captures the given request headers, e.g. `user-agent`, for the
+    /// [`on_timing`] callback, so that exporters have dimensions to group by.
+    ///
+    /// None by default, as headers may carry personal data or secrets.
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    pub fn with_report_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config_mut().report_headers = headers.into_iter().collect();
+        self
+    }
+
+    #[inline]
+    /// Sets a hook returning labels of the request, e.g. the client country,
+    /// ASN or type, added to the [`TimingReport`] passed to the [`on_timing`]
+    /// callback, but not to the header, so that latency analytics can be
+    /// segmented by client population.
+    ///
+    /// The hook is called before the request is passed on, without its body,
+    /// and only when the callback is set. Lookups which must be async can be
+    /// done by an outer middleware inserting their result into the request
+    /// extensions, for the hook to read.
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    pub fn with_enrichment<F>(mut self, enrich: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Vec<(&'static str, String)> + Send + Sync + 'static,
+    {
+        self.config_mut().enrich = Some(Enrich::new(enrich));
+        self
+    }
+
+    #[inline]
+    /// Sets a hook returning a future resolving extra metrics of the request,
+    /// e.g. a `flag;desc="new-checkout"` marker from a feature flag lookup,
+    /// serialized after the custom metrics.
+    ///
+    /// The future is polled alongside the inner service, never delaying the
+    /// response: it's given up on once the response is ready, or once the
+    /// given budget has elapsed since the request was received. The hook is
+    /// called before the request is passed on, without its body.
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use miku_server_timing::{ServerTimingLayer, TimingMetric};
+    /// async fn lookup_flag(user: Option<String>) -> Option<&'static str> {
+    ///     user.map(|_| "new-checkout")
+    /// }
+    ///
+    /// let layer = ServerTimingLayer::new("app").with_async_metrics(Duration::from_millis(5), |req| {
+    ///     let user = req
+    ///         .headers()
+    ///         .get("x-user")
+    ///         .and_then(|user| user.to_str().ok())
+    ///         .map(str::to_owned);
+    ///     async move {
+    ///         lookup_flag(user)
+    ///             .await
+    ///             .map(|flag| TimingMetric::new("flag").with_description(flag))
+    ///             .into_iter()
+    ///             .collect()
+    ///     }
+    /// });
+    /// ```
+    pub fn with_async_metrics<F, Fut>(mut self, budget: Duration, f: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Vec<TimingMetric>> + Send + 'static,
+    {
+        self.config_mut().async_metrics = Some(AsyncMetrics::new(budget, f));
+        self
+    }
+
+    #[inline]
+    /// Sets a hook extracting the tenant of requests, e.g. from a header, the
+    /// subdomain or an extension, so that platforms serving many tenants from
+    /// one shared layer can tell them apart.
+    ///
+    /// The tenant is passed to the [`on_timing`] callback, see
+    /// [`TimingReport::tenant`], and picks the sampling set with
+    /// [`ServerTimingLayer::with_tenant_sampling`]. The hook is called before
+    /// the request is passed on, without its body.
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    pub fn with_tenant<F>(mut self, tenant: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.config_mut().tenant = Some(TenantFn::new(tenant));
+        self
+    }
+
+    #[inline]
+    /// Only adds the header to the requests of the given tenant picked by the
+    /// given [`Sampling`], overriding [`ServerTimingLayer::with_sampling`],
+    /// e.g. `Sampling::new(0.0)` suppresses the header for the tenant.
+    ///
+    /// Requires [`ServerTimingLayer::with_tenant`].
+    pub fn with_tenant_sampling(mut self, tenant: impl Into<String>, sampling: Sampling) -> Self {
+        self.config_mut()
+            .policies
+            .sampling
+            .set(tenant.into(), sampling);
+        self
+    }
+
+    #[inline]
+    /// Caps how many responses per second get the header per route or tenant,
+    /// see [`HeaderRateLimit`].
+    ///
+    /// Applied after the other options, e.g. sampling, so that only the
+    /// responses which would get the header take from the budget.
+    pub fn with_rate_limit(mut self, rate_limit: HeaderRateLimit) -> Self {
+        self.config_mut().policies.rate_limit = Some(rate_limit);
+        self
+    }
+
+    #[inline]
+    /// Sends the timings to the given [`BatchExporter`], replacing the
+    /// [`on_timing`] callback.
+    ///
+    /// [`BatchExporter`]: export::BatchExporter
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    pub fn with_exporter(self, exporter: export::BatchExporter) -> Self {
+        self.with_on_timing(move |report| exporter.push(report.clone()))
+    }
+
+    #[inline]
+    /// Samples the requests getting the [`on_timing`] callback independently
+    /// from the ones getting the header.
+    ///
+    /// Exporting per-request data is expensive while the header is nearly
+    /// free, e.g. the header can be added to every response while only 1% of
+    /// them, at most 100 per second, are exported.
+    ///
+    /// [`on_timing`]: ServerTimingLayer::with_on_timing
+    pub fn with_on_timing_sampling(mut self, sampling: Sampling) -> Self {
+        self.config_mut().on_timing_sampling = Some(sampling);
+        self
+    }
+
+    #[inline]
+    /// Turns the layer into one responding with the [`TimingReport`]
+    /// alongside the response, i.e. `(Response<B>, TimingReport)`, instead of
+    /// adding the header, for custom outputs, e.g. traces or a proprietary
+    /// header.
+    ///
+    /// All the options apply, except the ones deciding whether and how the
+    /// header is added, e.g. sampling, as every request gets a report. It's
+    /// empty for the requests disabled by the feature flag, see
+    /// [`ServerTimingLayer::with_feature_flag`].
+    pub fn into_report_layer(self) -> ReportLayer {
+        ReportLayer::new(self.config)
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+impl<S> tower_layer::Layer<S> for ServerTimingLayer {
+    type Service = ServerTimingService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ServerTimingService {
+            service,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+#[derive(Debug, Clone)]
+/// A service that will add a Server-Timing header to the response.
+pub struct ServerTimingService<S> {
+    /// The service to wrap.
+    service: S,
+
+    /// The options of the middleware.
+    config: SharedConfig,
+}
+
+#[cfg(feature = "feat-layer")]
+impl<S, ReqBody, ResBody> tower_service::Service<Request<ReqBody>> for ServerTimingService<S>
+where
+    S: tower_service::Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        ResponseFuture::start(&self.config, req, |req| self.service.call(req), false)
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+pin_project! {
+    /// A future that will add a Server-Timing header to the response.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        handle: Option<ServerTimingHandle>,
+        request: Option<RequestMeta>,
+        app: Option<Cow<'static, str>>,
+        tenant: Option<String>,
+        protocol: Option<&'static str>,
+        polled: Option<Instant>,
+        deferred: Option<Deferred>,
+        report: bool,
+        exchange: Option<Parts>,
+        matched: bool,
+        trailers: bool,
+        config: SharedConfig,
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+impl<F> ResponseFuture<F> {
+    #[inline]
+    /// Times the response of the future returned by `call`, the same way as
+    /// [`ServerTimingService`], for hand-written services which can't use the
+    /// layer:
+    ///
+    /// ```rust
+    /// # use std::{convert::Infallible, future::Ready, task::{Context, Poll}};
+    /// # use http::{Request, Response};
+    /// # use miku_server_timing::{ResponseFuture, ServerTimingLayer};
+    /// struct MyService {
+    ///     timing: ServerTimingLayer,
+    /// }
+    ///
+    /// impl tower_service::Service<Request<()>> for MyService {
+    ///     type Response = Response<()>;
+    ///     type Error = Infallible;
+    ///     type Future = ResponseFuture<Ready<Result<Response<()>, Infallible>>>;
+    ///
+    ///     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+    ///         Poll::Ready(Ok(()))
+    ///     }
+    ///
+    ///     fn call(&mut self, req: Request<()>) -> Self::Future {
+    ///         ResponseFuture::wrap(&self.timing, req, |_req| {
+    ///             std::future::ready(Ok(Response::new(())))
+    ///         })
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// The [`ServerTimingHandle`] is inserted into the request passed to
+    /// `call`.
+    pub fn wrap<B, C>(layer: &ServerTimingLayer, req: Request<B>, call: C) -> Self
+    where
+        C: FnOnce(Request<B>) -> F,
+    {
+        Self::start(&layer.config, req, call, false)
+    }
+
+    /// See [`ResponseFuture::wrap`], adding the [`TimingReport`] to the
+    /// response extensions instead of the header if `report`.
+    fn start<B, C>(config: &SharedConfig, mut req: Request<B>, call: C, report: bool) -> Self
+    where
+        C: FnOnce(Request<B>) -> F,
+    {
+        let config = config
+            .methods
+            .resolve(req.method(), config)
+            .unwrap_or(config);
+
+        let verbose;
+        let config = if cfg!(debug_assertions) && config.verbose_query && dev::requested(req.uri())
+        {
+            verbose = SharedConfig::Arc(Arc::new(dev::verbose(config)));
+            &verbose
+        } else {
+            config
+        };
+
+        let exchange =
+            Exchange::request(req.method(), req.uri(), req.headers()).with_route(route(&req));
+        let decided = config.policies.decide(&exchange);
+        // Evaluated again with the response.
+        let exchange = (decided.is_none() || config.on_timing_sampling.is_some())
+            .then(|| Parts::capture(&exchange));
+
+        let enabled = match &config.flag {
+            _ if config.methods.skips(req.method())
+                || decided == Some(false)
+                || req.extensions().get() == Some(&FlagEnabled(false)) =>
+            {
+                false
+            }
+            Some(flag) => {
+                let enabled;
+                (req, enabled) = flag.enabled(req);
+                enabled
+            }
+            None => true,
+        };
+
+        let depth = req.extensions().get::<Depth>().map_or(0, |depth| depth.0) + 1;
+        let handle = if !enabled || (config.nested == NestedPolicy::Collapse && depth > 1) {
+            // Disabled, or the outer instance takes care of the header.
+            None
+        } else {
+            let handle = ServerTimingHandle::new(Instant::now());
+            req.extensions_mut().insert(handle.clone());
+            req.extensions_mut().insert(Depth(depth));
+            Some(handle)
+        };
+
+        let mut app = match &config.app_fn {
+            Some(resolver) if handle.is_some() => {
+                let app;
+                (req, app) = resolver.resolve(req);
+                Some(app)
+            }
+            _ => None,
+        };
+
+        if let Some(renamed) = config
+            .nested
+            .rename(app.as_deref().unwrap_or(&config.app), depth)
+        {
+            app = Some(renamed.into());
+        }
+
+        let tenant = match &config.tenant {
+            Some(extractor) if handle.is_some() => {
+                let tenant;
+                (req, tenant) = extractor.extract(req);
+                tenant
+            }
+            _ => None,
+        };
+
+        let mut request = (handle.is_some()
+            && (report || config.on_timing.is_some() || config.on_error.is_some()))
+        .then(|| RequestMeta::capture(&req, &config.report_headers));
+
+        if let (Some(request), Some(enrich)) = (&mut request, &config.enrich) {
+            req = request.enrich(req, enrich);
+        }
+
+        let deferred = match (&config.async_metrics, &handle) {
+            (Some(async_metrics), Some(handle)) => {
+                let deferred;
+                (req, deferred) = async_metrics.start(req, handle.start());
+                Some(deferred)
+            }
+            _ => None,
+        };
+
+        let protocol = config.protocol.then(|| protocol(req.version())).flatten();
+        let matched = matched(&req);
+        let trailers = trailers(&req);
+        let exchange = exchange.filter(|_| handle.is_some());
+
+        ResponseFuture {
+            inner: call(req),
+            handle,
+            request,
+            app,
+            tenant,
+            protocol,
+            polled: None,
+            deferred,
+            report,
+            exchange,
+            matched,
+            trailers,
+            config: config.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// The default max number of characters of the descriptions of custom
+/// metrics.
+const DEFAULT_MAX_DESCRIPTION: usize = 100;
+
+#[cfg(feature = "feat-layer")]
+/// The `Timing-Allow-Origin` header name.
+const TIMING_ALLOW_ORIGIN: HeaderName = HeaderName::from_static("timing-allow-origin");
+
+#[cfg(feature = "feat-layer")]
+/// The process-wide default options, see [`ServerTimingLayer::init_default`].
+static DEFAULT_LAYER: OnceLock<ServerTimingLayer> = OnceLock::new();
+
+#[cfg(feature = "feat-layer")]
+/// The per-process counter behind the `seq` param.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "feat-layer")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The total duration measured by [`ServerTimingService`], inserted into the
+/// response extensions so that outer layers, e.g. logging, can get it without
+/// parsing the header.
+///
+/// It's always inserted, even when the header itself is skipped.
+pub struct ServerTimingDuration(pub Duration);
+
+#[cfg(feature = "feat-layer")]
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if this.config.phases && this.handle.is_some() && this.polled.is_none() {
+            *this.polled = Some(Instant::now());
+        }
+
+        if let Some(deferred) = this.deferred.as_mut() {
+            deferred.poll(cx);
+        }
+
+        #[cfg(feature = "feat-alloc")]
+        let result = {
+            let counter = this.config.alloc_counter.filter(|_| this.handle.is_some());
+            let before = counter.map(|counter| counter());
+            let poll = if this.config.catch_panic {
+                poll_catching(this.inner, cx)
+            } else {
+                this.inner.poll(cx)
+            };
+            if let (Some(counter), Some(before), Some(handle)) = (counter, before, &this.handle) {
+                handle.allocate(counter().saturating_sub(before));
+            }
+            ready!(poll)
+        };
+        #[cfg(not(feature = "feat-alloc"))]
+        let result = ready!(if this.config.catch_panic {
+            poll_catching(this.inner, cx)
+        } else {
+            this.inner.poll(cx)
+        });
+
+        let mut response: Response<B> = match result {
+            Ok(response) => response,
+            Err(e) => {
+                if let Some(handle) = this.handle {
+                    let app = this.app.take().unwrap_or_else(|| this.config.app.clone());
+                    report_failure(this.config, app, handle, this.request.take());
+                }
+
+                return Poll::Ready(Err(e));
+            }
+        };
+
+        let Some(handle) = this.handle else {
+            return Poll::Ready(Ok(response));
+        };
+
+        let config = this.config;
+        let app = this.app.as_deref().unwrap_or(&config.app);
+        let elapsed = handle.start().elapsed();
+
+        response
+            .extensions_mut()
+            .insert(ServerTimingDuration(elapsed));
+
+        if let Some(aggregator) = &config.percentile {
+            aggregator.record(elapsed);
+        }
+
+        let status = response.status();
+        let exchange = this.exchange.as_ref().map(|parts| {
+            parts
+                .exchange()
+                .with_tenant(this.tenant.as_deref())
+                .with_response(status, response.headers(), elapsed)
+        });
+        let header = !*this.report
+            && (config.not_found || *this.matched || status != StatusCode::NOT_FOUND)
+            && exchange.as_ref().map_or(true, |exchange| {
+                config.policies.decide(exchange) != Some(false)
+            });
+        let timing = config.on_timing.is_some()
+            && config
+                .on_timing_sampling
+                .as_ref()
+                .zip(exchange.as_ref())
+                .map_or(header, |(sampling, exchange)| {
+                    sampling.decide(exchange) != Some(false)
+                });
+
+        if !header && !timing && !*this.report {
+            return Poll::Ready(Ok(response));
+        }
+
+        let mut metrics = handle.take_metrics();
+        if let Some(deferred) = this.deferred.as_mut() {
+            deferred.poll(cx);
+            metrics.extend(deferred.take());
+        }
+        let mut metrics = rollup(metrics);
+
+        let cache = response.extensions().get::<CacheStatus>().copied();
+
+        if let Some(polled) = *this.polled {
+            let route = polled.saturating_duration_since(handle.start());
+            metrics.insert(0, TimingMetric::new("route").with_duration(route));
+
+            // The handler didn't run for responses served from a cache.
+            if cache != Some(CacheStatus::Hit) {
+                let handler = TimingMetric::new("handler")
+                    .with_start(route)
+                    .with_duration(polled.elapsed());
+                metrics.insert(1, handler);
+            }
+        }
+
+        if let Some(cache) = cache {
+            metrics.push(cache.marker());
+        }
+
+        if response.extensions().get::<Panicked>().is_some() {
+            metrics.push(Panicked::marker());
+        }
+
+        if let Some(redact) = &config.redact {
+            metrics = redact.apply(metrics);
+        }
+
+        if let Some(visit_mut) = &config.visit_mut {
+            visit_mut.apply(&mut metrics);
+        }
+
+        if header {
+            #[cfg(feature = "feat-trailer")]
+            if *this.trailers {
+                response.extensions_mut().insert(trailer::TrailerSupport);
+            }
+
+            add_header(
+                &mut response,
+                config,
+                app,
+                *this.protocol,
+                handle,
+                elapsed,
+                &metrics,
+            );
+        }
+
+        if timing || *this.report {
+            let app = this.app.clone().unwrap_or_else(|| config.app.clone());
+            let mut entry = TimingMetric::new(app).with_duration(elapsed);
+            if let Some(description) = &config.description {
+                entry = entry.with_description(description.clone());
+            }
+
+            let mut report = std::iter::once(entry)
+                .chain(metrics)
+                .collect::<TimingReport>()
+                .with_status(status);
+            if let Some(request) = this.request.take() {
+                report = request.apply(report);
+            }
+            if let Some(tenant) = this.tenant.take() {
+                report = report.with_tenant(tenant);
+            }
+
+            if let Some(on_timing) = config.on_timing.as_ref().filter(|_| timing) {
+                on_timing.call(&report);
+            }
+
+            if *this.report {
+                response.extensions_mut().insert(report);
+            }
+        }
+
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Reports the failure of the inner service, which leaves no response to add
+/// the header to.
+fn report_failure(
+    config: &Config,
+    app: Cow<'static, str>,
+    handle: &ServerTimingHandle,
+    request: Option<RequestMeta>,
+) {
+    let elapsed = handle.start().elapsed();
+
+    #[cfg(feature = "feat-tracing")]
+    tracing::warn!("Inner service failed after {elapsed:?}, no `server-timing` header");
+
+    let Some(on_error) = &config.on_error else {
+        return;
+    };
+
+    let mut metrics = rollup(handle.take_metrics());
+    if let Some(redact) = &config.redact {
+        metrics = redact.apply(metrics);
+    }
+
+    if let Some(visit_mut) = &config.visit_mut {
+        visit_mut.apply(&mut metrics);
+    }
+
+    let mut report = std::iter::once(TimingMetric::new(app).with_duration(elapsed))
+        .chain(metrics)
+        .collect::<TimingReport>();
+    if let Some(request) = request {
+        report = request.apply(report);
+    }
+
+    on_error.call(&ServerTimingError::ServiceFailed(Box::new(report)));
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns the route of the request, i.e. the path matched by the router if
+/// known, or the request path.
+fn route<B>(req: &Request<B>) -> &str {
+    #[cfg(feature = "feat-router")]
+    if let Some(path) = req.extensions().get::<axum::extract::MatchedPath>() {
+        return path.as_str();
+    }
+
+    req.uri().path()
+}
+
+#[cfg(feature = "feat-router")]
+/// Returns whether the router matched a route for the request.
+fn matched<B>(req: &Request<B>) -> bool {
+    req.extensions()
+        .get::<axum::extract::MatchedPath>()
+        .is_some()
+}
+
+#[cfg(all(feature = "feat-layer", not(feature = "feat-router")))]
+/// Returns whether the router matched a route for the request, never known
+/// without `feat-router`.
+const fn matched<B>(_req: &Request<B>) -> bool {
+    false
+}
+
+#[cfg(feature = "feat-trailer")]
+/// Returns whether the response body gets wrapped by the
+/// [`TrailerLayer`], so that the header can be emitted as a trailer.
+fn trailers<B>(req: &Request<B>) -> bool {
+    req.extensions().get::<trailer::TrailerSupport>().is_some()
+}
+
+#[cfg(all(feature = "feat-layer", not(feature = "feat-trailer")))]
+/// Returns whether the response body gets wrapped so that the header can be
+/// emitted as a trailer, never without `feat-trailer`.
+const fn trailers<B>(_req: &Request<B>) -> bool {
+    false
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns the milliseconds elapsed since the Unix epoch at the given time, 0
+/// if before.
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| {
+            u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns the name and id of the current thread, e.g.
+/// `tokio-runtime-worker#7`.
+fn current_thread() -> String {
+    let thread = std::thread::current();
+
+    // `ThreadId::as_u64` is unstable, but its `Debug` output is `ThreadId(7)`.
+    let id = format!("{:?}", thread.id());
+    let id = id.trim_start_matches("ThreadId(").trim_end_matches(')');
+
+    format!("{}#{id}", thread.name().unwrap_or("unnamed"))
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns the ALPN identifier of the given HTTP version.
+fn protocol(version: Version) -> Option<&'static str> {
+    match version {
+        Version::HTTP_09 => Some("http/0.9"),
+        Version::HTTP_10 => Some("http/1.0"),
+        Version::HTTP_11 => Some("http/1.1"),
+        Version::HTTP_2 => Some("h2"),
+        Version::HTTP_3 => Some("h3"),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Builds the header value and adds it to the response.
+fn add_header<B: Default>(
+    response: &mut Response<B>,
+    config: &Config,
+    app: &str,
+    protocol: Option<&str>,
+    handle: &ServerTimingHandle,
+    elapsed: Duration,
+    metrics: &[TimingMetric],
+) {
+    if let Some(mode) = config.upstream_parsing {
+        check_upstream(response.headers_mut(), mode, config.on_error.as_ref());
+    }
+
+    let resolved = config.duplicates.resolve(response.headers_mut(), app);
+    let clamped = report_clamped(config, metrics);
+
+    let buf = if config.scratch_buffer {
+        scratch::take()
+    } else {
+        Vec::new()
+    };
+
+    let mut value = Entry {
+        app: resolved.rename.unwrap_or(app),
+        description: config.description.as_deref(),
+        elapsed: elapsed.saturating_add(resolved.extra),
+        precision: config.precision,
+        order: config.param_order,
+        non_ascii: config.non_ascii,
+        clamped: if config.clamp_marker { clamped } else { 0 },
+        attempts: handle.attempts(),
+        timeout: config.timeout_marker
+            && matches!(
+                response.status(),
+                StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT
+            ),
+        seq: config
+            .sequence
+            .then(|| SEQUENCE.fetch_add(1, Ordering::Relaxed)),
+        end: config.end_timestamp.then(|| unix_millis(SystemTime::now())),
+        pct: config
+            .percentile
+            .as_ref()
+            .and_then(|aggregator| aggregator.rank(elapsed)),
+        proto: protocol,
+        thread: config.thread.then(current_thread),
+        #[cfg(feature = "feat-alloc")]
+        alloc: config.alloc_counter.map(|_| handle.allocated()),
+    }
+    .encode(buf);
+
+    if let Some(params) = &config.params {
+        params.encode(&mut value, response);
+    }
+
+    let entry_len = value.len();
+    encode_metrics(&mut value, config, response.headers(), elapsed, metrics);
+
+    if fits(response.headers(), config, &mut value, entry_len) {
+        add_value(response, config, &mut value);
+    }
+
+    if config.scratch_buffer {
+        scratch::put(value);
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns the number of custom metrics with a negative duration or start
+/// offset, reporting them.
+fn report_clamped(config: &Config, metrics: &[TimingMetric]) -> usize {
+    let clamped = metrics.iter().filter(|metric| metric.is_negative()).count();
+    if clamped > 0 {
+        #[cfg(feature = "feat-tracing")]
+        tracing::warn!("{clamped} negative metric durations clamped to 0");
+
+        if let Some(on_error) = &config.on_error {
+            on_error.call(&ServerTimingError::ClampedDurations(clamped));
+        }
+    }
+
+    clamped
+}
+
+#[cfg(feature = "feat-layer")]
+/// Encodes the entries following the one of the service: the version and
+/// host info, the custom metrics and the cache markers.
+fn encode_metrics(
+    value: &mut Vec<u8>,
+    config: &Config,
+    headers: &HeaderMap,
+    elapsed: Duration,
+    metrics: &[TimingMetric],
+) {
+    if let Some(version) = &config.version {
+        value.extend_from_slice(b", ");
+        encode_info(value, "ver", version, config.non_ascii);
+    }
+
+    if let Some(host) = &config.host {
+        value.extend_from_slice(b", ");
+        encode_info(value, "host", host, config.non_ascii);
+    }
+
+    let style = Style {
+        with_start: config.start_offsets,
+        precision: config.precision,
+        order: config.param_order,
+        non_ascii: config.non_ascii,
+        max_description: config.max_description,
+        total: config.share.then_some(elapsed.as_secs_f64() * 1000.0),
+    };
+
+    for metric in metrics {
+        value.extend_from_slice(b", ");
+        metric.encode_styled(value, &style);
+    }
+
+    if config.cache_markers {
+        let style = Style {
+            with_start: false,
+            total: None,
+            ..style
+        };
+
+        for marker in cache_markers(headers) {
+            value.extend_from_slice(b", ");
+            marker.encode_styled(value, &style);
+        }
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns whether the given header value may be added, shrinking it to the
+/// entry of the service, of the given length, beyond the max header size.
+fn fits(headers: &HeaderMap, config: &Config, value: &mut Vec<u8>, entry_len: usize) -> bool {
+    let mut fits = true;
+    if let Some(max) = config.max_header_size {
+        let size = headers_size(headers, value.len(), config.append);
+        if size > max {
+            #[cfg(feature = "feat-tracing")]
+            tracing::warn!("Response headers would exceed {max} bytes, `server-timing` shrunk");
+
+            if let Some(on_error) = &config.on_error {
+                on_error.call(&ServerTimingError::HeaderTooLarge(size));
+            }
+
+            value.truncate(entry_len);
+            let size = headers_size(headers, entry_len, config.append);
+            fits = size <= max;
+            if !fits {
+                #[cfg(feature = "feat-tracing")]
+                tracing::warn!(
+                    "Response headers would exceed {max} bytes, `server-timing` skipped"
+                );
+
+                if let Some(on_error) = &config.on_error {
+                    on_error.call(&ServerTimingError::HeaderDropped(size));
+                }
+            }
+        }
+    }
+
+    if let (true, Some(mode)) = (fits, config.strict) {
+        fits = compliant(value, mode, config.on_error.as_ref());
+    }
+
+    fits
+}
+
+#[cfg(feature = "feat-layer")]
+/// Adds the serialized header value, along with `Timing-Allow-Origin`.
+fn add_value<B>(response: &mut Response<B>, config: &Config, value: &mut Vec<u8>) {
+    let mut result = insert_header(response.headers_mut(), value, config.append);
+    if matches!(result, Err(ServerTimingError::MaxSizeReached(_)))
+        && config.full.evict(response.headers_mut())
+    {
+        #[cfg(feature = "feat-tracing")]
+        tracing::warn!("Response headers full, header evicted for `server-timing`");
+
+        result = insert_header(response.headers_mut(), value, config.append);
+    }
+
+    #[cfg(feature = "feat-trailer")]
+    if matches!(result, Err(ServerTimingError::MaxSizeReached(_)))
+        && config.full == FullPolicy::Trailer
+        && response
+            .extensions_mut()
+            .remove::<trailer::TrailerSupport>()
+            .is_some()
+    {
+        result = HeaderValue::from_bytes(value)
+            .map(|value| {
+                response
+                    .extensions_mut()
+                    .insert(trailer::PendingTrailer(value));
+            })
+            .map_err(ServerTimingError::from);
+    }
+
+    match result {
+        Ok(()) => {
+            if let Some(origin) = &config.timing_allow_origin {
+                let entry = response.headers_mut().try_entry(TIMING_ALLOW_ORIGIN);
+                if let Ok(http::header::Entry::Vacant(entry)) = entry {
+                    let _ = entry.try_insert(origin.clone());
+                }
+            }
+        }
+        Err(e) => {
+            #[cfg(feature = "feat-tracing")]
+            tracing::error!("Failed to add `server-timing` header: {e:?}");
+
+            if let Some(on_error) = &config.on_error {
+                on_error.call(&e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns the size of the headers once a value of the given length is added
+/// to the `Server-Timing` header, counted as `name: value\r\n`.
+fn headers_size(headers: &HeaderMap, len: usize, append: bool) -> usize {
+    let size: usize = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum();
+
+    if append || !headers.contains_key(SERVER_TIMING) {
+        size + SERVER_TIMING.as_str().len() + len + 4
+    } else {
+        // Merged into the existing value.
+        size + len + 2
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Adds the formatted entry to the `Server-Timing` header.
+pub(crate) fn insert_header(
+    headers: &mut HeaderMap,
+    value: &mut Vec<u8>,
+    append: bool,
+) -> Result<(), ServerTimingError> {
+    if append {
+        headers.try_append(SERVER_TIMING, HeaderValue::from_bytes(value)?)?;
+    } else {
+        // Merge on bytes: upstream values may contain opaque bytes which are not
+        // valid UTF-8 but must be kept as is.
+        for upstream in headers.get_all(SERVER_TIMING) {
+            value.extend_from_slice(b", ");
+            value.extend_from_slice(upstream.as_bytes());
+        }
+
+        headers.try_insert(SERVER_TIMING, HeaderValue::from_bytes(value)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "feat-layer")]
+/// Returns whether the given header value complies with the spec, reporting
+/// it otherwise, or panicking with [`StrictMode::Panic`].
+fn compliant(value: &[u8], mode: StrictMode, on_error: Option<&OnError>) -> bool {
+    let Err(e) = parse_header(value) else {
+        return true;
+    };
+
+    assert!(
+        mode != StrictMode::Panic,
+        "Non-compliant `server-timing` header value {:?}: {e}",
+        String::from_utf8_lossy(value)
+    );
+
+    #[cfg(feature = "feat-tracing")]
+    tracing::error!("Non-compliant `server-timing` header value, skipped: {e}");
+
+    if let Some(on_error) = on_error {
+        on_error.call(&ServerTimingError::NonCompliant(e));
+    }
+
+    false
+}
+
+#[cfg(feature = "feat-layer")]
+/// Drops the malformed entries of upstream headers, or the whole values in
+/// strict mode, reporting them.
+fn check_upstream(headers: &mut HeaderMap, mode: ParseMode, on_error: Option<&OnError>) {
+    if !headers.contains_key(SERVER_TIMING) {
+        return;
+    }
+
+    let upstream: Vec<_> = headers.get_all(SERVER_TIMING).iter().cloned().collect();
+    headers.remove(SERVER_TIMING);
+
+    for value in upstream {
+        let bytes = value.as_bytes();
+        let mut kept = Vec::with_capacity(bytes.len());
+        let mut malformed = false;
+
+        for entry in Entries::new(bytes, mode) {
+            match entry {
+                Ok((_, range)) => {
+                    if !kept.is_empty() {
+                        kept.extend_from_slice(b", ");
+                    }
+                    kept.extend_from_slice(&bytes[range]);
+                }
+                Err(e) => {
+                    malformed = true;
+
+                    #[cfg(feature = "feat-tracing")]
+                    tracing::warn!("Malformed upstream `server-timing` header: {e}");
+
+                    if let Some(on_error) = on_error {
+                        on_error.call(&ServerTimingError::MalformedUpstream(e));
+                    }
+                }
+            }
+        }
+
+        let value = match mode {
+            _ if !malformed => value,
+            ParseMode::Lenient if !kept.is_empty() => match HeaderValue::from_bytes(&kept) {
+                Ok(value) => value,
+                Err(_) => continue,
+            },
+            ParseMode::Lenient | ParseMode::Strict => continue,
+        };
+
+        // Room was made by removing the values.
+        let _ = headers.try_append(SERVER_TIMING, value);
+    }
+}
+
+#[cfg(feature = "feat-layer")]
+/// Appends an informational entry, e.g. `ver;desc="1.4.2"`.
+fn encode_info(buf: &mut Vec<u8>, name: &str, desc: &str, non_ascii: NonAsciiPolicy) {
+    buf.extend_from_slice(name.as_bytes());
+    buf.extend_from_slice(b";desc=");
+    push_description(buf, desc, non_ascii);
+}
+
+#[cfg(feature = "feat-layer")]
+/// The entry of the service, e.g.
+/// `app;desc="description";dur=12.3;attempts=2;timeout=1;seq=42;pct=99`.
+struct Entry<'a> {
+    /// The service name.
+    app: &'a str,
+
+    /// An optional description of the service.
+    description: Option<&'a str>,
+
+    /// The elapsed time of the request.
+    elapsed: Duration,
+
+    /// The number of decimal places of `dur`.
+    precision: u8,
+
+    /// The order of `desc` and `dur`.
+    order: ParamOrder,
+
+    /// What happens to the non-ASCII characters of the description.
+    non_ascii: NonAsciiPolicy,
+
+    /// How many times the request has been dispatched, omitted if 0.
+    attempts: u32,
+
+    /// Whether the response indicates a timeout.
+    timeout: bool,
+
+    /// The sequence number, if enabled.
+    seq: Option<u64>,
+
+    /// The milliseconds since the Unix epoch the header is added at, if
+    /// enabled.
+    end: Option<u64>,
+
+    /// The percentile rank among recent requests, if enabled.
+    pct: Option<u8>,
+
+    /// The number of clamped metrics, omitted if 0.
+    clamped: usize,
+
+    /// The HTTP version, if enabled.
+    proto: Option<&'a str>,
+
+    /// The current thread, if enabled.
+    thread: Option<String>,
+
+    /// The bytes allocated while processing the request, if enabled.
+    #[cfg(feature = "feat-alloc")]
+    alloc: Option<u64>,
+}
+
+#[cfg(feature = "feat-layer")]
+impl Entry<'_> {
+    /// Formats the entry into the given empty buffer.
+    fn encode(&self, mut buf: Vec<u8>) -> Vec<u8> {
+        buf.reserve(self.app.len() + self.description.map_or(0, |d| d.len() + 8) + 16);
+
+        buf.extend_from_slice(self.app.as_bytes());
+
+        let description = |buf: &mut Vec<u8>| match (self.description, self.non_ascii) {
+            // Kept as is, for invalid values to be reported.
+            (Some(description), NonAsciiPolicy::Keep) => {
+                buf.extend_from_slice(b";desc=\"");
+                buf.extend_from_slice(description.as_bytes());
+                buf.push(b'"');
+            }
+            (Some(description), non_ascii) => {
+                buf.extend_from_slice(b";desc=");
+                push_description(buf, description, non_ascii);
+            }
+            (None, _) => {}
+        };
+
+        if self.order == ParamOrder::DescFirst {
+            description(&mut buf);
+        }
+
+        buf.extend_from_slice(b";dur=");
+        Millis::from_duration(self.elapsed, self.precision).encode(&mut buf);
+
+        if self.order == ParamOrder::DurFirst {
+            description(&mut buf);
+        }
+
+        if self.attempts > 0 {
+            // Writing to a `Vec` never fails.
+            let _ = write!(buf, ";attempts={}", self.attempts);
+        }
+
+        if self.timeout {
+            buf.extend_from_slice(b";timeout=1");
+        }
+
+        if let Some(seq) = self.seq {
+            let _ = write!(buf, ";seq={seq}");
+        }
+
+        if let Some(end) = self.end {
+            let _ = write!(buf, ";end={end}");
+        }
+
+        if let Some(pct) = self.pct {
+            let _ = write!(buf, ";pct={pct}");
+        }
+
+        if self.clamped > 0 {
+            let _ = write!(buf, ";clamped={}", self.clamped);
+        }
+
+        if let Some(proto) = self.proto {
+            push_param(&mut buf, "proto", proto.as_bytes());
+        }
+
+        if let Some(thread) = &self.thread {
+            push_param(&mut buf, "thread", thread.as_bytes());
+        }
+
+        #[cfg(feature = "feat-alloc")]
+        if let Some(alloc) = self.alloc {
+            allocation::encode(&mut buf, alloc);
+        }
+
+        buf
+    }
+}
+
+/// Compile-time guarantees on auto traits, e.g. axum requires the futures of
+/// middlewares to be `Send`, and fails with confusing bounds errors otherwise.
+#[cfg(feature = "feat-layer")]
+const _: () = {
+    /// A future like the ones of inner services.
+    type Inner = std::future::Ready<Result<Response<()>, std::convert::Infallible>>;
+
+    const fn send_sync_unpin<T: Send + Sync + Unpin + 'static>() {}
+
+    send_sync_unpin::<ServerTimingLayer>();
+    send_sync_unpin::<Config>();
+    send_sync_unpin::<ServerTimingService<()>>();
+    send_sync_unpin::<ResponseFuture<Inner>>();
+    send_sync_unpin::<ReportLayer>();
+    send_sync_unpin::<ReportService<()>>();
+    send_sync_unpin::<ReportFuture<Inner>>();
+    send_sync_unpin::<AsyncFlagLayer>();
+    send_sync_unpin::<AsyncFlagService<()>>();
+    send_sync_unpin::<ServerTimingHandle>();
+    send_sync_unpin::<ServerTimingDuration>();
+    send_sync_unpin::<ServerTimingError>();
+    send_sync_unpin::<InvalidName>();
+    send_sync_unpin::<ServerTimingName>();
+    send_sync_unpin::<TimingMetric>();
+    send_sync_unpin::<TimingReport>();
+    send_sync_unpin::<TimingDiff>();
+    send_sync_unpin::<Aggregator>();
+    send_sync_unpin::<Sampling>();
+    send_sync_unpin::<HeaderRateLimit>();
+    send_sync_unpin::<DuplicatePolicy>();
+    send_sync_unpin::<FullPolicy>();
+    send_sync_unpin::<MethodPolicy>();
+    send_sync_unpin::<NestedPolicy>();
+    send_sync_unpin::<Preset>();
+    send_sync_unpin::<ParamOrder>();
+    send_sync_unpin::<NonAsciiPolicy>();
+    send_sync_unpin::<CacheStatus>();
+    send_sync_unpin::<Panicked>();
+    send_sync_unpin::<export::BatchExporter>();
+    #[cfg(feature = "feat-testing")]
+    send_sync_unpin::<testing::MockUpstream>();
+    #[cfg(feature = "feat-tracing-subscriber")]
+    send_sync_unpin::<subscriber::SpanTimingLayer>();
+    #[cfg(feature = "feat-trailer")]
+    send_sync_unpin::<TrailerLayer>();
+    #[cfg(feature = "feat-trailer")]
+    send_sync_unpin::<TrailerService<()>>();
+    #[cfg(feature = "feat-trailer")]
+    send_sync_unpin::<TrailerFuture<Inner>>();
+    #[cfg(feature = "feat-trailer")]
+    send_sync_unpin::<TrailerBody<String>>();
+};
+
+#[cfg(all(test, feature = "feat-layer"))]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::Future,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use axum::{body::Body, routing::get, Extension, Router};
+    use http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+    use tower::{service_fn, timeout::error::Elapsed, BoxError, ServiceBuilder, ServiceExt};
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use super::{
+        add_header, export::BatchConfig, parse::ParseMode, Aggregator, AsyncFlagLayer, CacheStatus,
+        Config, DuplicatePolicy, Entry, FullPolicy, HeaderRateLimit, MethodPolicy, NestedPolicy,
+        NonAsciiPolicy, Panicked, ParamOrder, Preset, Sampling, ServerTimingDuration,
+        ServerTimingError, ServerTimingHandle, ServerTimingLayer, StrictMode, TimingMetric,
+        TimingReport,
+    };
+
+    #[test]
+    fn service_name() {
+        let name = "svc1";
+        let obj = ServerTimingLayer::new(name);
+        assert_eq!(obj.config.app, name);
+    }
+
+    #[test]
+    fn service_try_new() {
+        let obj = ServerTimingLayer::try_new(String::from("svc1")).unwrap();
+        assert_eq!(obj.config.app, "svc1");
+
+        for name in ["", "my svc", "svc;1", "svc\"1\""] {
+            let e = ServerTimingLayer::try_new(name).unwrap_err();
+            assert_eq!(e.name(), name);
+        }
+    }
+
+    #[test]
+    fn service_shared_config() {
+        let obj = ServerTimingLayer::new("svc1").with_sequence(true);
+        let shared = ServerTimingLayer::from_config(obj.config());
+        assert!(Arc::ptr_eq(&obj.config(), &shared.config()));
+
+        let service = shared.layer(());
+        assert!(std::ptr::eq(&*obj.config, &*service.config));
+
+        let other = shared.with_description("desc");
+        assert!(!Arc::ptr_eq(&obj.config(), &other.config()));
+        assert_eq!(obj.config.description, None);
+        assert_eq!(other.config.description.as_deref(), Some("desc"));
+        assert!(other.config.sequence);
+    }
+
+    #[test]
+    fn service_static_config() {
+        static CONFIG: Config = Config::described("svc1", "desc")
+            .with_sequence(true)
+            .with_precision(3);
+        static LAYER: ServerTimingLayer = ServerTimingLayer::static_config(&CONFIG);
+
+        let service = LAYER.layer(());
+        assert!(std::ptr::eq(&*service.config, &CONFIG));
+        assert_eq!(service.config.app, "svc1");
+        assert_eq!(service.config.description.as_deref(), Some("desc"));
+        assert!(service.config.sequence);
+
+        // Copied on write.
+        let obj = LAYER.clone().with_append(true);
+        assert!(obj.config.append);
+        assert!(!CONFIG.append);
+        assert_eq!(obj.config.precision, 3);
+    }
+
+    #[test]
+    fn service_from_default() {
+        // The only test setting the process-wide default.
+        let obj = ServerTimingLayer::from_default("svc1");
+        assert_eq!(obj.config.app, "svc1");
+        assert!(!obj.config.timeout_marker);
+        assert!(obj.config.not_found);
+
+        assert!(ServerTimingLayer::new("ignored")
+            .with_sequence(true)
+            .init_default());
+        assert!(!ServerTimingLayer::new("ignored").init_default());
+
+        let obj = ServerTimingLayer::from_default("svc2");
+        assert_eq!(obj.config.app, "svc2");
+        assert!(obj.config.sequence);
+        assert!(!obj.config.timeout_marker);
+    }
+
+    #[test]
+    fn service_desc() {
+        let name = "svc1";
+        let desc = "desc1";
+        let obj = ServerTimingLayer::new(name).with_description(desc);
+        assert_eq!(obj.config.app, name);
+        assert_eq!(obj.config.description.as_deref(), Some(desc));
+    }
+
+    #[test]
+    fn service_min_duration() {
+        let obj = ServerTimingLayer::new("svc1").with_min_duration(Duration::from_millis(5));
+        assert_eq!(obj.config.policies.min_duration.0, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn service_append() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.append);
+        let obj = obj.with_append(true);
+        assert!(obj.config.append);
+    }
+
+    #[test]
+    fn service_timeout_marker() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.timeout_marker);
+        let obj = obj.with_timeout_marker(true);
+        assert!(obj.config.timeout_marker);
+    }
+
+    #[test]
+    fn service_collapse_nested() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert_eq!(obj.config.nested, NestedPolicy::Keep);
+        let obj = obj.with_collapse_nested(true);
+        assert_eq!(obj.config.nested, NestedPolicy::Collapse);
+        let obj = obj.with_nested_policy(NestedPolicy::Rename);
+        assert_eq!(obj.config.nested, NestedPolicy::Rename);
+    }
+
+    #[test]
+    fn service_not_found() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.not_found);
+        let obj = obj.with_not_found(false);
+        assert!(!obj.config.not_found);
+    }
+
+    #[test]
+    fn service_sequence() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.sequence);
+        let obj = obj.with_sequence(true);
+        assert!(obj.config.sequence);
+    }
+
+    #[test]
+    fn service_end_timestamp() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.end_timestamp);
+        let obj = obj.with_end_timestamp(true);
+        assert!(obj.config.end_timestamp);
+    }
+
+    #[test]
+    fn unix_millis() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_micros(1_712_345_679_042_500);
+        assert_eq!(super::unix_millis(time), 1_712_345_679_042);
+        let time = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(super::unix_millis(time), 0);
+    }
+
+    #[test]
+    fn service_start_offsets() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.start_offsets);
+        let obj = obj.with_start_offsets(true);
+        assert!(obj.config.start_offsets);
+    }
+
+    #[test]
+    fn service_share() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.share);
+        let obj = obj.with_share(true);
+        assert!(obj.config.share);
+    }
+
+    #[test]
+    fn service_upstream_parsing() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.upstream_parsing.is_none());
+        let obj = obj.with_upstream_parsing(ParseMode::Lenient);
+        assert_eq!(obj.config.upstream_parsing, Some(ParseMode::Lenient));
+    }
+
+    #[test]
+    fn service_duplicate_policy() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert_eq!(obj.config.duplicates, DuplicatePolicy::KeepBoth);
+        let obj = obj.with_duplicate_policy(DuplicatePolicy::Sum);
+        assert_eq!(obj.config.duplicates, DuplicatePolicy::Sum);
+    }
+
+    #[test]
+    fn service_full_policy() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert_eq!(obj.config.full, FullPolicy::Report);
+        let name = http::HeaderName::from_static("x-debug");
+        let obj = obj.with_full_policy(FullPolicy::Evict(name.clone()));
+        assert_eq!(obj.config.full, FullPolicy::Evict(name));
+    }
+
+    #[test]
+    fn service_precision() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert_eq!(obj.config.precision, 1);
+        let obj = obj.with_precision(3);
+        assert_eq!(obj.config.precision, 3);
+    }
+
+    #[test]
+    fn service_clamp_marker() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.clamp_marker);
+        let obj = obj.with_clamp_marker(true);
+        assert!(obj.config.clamp_marker);
+    }
+
+    #[test]
+    fn service_param_fn() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.params.is_none());
+        let obj = obj.with_param_fn(|_| Vec::new());
+        assert!(obj.config.params.is_some());
+    }
+
+    #[test]
+    fn service_report_headers() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.report_headers.is_empty());
+        let obj = obj.with_report_headers([http::header::USER_AGENT]);
+        assert_eq!(obj.config.report_headers, [http::header::USER_AGENT]);
+    }
+
+    #[test]
+    fn service_enrichment() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.enrich.is_none());
+        let obj = obj.with_enrichment(|_| Vec::new());
+        assert!(obj.config.enrich.is_some());
+    }
+
+    #[test]
+    fn service_tenant() {
+        let obj = ServerTimingLayer::new("svc1").with_sampling(Sampling::new(1.0));
+        assert!(obj.config.tenant.is_none());
+        let obj = obj
+            .with_tenant(|_| None)
+            .with_tenant_sampling("acme", Sampling::new(0.0));
+        assert!(obj.config.tenant.is_some());
+        assert!(obj.config.policies.sampling.get(Some("acme")).is_some());
+        assert!(obj.config.policies.sampling.get(Some("other")).is_some());
+        assert_eq!(obj.config.policies.sampling.tenants.len(), 1);
+
+        let obj = obj.with_tenant_sampling("acme", Sampling::new(0.5));
+        assert_eq!(obj.config.policies.sampling.tenants.len(), 1);
+    }
+
+    #[test]
+    fn service_rate_limit() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.policies.rate_limit.is_none());
+        let obj = obj.with_rate_limit(HeaderRateLimit::per_route(10));
+        assert!(obj.config.policies.rate_limit.is_some());
+    }
+
+    #[test]
+    fn service_app_fn() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.app_fn.is_none());
+        let obj = obj.with_app_fn(|_| "svc2".into());
+        assert!(obj.config.app_fn.is_some());
+    }
+
+    #[test]
+    fn service_cache_markers() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.cache_markers);
+        let obj = obj.with_cache_markers(true);
+        assert!(obj.config.cache_markers);
+    }
+
+    #[test]
+    fn service_protocol() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.protocol);
+        let obj = obj.with_protocol(true);
+        assert!(obj.config.protocol);
+
+        assert_eq!(super::protocol(http::Version::HTTP_2), Some("h2"));
+        assert_eq!(super::protocol(http::Version::HTTP_11), Some("http/1.1"));
+    }
+
+    #[test]
+    fn service_max_header_size() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.max_header_size.is_none());
+        let obj = obj.with_max_header_size(8192);
+        assert_eq!(obj.config.max_header_size, Some(8192));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(super::headers_size(&headers, 10, false), 27);
+        headers.insert("server-timing", HeaderValue::from_static("db;dur=1"));
+        assert_eq!(super::headers_size(&headers, 10, false), 37);
+        assert_eq!(super::headers_size(&headers, 10, true), 52);
+    }
+
+    #[test]
+    fn service_phases() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.phases);
+        let obj = obj.with_phases(true);
+        assert!(obj.config.phases);
+    }
+
+    #[test]
+    fn service_scratch_buffer() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.scratch_buffer);
+        let obj = obj.with_scratch_buffer(false);
+        assert!(!obj.config.scratch_buffer);
+    }
+
+    #[test]
+    fn service_thread() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(!obj.config.thread);
+        let obj = obj.with_thread(true);
+        assert!(obj.config.thread);
+
+        let thread = std::thread::Builder::new()
+            .name("worker".to_owned())
+            .spawn(super::current_thread)
+            .unwrap()
+            .join()
+            .unwrap();
+        let id = thread.strip_prefix("worker#").unwrap();
+        assert!(id.parse::<u64>().is_ok(), "{thread}");
+    }
+
+    #[test]
+    fn service_version() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.version.is_none());
+        let obj = obj.with_version("1.4.2+abc123");
+        assert_eq!(obj.config.version.as_deref(), Some("1.4.2+abc123"));
+
+        let obj = ServerTimingLayer::new("svc1")
+            .with_version_from_env("MIKU_SERVER_TIMING_TEST_UNSET_VERSION");
+        assert!(obj.config.version.is_none());
+    }
+
+    #[test]
+    fn service_host() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.host.is_none());
+        let obj = obj.with_host("pod-7f9c");
+        assert_eq!(obj.config.host.as_deref(), Some("pod-7f9c"));
+    }
+
+    #[test]
+    fn service_percentile() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.percentile.is_none());
+        let obj = obj.with_percentile(Aggregator::new(Duration::from_secs(60)));
+        assert!(obj.config.percentile.is_some());
+    }
+
+    #[test]
+    fn service_sampling() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.policies.sampling.default.is_none());
+        let obj = obj.with_sampling(Sampling::new(0.1));
+        assert!(obj.config.policies.sampling.default.is_some());
+    }
+
+    #[test]
+    fn service_on_timing() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.on_timing.is_none());
+        let obj = obj.with_on_timing(|_| {});
+        assert!(obj.config.on_timing.is_some());
+    }
+
+    #[test]
+    fn service_on_timing_sampling() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.on_timing_sampling.is_none());
+        let obj = obj.with_on_timing_sampling(Sampling::new(0.1));
+        assert!(obj.config.on_timing_sampling.is_some());
+    }
+
+    #[test]
+    fn service_exporter() {
+        let exporter = BatchConfig::new().spawn(|_: &[TimingReport]| {}).unwrap();
+        let obj = ServerTimingLayer::new("svc1").with_exporter(exporter);
+        assert!(obj.config.on_timing.is_some());
+    }
+
+    #[test]
+    fn service_on_error() {
+        let obj = ServerTimingLayer::new("svc1");
+        assert!(obj.config.on_error.is_none());
+        let obj = obj.with_on_error(|_| {});
+        assert!(obj.config.on_error.is_some());
+    }
+
+    #[tokio::test]
+    async fn axum_test() {
+        let name = "svc1";
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    ""
+                }),
+            )
+            .layer(ServerTimingLayer::new(name));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
+
+        tokio::spawn(async move { axum::serve(listener, app.into_make_service()).await });
+
+        let _ = tokio::task::spawn_blocking(|| {
+            let headers = minreq::get("http://localhost:3001/")
+                .send()
+                .unwrap()
+                .headers;
+
+            let hdr = headers.get("server-timing");
+            assert!(
+                hdr.is_some(),
+                "Cannot find `server-timing` from: {headers:#?}"
+            );
+
+            let val: f32 = hdr.unwrap()[9..].parse().unwrap();
+            assert!(
+                (100f32..300f32).contains(&val),
+                "Invalid `server-timing` from: {headers:#?}"
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn support_existing_header() {
+        let name = "svc1";
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let mut hdr = HeaderMap::new();
+                    hdr.insert("server-timing", HeaderValue::from_static("inner;dur=23"));
+                    (hdr, "")
+                }),
+            )
+            .layer(ServerTimingLayer::new(name).with_description("desc1"));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3003").await.unwrap();
+        tokio::spawn(async { axum::serve(listener, app.into_make_service()).await });
+
+        let _ = tokio::task::spawn_blocking(|| {
+            let headers = minreq::get("http://localhost:3003/")
+                .send()
+                .unwrap()
+                .headers;
+
+            let hdr = headers.get("server-timing").unwrap();
+            assert!(hdr.contains("svc1"));
+            assert!(hdr.contains("inner"));
+            println!("{hdr}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn skip_fast_response() {
+        let name = "svc1";
+        let app = Router::new()
+            .route("/", get(|| async move { "" }))
+            .layer(ServerTimingLayer::new(name).with_min_duration(Duration::from_secs(10)));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3005").await.unwrap();
+        tokio::spawn(async { axum::serve(listener, app.into_make_service()).await });
+
+        let _ = tokio::task::spawn_blocking(|| {
+            let headers = minreq::get("http://localhost:3005/")
+                .send()
+                .unwrap()
+                .headers;
+
+            assert!(
+                !headers.contains_key("server-timing"),
+                "Unexpected `server-timing` from: {headers:#?}"
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn append_existing_header() {
+        let name = "svc1";
+        let mut app = Router::new()
+            .route(
+                "/",
+                get(|| async move {
+                    let mut hdr = HeaderMap::new();
+                    hdr.insert("server-timing", HeaderValue::from_static("inner;dur=23"));
+                    (hdr, "")
+                }),
+            )
+            .layer(ServerTimingLayer::new(name).with_append(true));
+
+        let response = app.call(Request::new(Body::empty())).await.unwrap();
+
+        let values: Vec<_> = response.headers().get_all("server-timing").iter().collect();
+        assert_eq!(values.len(), 2, "Invalid `server-timing` from: {values:#?}");
+        assert_eq!(values[0], "inner;dur=23");
+        assert!(values[1].to_str().unwrap().starts_with("svc1;dur="));
+    }
+
+    #[tokio::test]
+    async fn merge_opaque_header() {
+        let upstream = b"inner;desc=\"\xfa\xfb\";dur=23";
+
+        let name = "svc1";
+        let mut app = Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    let mut hdr = HeaderMap::new();
+                    hdr.insert("server-timing", HeaderValue::from_bytes(upstream).unwrap());
+                    (hdr, "")
+                }),
+            )
+            .layer(ServerTimingLayer::new(name));
+
+        let response = app.call(Request::new(Body::empty())).await.unwrap();
+
+        let values: Vec<_> = response.headers().get_all("server-timing").iter().collect();
+        assert_eq!(values.len(), 1, "Invalid `server-timing` from: {values:#?}");
+        assert!(values[0].as_bytes().starts_with(b"svc1;dur="));
+        assert!(values[0].as_bytes().ends_with(upstream));
+    }
+
+    #[tokio::test]
+    async fn merge_multiple_header() {
+        let name = "svc1";
+        let mut app = Router::new()
+            .route(
+                "/",
+                get(|| async move {
+                    let mut hdr = HeaderMap::new();
+                    hdr.append("server-timing", HeaderValue::from_static("db;dur=1"));
+                    hdr.append(
+                        "server-timing",
+                        HeaderValue::from_bytes(b"cache;desc=\"\xff\"").unwrap(),
+                    );
+                    (hdr, "")
+                }),
+            )
+            .layer(ServerTimingLayer::new(name));
+
+        let response = app.call(Request::new(Body::empty())).await.unwrap();
+
+        let values: Vec<_> = response.headers().get_all("server-timing").iter().collect();
+        assert_eq!(values.len(), 1, "Invalid `server-timing` from: {values:#?}");
+        assert!(values[0]
+            .as_bytes()
+            .ends_with(b", db;dur=1, cache;desc=\"\xff\""));
+    }
+
+    #[tokio::test]
+    async fn report_invalid_header_value() {
+        let errors = Arc::new(AtomicUsize::new(0));
+
+        let mut app = Router::new().route("/", get(|| async move { "" })).layer(
+            ServerTimingLayer::new("svc1")
+                .with_description("line\nbreak")
+                .with_on_error({
+                    let errors = errors.clone();
+                    move |e| {
+                        assert!(matches!(e, ServerTimingError::InvalidHeaderValue(_)));
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }),
+        );
+
+        let response = app.call(Request::new(Body::empty())).await.unwrap();
+
+        assert!(response.headers().get("server-timing").is_none());
+        assert_eq!(errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn decorate_tower_timeout() {
+        let service = ServiceBuilder::new()
+            .layer(ServerTimingLayer::new("svc1").with_timeout_marker(true))
+            // What an error handler, e.g. axum's `HandleErrorLayer`, does.
+            .map_result(|result: Result<Response<()>, BoxError>| match result {
+                Err(e) if e.is::<Elapsed>() => {
+                    let mut response = Response::new(());
+                    *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+                    Ok(response)
+                }
+                result => result,
+            })
+            .timeout(Duration::from_millis(10))
+            .service_fn(|_req: Request<()>| async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok::<_, BoxError>(Response::new(()))
+            });
+
+        let response = service.oneshot(Request::new(())).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            hdr.starts_with("svc1;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(
+            hdr.ends_with(";timeout=1"),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn decorate_synthesized_timeout() {
+        // What `tower_http::timeout` does.
+        let inner = service_fn(|_req: Request<()>| async move {
+            let mut response = Response::new(());
+            *response.status_mut() = StatusCode::REQUEST_TIMEOUT;
+            Ok::<_, Infallible>(response)
+        });
+
+        let response = ServerTimingLayer::new("svc1")
+            .layer(inner)
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(!hdr.contains("timeout"), "Invalid `server-timing`: {hdr}");
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_timeout_marker(true)
+            .layer(inner)
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            hdr.ends_with(";timeout=1"),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+
+    fn server_timing(response: &Response<Body>) -> &str {
+        response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn nested_router() {
+        let api = Router::new()
+            .route("/", get(|| async { "" }))
+            .layer(ServerTimingLayer::new("api"));
+        let app = Router::new()
+            .nest("/api", api)
+            .layer(ServerTimingLayer::new("app"));
+
+        let response = app
+            .oneshot(Request::get("/api").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        // The outermost entry comes first.
+        let hdr = server_timing(&response);
+        let (outer, inner) = hdr.split_once(", ").unwrap();
+        assert!(
+            outer.starts_with("app;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(
+            inner.starts_with("api;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_router_collapsed() {
+        let layer = ServerTimingLayer::new("svc1").with_collapse_nested(true);
+
+        let api = Router::new()
+            .route(
+                "/",
+                get(
+                    |Extension(timing): Extension<ServerTimingHandle>| async move {
+                        timing.record(TimingMetric::new("db").with_millis(1.0));
+                        ""
+                    },
+                ),
+            )
+            .layer(layer.clone());
+        let app = Router::new().nest("/api", api).layer(layer);
+
+        let response = app
+            .oneshot(Request::get("/api").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let hdr = server_timing(&response);
+        assert_eq!(
+            hdr.matches("svc1;").count(),
+            1,
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(
+            hdr.ends_with(", db;dur=1.0"),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn merged_router() {
+        let app = Router::new()
+            .route("/a", get(|| async { "" }))
+            .merge(
+                Router::new()
+                    .route("/b", get(|| async { "" }))
+                    .layer(ServerTimingLayer::new("b")),
+            )
+            .layer(ServerTimingLayer::new("app"));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/a").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let hdr = server_timing(&response);
+        assert!(
+            hdr.starts_with("app;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(!hdr.contains("b;"), "Invalid `server-timing`: {hdr}");
+
+        let response = app
+            .oneshot(Request::get("/b").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let hdr = server_timing(&response);
+        let (outer, inner) = hdr.split_once(", ").unwrap();
+        assert!(
+            outer.starts_with("app;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(
+            inner.starts_with("b;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_route() {
+        let app = Router::new()
+            .route("/", get(|| async { "" }))
+            .layer(ServerTimingLayer::new("app"));
+
+        let response = app
+            .oneshot(Request::get("/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(server_timing(&response).starts_with("app;dur="));
+    }
+
+    #[tokio::test]
+    async fn nested_fallback_route() {
+        let api = Router::new()
+            .route("/", get(|| async { "" }))
+            .layer(ServerTimingLayer::new("api"));
+        let app = Router::new()
+            .nest("/api", api)
+            .fallback(|| async { (StatusCode::NOT_FOUND, "missing") })
+            .layer(ServerTimingLayer::new("app"));
+
+        let response = app
+            .oneshot(Request::get("/api/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        // Unmatched paths below the nested router reach the outer fallback,
+        // without the layer of the nested router.
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let hdr = server_timing(&response);
+        assert!(
+            hdr.starts_with("app;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(!hdr.contains("api;"), "Invalid `server-timing`: {hdr}");
+    }
+
+    #[tokio::test]
+    async fn skip_fallback_route() {
+        let app = Router::new()
+            .route("/", get(|| async { "" }))
+            .layer(ServerTimingLayer::new("app").with_not_found(false));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(server_timing(&response).starts_with("app;dur="));
+
+        let response = app
+            .oneshot(Request::get("/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.headers().get("server-timing").is_none());
+    }
+
+    #[cfg(feature = "feat-router")]
+    #[tokio::test]
+    async fn keep_handler_not_found() {
+        let app = Router::new()
+            .route(
+                "/users/{id}",
+                get(|| async { (StatusCode::NOT_FOUND, "user not found") }),
+            )
+            .layer(ServerTimingLayer::new("app").with_not_found(false));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/users/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(server_timing(&response).starts_with("app;dur="));
+
+        let response = app
+            .oneshot(Request::get("/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!response.headers().contains_key("server-timing"));
+    }
+
+    #[tokio::test]
+    async fn expose_duration() {
+        let inner = service_fn(|_req: Request<()>| async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok::<_, Infallible>(Response::new(()))
+        });
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_min_duration(Duration::from_secs(10))
+            .layer(inner)
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        assert!(response.headers().get("server-timing").is_none());
+        let ServerTimingDuration(elapsed) = response.extensions().get().copied().unwrap();
+        assert!(elapsed >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn sequence_number() {
+        let inner =
+            service_fn(|_req: Request<()>| async move { Ok::<_, Infallible>(Response::new(())) });
+        let mut svc = ServerTimingLayer::new("svc1")
+            .with_sequence(true)
+            .layer(inner);
+
+        let mut seqs = Vec::new();
+        for _ in 0..2 {
+            let response = svc
+                .ready()
+                .await
+                .unwrap()
+                .call(Request::new(()))
+                .await
+                .unwrap();
+            let hdr = response.headers()["server-timing"].to_str().unwrap();
+            let (_, seq) = hdr.split_once(";seq=").unwrap();
+            seqs.push(seq.parse::<u64>().unwrap());
+        }
+
+        assert!(seqs[0] < seqs[1]);
+    }
+
+    #[tokio::test]
+    async fn end_timestamp() {
+        let before = super::unix_millis(SystemTime::now());
+        let response = ServerTimingLayer::new("svc1")
+            .with_end_timestamp(true)
+            .layer(service_fn(|_req: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        let after = super::unix_millis(SystemTime::now());
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        let (_, end) = hdr.split_once(";end=").unwrap();
+        let end = end.parse::<u64>().unwrap();
+        assert!((before..=after).contains(&end), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn info_entries() {
+        let inner = service_fn(|_req: Request<()>| async move {
+            let mut response = Response::new(());
+            response
+                .headers_mut()
+                .insert("server-timing", HeaderValue::from_static("inner;dur=1"));
+            Ok::<_, Infallible>(response)
+        });
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_version("1.4.2+abc123")
+            .with_host("pod-7f9c")
+            .layer(inner)
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(
+            hdr.ends_with(", ver;desc=\"1.4.2+abc123\", host;desc=\"pod-7f9c\", inner;dur=1"),
+            "{hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn percentile_rank() {
+        let aggregator = Aggregator::new(Duration::from_secs(60));
+        for _ in 0..99 {
+            aggregator.record(Duration::ZERO);
+        }
+
+        let inner = service_fn(|_req: Request<()>| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, Infallible>(Response::new(()))
+        });
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_percentile(aggregator)
+            .layer(inner)
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(";pct=99"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn report_headers() {
+        let timings = Arc::new(AtomicUsize::new(0));
+        let svc = ServerTimingLayer::new("svc1")
+            .with_report_headers([http::header::USER_AGENT])
+            .with_on_timing({
+                let timings = timings.clone();
+                move |report| {
+                    assert_eq!(report.route(), Some("/users"));
+                    assert_eq!(report.headers().len(), 1);
+                    assert_eq!(report.header("user-agent").unwrap(), "curl/8.0");
+                    timings.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let req = Request::get("/users")
+            .header("user-agent", "curl/8.0")
+            .header("authorization", "secret")
+            .body(())
+            .unwrap();
+        svc.oneshot(req).await.unwrap();
+        assert_eq!(timings.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn enrichment() {
+        let timings = Arc::new(AtomicUsize::new(0));
+        let svc = ServerTimingLayer::new("svc1")
+            .with_enrichment(|req| {
+                let country = req.extensions().get::<&str>().copied().unwrap_or("ZZ");
+                vec![("country", country.to_owned())]
+            })
+            .with_on_timing({
+                let timings = timings.clone();
+                move |report| {
+                    assert_eq!(report.label("country"), Some("FR"));
+                    timings.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .layer(service_fn(|req: Request<u8>| async move {
+                assert_eq!(*req.body(), 7);
+                assert_eq!(req.extensions().get::<&str>(), Some(&"FR"));
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let mut req = Request::new(7);
+        req.extensions_mut().insert("FR");
+        let response = svc.oneshot(req).await.unwrap();
+
+        assert_eq!(timings.load(Ordering::Relaxed), 1);
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(!hdr.contains("FR"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn tenant() {
+        let tenants = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let svc = ServerTimingLayer::new("svc1")
+            .with_tenant(|req| {
+                let tenant = req.headers().get("x-tenant")?;
+                tenant.to_str().ok().map(str::to_owned)
+            })
+            .with_tenant_sampling("noisy", Sampling::new(0.0))
+            .with_on_timing_sampling(Sampling::new(1.0))
+            .with_on_timing({
+                let tenants = tenants.clone();
+                move |report| {
+                    tenants
+                        .lock()
+                        .unwrap()
+                        .push(report.tenant().map(str::to_owned));
+                }
+            })
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        for tenant in [Some("acme"), Some("noisy"), None] {
+            let mut req = Request::builder();
+            if let Some(tenant) = tenant {
+                req = req.header("x-tenant", tenant);
+            }
+
+            let response = svc.clone().oneshot(req.body(()).unwrap()).await.unwrap();
+            assert_eq!(
+                response.headers().contains_key("server-timing"),
+                tenant != Some("noisy"),
+                "{tenant:?}"
+            );
+        }
+
+        assert_eq!(
+            *tenants.lock().unwrap(),
+            [Some("acme".to_owned()), Some("noisy".to_owned()), None]
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limit() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_tenant(|req| Some(req.uri().path().trim_start_matches('/').to_owned()))
+            .with_rate_limit(HeaderRateLimit::per_tenant(2))
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let mut headers = Vec::new();
+        for path in ["/acme", "/acme", "/acme", "/other"] {
+            let req = Request::get(path).body(()).unwrap();
+            let response = svc.clone().oneshot(req).await.unwrap();
+            headers.push(response.headers().contains_key("server-timing"));
+        }
+
+        assert_eq!(headers, [true, true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn app_fn() {
+        let names = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let svc = ServerTimingLayer::new("svc")
+            .with_app_fn(|req| {
+                if req.uri().path().starts_with("/admin") {
+                    "admin".into()
+                } else {
+                    format!("api-{}", req.method()).into()
+                }
+            })
+            .with_on_timing({
+                let names = names.clone();
+                move |report| {
+                    let name = report.metrics()[0].name().to_owned();
+                    names.lock().unwrap().push(name);
+                }
+            })
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        for (path, name) in [("/admin/users", "admin;"), ("/users", "api-GET;")] {
+            let req = Request::get(path).body(()).unwrap();
+            let response = svc.clone().oneshot(req).await.unwrap();
+            let hdr = response.headers()["server-timing"].to_str().unwrap();
+            assert!(hdr.starts_with(name), "{hdr}");
+        }
+
+        assert_eq!(*names.lock().unwrap(), ["admin", "api-GET"]);
+    }
+
+    #[tokio::test]
+    async fn protocol() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_protocol(true)
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        for (version, proto) in [
+            (http::Version::HTTP_11, ";proto=\"http/1.1\""),
+            (http::Version::HTTP_2, ";proto=h2"),
+            (http::Version::HTTP_3, ";proto=h3"),
+        ] {
+            let req = Request::builder().version(version).body(()).unwrap();
+            let response = svc.clone().oneshot(req).await.unwrap();
+            let hdr = response.headers()["server-timing"].to_str().unwrap();
+            assert!(hdr.ends_with(proto), "{hdr}");
+        }
+    }
+
+    #[cfg(feature = "feat-alloc")]
+    #[tokio::test]
+    async fn alloc_counter() {
+        thread_local! {
+            static ALLOCATED: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+        }
+
+        let allocate = |bytes| ALLOCATED.with(|n| n.set(n.get() + bytes));
+        let response = ServerTimingLayer::new("svc1")
+            .with_alloc_counter(|| ALLOCATED.with(std::cell::Cell::get))
+            .layer(service_fn(move |_: Request<()>| async move {
+                allocate(1024);
+                tokio::task::yield_now().await;
+                allocate(2048);
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        // Allocations outside of the request don't count.
+        allocate(4096);
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(";alloc=3kb"), "{hdr}");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn thread() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_thread(true)
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        // Spawned, as the test itself runs outside of the workers.
+        let response = tokio::spawn(svc.oneshot(Request::new(())))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.contains(";thread=tokio-runtime-worker#"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn max_header_size() {
+        async fn call(max: usize, errors: &Arc<std::sync::Mutex<Vec<String>>>) -> Option<String> {
+            let response = ServerTimingLayer::new("svc1")
+                .with_max_header_size(max)
+                .with_on_error({
+                    let errors = errors.clone();
+                    move |e| errors.lock().unwrap().push(e.to_string())
+                })
+                .layer(service_fn(|req: Request<()>| async move {
+                    let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                    handle.record(TimingMetric::new("db").with_millis(1.0));
+                    let response = Response::builder()
+                        .header("x-padding", "a".repeat(100))
+                        .body(())
+                        .unwrap();
+                    Ok::<_, Infallible>(response)
+                }))
+                .oneshot(Request::new(()))
+                .await
+                .unwrap();
+
+            let hdr = response.headers().get("server-timing")?;
+            Some(hdr.to_str().unwrap().to_owned())
+        }
+
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        // `x-padding` takes 113 bytes, `server-timing` takes 17 bytes on top.
+        let hdr = call(1024, &errors).await.unwrap();
+        assert!(hdr.ends_with(", db;dur=1.0"), "{hdr}");
+        assert!(errors.lock().unwrap().is_empty());
+
+        let hdr = call(113 + 17 + 14, &errors).await.unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(!hdr.contains("db"), "{hdr}");
+        assert_eq!(
+            *errors.lock().unwrap(),
+            ["response headers would reach 154 bytes"]
+        );
+
+        // Shrunk first, then skipped as the entry of the service doesn't fit.
+        errors.lock().unwrap().clear();
+        assert!(call(120, &errors).await.is_none());
+        assert_eq!(
+            *errors.lock().unwrap(),
+            [
+                "response headers would reach 154 bytes",
+                "response headers would reach 142 bytes, header skipped",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn wrap() {
+        let layer = ServerTimingLayer::new("svc1").with_description("manual");
+        let response = super::ResponseFuture::wrap(&layer, Request::new(()), |req| async move {
+            let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+            handle.record(TimingMetric::new("db").with_millis(1.0));
+            Ok::<_, Infallible>(Response::new(()))
+        })
+        .await
+        .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;desc=\"manual\";dur="), "{hdr}");
+        assert!(hdr.ends_with(", db;dur=1.0"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn scratch_buffer() {
+        for scratch_buffer in [true, false] {
+            let svc = ServerTimingLayer::new("svc1")
+                .with_scratch_buffer(scratch_buffer)
+                .layer(service_fn(|req: Request<()>| async move {
+                    let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                    handle.record(TimingMetric::new("db").with_millis(1.0));
+                    Ok::<_, Infallible>(Response::new(()))
+                }));
+
+            // Nothing leaks from a response to the next one.
+            for _ in 0..2 {
+                let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+                let hdr = response.headers()["server-timing"].to_str().unwrap();
+                assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+                assert_eq!(hdr.matches("db;").count(), 1, "{hdr}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn phases() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_phases(true)
+            .layer(service_fn(|_: Request<()>| {
+                // Like routing, before the future is polled.
+                std::thread::sleep(Duration::from_millis(5));
+                async move {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Ok::<_, Infallible>(Response::new(()))
+                }
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        let dur = |name: &str| -> f64 {
+            let entry = hdr.split(", ").find(|entry| entry.starts_with(name));
+            entry
+                .unwrap()
+                .split("dur=")
+                .nth(1)
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        assert!(dur("route;") >= 5.0, "{hdr}");
+        assert!(dur("handler;") >= 10.0, "{hdr}");
+        assert!(
+            dur("svc1;") >= dur("route;") + dur("handler;") - 0.2,
+            "{hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_policy() {
+        async fn call(nested: NestedPolicy) -> String {
+            let layer = ServerTimingLayer::new("svc1").with_nested_policy(nested);
+            let response = ServiceBuilder::new()
+                .layer(layer.clone())
+                .layer(layer)
+                .service(service_fn(|req: Request<()>| async move {
+                    let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                    handle.record(TimingMetric::new("db").with_millis(1.0));
+                    Ok::<_, Infallible>(Response::new(()))
+                }))
+                .oneshot(Request::new(()))
+                .await
+                .unwrap();
+
+            let hdr = response.headers()["server-timing"].to_str().unwrap();
+            hdr.split(", ")
+                .map(|entry| entry.split(';').next().unwrap())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        assert_eq!(call(NestedPolicy::Keep).await, "svc1, svc1, db");
+        assert_eq!(call(NestedPolicy::Collapse).await, "svc1, db");
+        assert_eq!(call(NestedPolicy::Rename).await, "svc1, svc1-2, db");
+    }
+
+    #[tokio::test]
+    async fn preset() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_preset(super::Preset::Browser)
+            .with_precision(0)
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["server-timing"], "svc1;dur=0");
+        assert_eq!(response.headers()["timing-allow-origin"], "*");
+    }
+
+    #[tokio::test]
+    async fn timing_allow_origin() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_timing_allow_origin(HeaderValue::from_static("https://example.com"))
+            .layer(service_fn(|req: Request<()>| async move {
+                let mut response = Response::new(());
+                if req.uri().path() == "/own" {
+                    response
+                        .headers_mut()
+                        .insert("timing-allow-origin", HeaderValue::from_static("*"));
+                }
+                Ok::<_, Infallible>(response)
+            }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(
+            response.headers()["timing-allow-origin"],
+            "https://example.com"
+        );
+
+        let req = Request::get("/own").body(()).unwrap();
+        let response = svc.oneshot(req).await.unwrap();
+        assert_eq!(response.headers()["timing-allow-origin"], "*");
+    }
+
+    #[tokio::test]
+    async fn cache_markers() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_cache_markers(true)
+            .layer(service_fn(|_: Request<()>| async move {
+                let response = Response::builder()
+                    .header("cf-cache-status", "hit")
+                    .header("age", "120")
+                    .body(())
+                    .unwrap();
+                Ok::<_, Infallible>(response)
+            }));
+
+        let response = svc.oneshot(Request::new(())).await.unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(
+            hdr.ends_with(r#", cdn-cache;desc="HIT", cdn-age;desc="120""#),
+            "{hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_status() {
+        async fn call(status: Option<CacheStatus>) -> String {
+            let response = ServerTimingLayer::new("svc1")
+                .with_phases(true)
+                .layer(service_fn(move |_: Request<()>| async move {
+                    let mut response = Response::new(());
+                    if let Some(status) = status {
+                        response.extensions_mut().insert(status);
+                    }
+                    Ok::<_, Infallible>(response)
+                }))
+                .oneshot(Request::new(()))
+                .await
+                .unwrap();
+
+            response.headers()["server-timing"]
+                .to_str()
+                .unwrap()
+                .to_owned()
+        }
+
+        let hdr = call(None).await;
+        assert!(hdr.contains("handler;"), "{hdr}");
+        assert!(!hdr.contains("cache;"), "{hdr}");
+
+        let hdr = call(Some(CacheStatus::Miss)).await;
+        assert!(hdr.contains("handler;"), "{hdr}");
+        assert!(hdr.ends_with(r#", cache;desc="miss""#), "{hdr}");
+
+        let hdr = call(Some(CacheStatus::Hit)).await;
+        assert!(hdr.contains("route;"), "{hdr}");
+        assert!(!hdr.contains("handler;"), "{hdr}");
+        assert!(hdr.ends_with(r#", cache;desc="hit""#), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn async_metrics() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_async_metrics(Duration::from_secs(1), |req| {
+                let slow = req.uri().path() == "/slow";
+                async move {
+                    if slow {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                    vec![TimingMetric::new("flag").with_description("on")]
+                }
+            })
+            .layer(service_fn(|_: Request<()>| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(r#", flag;desc="on""#), "{hdr}");
+
+        let start = Instant::now();
+        let req = Request::builder().uri("/slow").body(()).unwrap();
+        let response = svc.oneshot(req).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(!hdr.contains("flag"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn feature_flag() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_feature_flag(|req: &Request<()>| req.headers().contains_key("x-debug"))
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().is_some();
+                Ok::<_, Infallible>(Response::new(handle))
+            }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert!(!response.body());
+        assert!(!response.headers().contains_key("server-timing"));
+        assert!(response
+            .extensions()
+            .get::<ServerTimingDuration>()
+            .is_none());
+
+        let req = Request::builder().header("x-debug", "1").body(()).unwrap();
+        let response = svc.oneshot(req).await.unwrap();
+        assert!(response.body());
+        assert!(response.headers().contains_key("server-timing"));
+    }
+
+    #[tokio::test]
+    async fn async_feature_flag() {
+        let svc = ServiceBuilder::new()
+            .layer(AsyncFlagLayer::new(|req: &Request<()>| {
+                let debug = req.headers().contains_key("x-debug");
+                async move {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    debug
+                }
+            }))
+            .layer(ServerTimingLayer::new("svc1"))
+            .service(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().is_some();
+                Ok::<_, Infallible>(Response::new(handle))
+            }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert!(!response.body());
+        assert!(!response.headers().contains_key("server-timing"));
+
+        let req = Request::builder().header("x-debug", "1").body(()).unwrap();
+        let response = svc.oneshot(req).await.unwrap();
+        assert!(response.body());
+        assert!(response.headers().contains_key("server-timing"));
+    }
+
+    #[tokio::test]
+    async fn include_exclude() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_include("/api/**")
+            .with_include("/**/*.json")
+            .with_exclude("/api/health")
+            .layer(service_fn(|req: Request<()>| async move {
+                let timed = req.extensions().get::<ServerTimingHandle>().is_some();
+                Ok::<_, Infallible>(Response::new(timed))
+            }));
+
+        for (path, expected) in [
+            ("/api/users", true),
+            ("/api", true),
+            ("/static/manifest.json", true),
+            ("/api/health", false),
+            ("/static/app.js", false),
+        ] {
+            let req = Request::builder().uri(path).body(()).unwrap();
+            let response = svc.clone().oneshot(req).await.unwrap();
+            assert_eq!(*response.body(), expected, "{path}");
+            assert_eq!(
+                response.headers().contains_key("server-timing"),
+                expected,
+                "{path}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn method_policy() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_method_policy(http::Method::OPTIONS, MethodPolicy::Skip)
+            .with_method_policy(http::Method::GET, MethodPolicy::Precision(0))
+            .with_method_policy(http::Method::POST, MethodPolicy::Preset(Preset::Verbose))
+            .layer(service_fn(|req: Request<()>| async move {
+                if let Some(handle) = req.extensions().get::<ServerTimingHandle>() {
+                    handle.record(TimingMetric::new("db").with_millis(1.25));
+                }
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let call = |method| {
+            let svc = svc.clone();
+            async move {
+                let req = Request::builder().method(method).body(()).unwrap();
+                let response = svc.oneshot(req).await.unwrap();
+                response
+                    .headers()
+                    .get("server-timing")
+                    .map(|hdr| hdr.to_str().unwrap().to_owned())
+            }
+        };
+
+        assert_eq!(call(http::Method::OPTIONS).await, None);
+        let hdr = call(http::Method::GET).await.unwrap();
+        assert!(hdr.ends_with(", db;dur=1"), "{hdr}");
+        let hdr = call(http::Method::POST).await.unwrap();
+        assert!(hdr.contains(";seq="), "{hdr}");
+        assert!(hdr.ends_with(", db;dur=1.250"), "{hdr}");
+        let hdr = call(http::Method::PUT).await.unwrap();
+        assert!(hdr.ends_with(", db;dur=1.3"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn content_type() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_content_type("text/html")
+            .with_content_type("application/json")
+            .layer(service_fn(|req: Request<()>| async move {
+                let mut response = Response::new(());
+                if let Some(content_type) = req.headers().get("accept") {
+                    response
+                        .headers_mut()
+                        .insert("content-type", content_type.clone());
+                }
+                Ok::<_, Infallible>(response)
+            }));
+
+        for (content_type, expected) in [
+            (Some("text/html; charset=utf-8"), true),
+            (Some("application/json"), true),
+            (Some("image/png"), false),
+            (Some("video/mp4"), false),
+            (None, true),
+        ] {
+            let mut req = Request::builder();
+            if let Some(content_type) = content_type {
+                req = req.header("accept", content_type);
+            }
+            let response = svc.clone().oneshot(req.body(()).unwrap()).await.unwrap();
+            assert_eq!(
+                response.headers().contains_key("server-timing"),
+                expected,
+                "{content_type:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn policy() {
+        use crate::policy::{self, Policy};
+
+        let svc = ServerTimingLayer::new("svc1")
+            .with_policy(
+                policy::path("/api/**").or(policy::status(|status| status.is_server_error())),
+            )
+            .with_policy(policy::method(http::Method::OPTIONS).not())
+            .layer(service_fn(|req: Request<()>| async move {
+                let timed = req.extensions().get::<ServerTimingHandle>().is_some();
+                let status = if req.uri().path() == "/fail" {
+                    StatusCode::BAD_GATEWAY
+                } else {
+                    StatusCode::OK
+                };
+                let response = Response::builder().status(status).body(timed).unwrap();
+                Ok::<_, Infallible>(response)
+            }));
+
+        for (method, path, timed, header) in [
+            (http::Method::GET, "/api/users", true, true),
+            (http::Method::OPTIONS, "/api/users", false, false),
+            (http::Method::GET, "/static/app.js", true, false),
+            (http::Method::GET, "/fail", true, true),
+        ] {
+            let req = Request::builder()
+                .method(&method)
+                .uri(path)
+                .body(())
+                .unwrap();
+            let response = svc.clone().oneshot(req).await.unwrap();
+            assert_eq!(*response.body(), timed, "{method} {path}");
+            assert_eq!(
+                response.headers().contains_key("server-timing"),
+                header,
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn policy_with_sampling() {
+        use crate::policy::{self, Policy};
+
+        // Sampling and rate limiting are policies, so they combine with the
+        // added ones and the built-in filters.
+        let svc = ServerTimingLayer::new("svc1")
+            .with_exclude("/health")
+            .with_content_type("text/html")
+            .with_policy(Sampling::new(0.0).or(policy::path("/checkout")))
+            .with_rate_limit(HeaderRateLimit::per_route(2))
+            .layer(service_fn(|req: Request<()>| async move {
+                let timed = req.extensions().get::<ServerTimingHandle>().is_some();
+                let response = Response::builder()
+                    .header("content-type", "text/html")
+                    .body(timed)
+                    .unwrap();
+                Ok::<_, Infallible>(response)
+            }));
+
+        for (path, timed, header) in [
+            ("/health", false, false),
+            ("/", true, false),
+            ("/checkout", true, true),
+            ("/checkout", true, true),
+            ("/checkout", true, false),
+        ] {
+            let req = Request::get(path).body(()).unwrap();
+            let response = svc.clone().oneshot(req).await.unwrap();
+            assert_eq!(*response.body(), timed, "{path}");
+            assert_eq!(
+                response.headers().contains_key("server-timing"),
+                header,
+                "{path}"
+            );
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    async fn verbose_query() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_min_duration(Duration::from_secs(1))
+            .with_verbose_query(true)
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        let response = svc.clone().oneshot(Request::new(())).await.unwrap();
+        assert!(!response.headers().contains_key("server-timing"));
+
+        let req = Request::builder()
+            .uri("/?server_timing=verbose")
+            .body(())
+            .unwrap();
+        let response = svc.oneshot(req).await.unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(hdr.contains("route;"), "{hdr}");
+        let dur = hdr["svc1;dur=".len()..].split(';').next().unwrap();
+        assert_eq!(dur.split('.').nth(1).unwrap().len(), 6, "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn service_failed() {
+        let failures = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let svc = ServerTimingLayer::new("svc1")
+            .with_on_error({
+                let failures = failures.clone();
+                move |e| {
+                    if let ServerTimingError::ServiceFailed(report) = e {
+                        failures.lock().unwrap().push(report.clone());
+                    }
+                }
+            })
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db").with_millis(1.0));
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Err::<Response<()>, _>("unavailable")
+            }));
+
+        let req = Request::builder().uri("/users").body(()).unwrap();
+        assert_eq!(svc.oneshot(req).await.unwrap_err(), "unavailable");
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 1);
+        let report = &failures[0];
+        assert_eq!(report.route(), Some("/users"));
+        assert_eq!(report.status(), None);
+        assert_eq!(report.metrics()[0].name(), "svc1");
+        assert!(report.metrics()[0].millis().unwrap() >= 10.0);
+        assert_eq!(report.metrics()[1].name(), "db");
+    }
+
+    #[tokio::test]
+    async fn catch_panic() {
+        fn boom() -> Response<String> {
+            std::panic::resume_unwind(Box::new("boom"))
+        }
+
+        let reports = Arc::new(AtomicUsize::new(0));
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_catch_panic(true)
+            .with_on_timing({
+                let reports = reports.clone();
+                move |report| {
+                    assert_eq!(report.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+                    assert_eq!(report.metrics().last().unwrap().name(), "panic");
+                    reports.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .layer(service_fn(|_: Request<()>| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok::<Response<String>, Infallible>(boom())
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(hdr.ends_with(", panic"), "{hdr}");
+        assert_eq!(reports.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn catch_panic_order() {
+        /// What `tower_http::catch_panic` does, with a custom response
+        /// inserting `Panicked`.
+        async fn catch<F>(fut: F) -> Result<Response<String>, Infallible>
+        where
+            F: Future<Output = Result<Response<String>, Infallible>>,
+        {
+            let mut fut = std::pin::pin!(fut);
+            std::future::poll_fn(|cx| {
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| fut.as_mut().poll(cx)))
+                    .unwrap_or_else(|_| {
+                        let mut response = Response::new("Service panicked".to_owned());
+                        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        response.extensions_mut().insert(Panicked);
+                        std::task::Poll::Ready(Ok(response))
+                    })
+            })
+            .await
+        }
+
+        async fn handler(_: Request<()>) -> Result<Response<String>, Infallible> {
+            std::panic::resume_unwind(Box::new("boom"))
+        }
+
+        // `CatchPanicLayer` inside: the middleware tags its response.
+        let response = ServerTimingLayer::new("svc1")
+            .layer(service_fn(|req| catch(handler(req))))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "Service panicked");
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(hdr.ends_with(", panic"), "{hdr}");
+
+        // `CatchPanicLayer` outside: the middleware catches the panic first.
+        let svc = ServerTimingLayer::new("svc1")
+            .with_catch_panic(true)
+            .layer(service_fn(handler));
+        let response = service_fn(move |req| catch(svc.clone().oneshot(req)))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "");
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert_eq!(hdr.matches("panic").count(), 1, "{hdr}");
+    }
+
+    #[test]
+    fn wire_format() {
+        /// The header for the given options, response and custom metrics.
+        fn render(config: &Config, mut response: Response<()>, metrics: &[TimingMetric]) -> String {
+            let handle = ServerTimingHandle::new(Instant::now());
+            let protocol = config.protocol.then_some("h2");
+            let elapsed = Duration::from_micros(12_345);
+            add_header(
+                &mut response,
+                config,
+                &config.app,
+                protocol,
+                &handle,
+                elapsed,
+                metrics,
+            );
+
+            let values: Vec<_> = response
+                .headers()
+                .get_all("server-timing")
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                .collect();
+            values.join(" | ")
+        }
+
+        let metrics = [
+            TimingMetric::new("db")
+                .with_description("users")
+                .with_start(Duration::from_micros(1_234))
+                .with_millis(4.56),
+            TimingMetric::new("query1")
+                .with_parent("db")
+                .with_millis(2.0),
+            TimingMetric::new("cache").with_description("hit"),
+        ];
+        let negative = [TimingMetric::new("clock").with_millis(-1.0)];
+        let unicode = [TimingMetric::new("search").with_description("ラーメン 100%")];
+        let long = [TimingMetric::new("db").with_description(format!(
+            "select * from users where id in ({})",
+            ["1"; 60].join(", ")
+        ))];
+
+        let upstream = || {
+            Response::builder()
+                .header("server-timing", "upstream;dur=1.5")
+                .body(())
+                .unwrap()
+        };
+        let status = |status| Response::builder().status(status).body(()).unwrap();
+        let preset = |preset| {
+            let mut config = Config::new("svc1");
+            Preset::apply(preset, &mut config);
+            config.sequence = false;
+            config.phases = false;
+            config
+        };
+        let mut versioned = Config::new("svc1");
+        versioned.version = Some("1.4.2".into());
+        versioned.host = Some("web-1".into());
+
+        let cases: Vec<(&str, Config, Response<()>, &[TimingMetric])> = vec![
+            ("default", Config::new("svc1"), Response::new(()), &metrics),
+            (
+                "description",
+                Config::described("svc1", "gateway"),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "precision_0",
+                Config::new("svc1").with_precision(0),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "precision_3",
+                Config::new("svc1").with_precision(3),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "start_offsets",
+                Config::new("svc1").with_start_offsets(true),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "dur_first",
+                Config::described("svc1", "gateway").with_param_order(ParamOrder::DurFirst),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "timeout_marker",
+                Config::new("svc1").with_timeout_marker(true),
+                status(StatusCode::GATEWAY_TIMEOUT),
+                &[],
+            ),
+            (
+                "clamp_marker",
+                Config::new("svc1").with_clamp_marker(true),
+                Response::new(()),
+                &negative,
+            ),
+            (
+                "protocol",
+                Config::new("svc1").with_protocol(true),
+                Response::new(()),
+                &[],
+            ),
+            (
+                "cache_markers",
+                Config::new("svc1").with_cache_markers(true),
+                Response::builder()
+                    .header("x-cache", "Hit from cloudfront")
+                    .header("age", "120")
+                    .body(())
+                    .unwrap(),
+                &[],
+            ),
+            ("version_host", versioned, Response::new(()), &[]),
+            ("merge", Config::new("svc1"), upstream(), &metrics),
+            (
+                "append",
+                Config::new("svc1").with_append(true),
+                upstream(),
+                &metrics,
+            ),
+            (
+                "non_ascii_keep",
+                Config::described("svc1", "Zürich"),
+                Response::new(()),
+                &unicode,
+            ),
+            (
+                "non_ascii_percent_encode",
+                Config::described("svc1", "Zürich").with_non_ascii(NonAsciiPolicy::PercentEncode),
+                Response::new(()),
+                &unicode,
+            ),
+            (
+                "non_ascii_replace",
+                Config::described("svc1", "Zürich").with_non_ascii(NonAsciiPolicy::Replace),
+                Response::new(()),
+                &unicode,
+            ),
+            (
+                "max_description",
+                Config::new("svc1"),
+                Response::new(()),
+                &long,
+            ),
+            (
+                "max_description_none",
+                Config::new("svc1").with_max_description(None),
+                Response::new(()),
+                &long,
+            ),
+            (
+                "preset_minimal",
+                preset(Preset::Minimal),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "preset_browser",
+                preset(Preset::Browser),
+                Response::new(()),
+                &metrics,
+            ),
+            (
+                "preset_verbose",
+                preset(Preset::Verbose),
+                Response::new(()),
+                &metrics,
+            ),
+        ];
+
+        let mut actual = String::new();
+        for (name, config, response, metrics) in cases {
+            actual.push_str(&format!("{name}: {}\n", render(&config, response, metrics)));
+        }
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/snapshots/wire_format.txt");
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(path, &actual).unwrap();
+        }
+
+        // The wire format is part of the API, see the README: update the
+        // snapshot with `UPDATE_SNAPSHOTS=1` only along a semver-major release.
+        assert_eq!(actual, std::fs::read_to_string(path).unwrap());
+    }
+
+    #[test]
+    fn entry_param_order() {
+        let entry = |order| Entry {
+            app: "svc1",
+            description: Some("gateway"),
+            elapsed: Duration::from_micros(12_345),
+            precision: 1,
+            order,
+            non_ascii: NonAsciiPolicy::Keep,
+            attempts: 2,
+            timeout: true,
+            seq: Some(42),
+            end: Some(1_712_345_679_042),
+            pct: Some(99),
+            clamped: 1,
+            proto: Some("h2"),
+            thread: None,
+            #[cfg(feature = "feat-alloc")]
+            alloc: None,
+        };
+
+        assert_eq!(
+            entry(ParamOrder::DescFirst).encode(Vec::new()),
+            br#"svc1;desc="gateway";dur=12.3;attempts=2;timeout=1;seq=42;end=1712345679042;pct=99;clamped=1;proto=h2"#
+        );
+        assert_eq!(
+            entry(ParamOrder::DurFirst).encode(Vec::new()),
+            br#"svc1;dur=12.3;desc="gateway";attempts=2;timeout=1;seq=42;end=1712345679042;pct=99;clamped=1;proto=h2"#
+        );
+    }
+
+    #[tokio::test]
+    async fn param_order() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_precision(0)
+            .with_description("gateway")
+            .with_param_order(ParamOrder::DurFirst)
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(
+                    TimingMetric::new("db")
+                        .with_description("users")
+                        .with_millis(4.0),
+                );
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        let (entry, metric) = hdr.split_once(", ").unwrap();
+        assert!(entry.starts_with("svc1;dur="), "{hdr}");
+        assert!(entry.ends_with(r#";desc="gateway""#), "{hdr}");
+        assert_eq!(metric, r#"db;dur=4;desc="users""#);
+    }
+
+    #[tokio::test]
+    async fn redaction() {
+        let descriptions = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_redaction(|desc: &str| desc.replace("miku@example.com", "***"))
+            .with_on_timing({
+                let descriptions = descriptions.clone();
+                move |report| {
+                    let mut descriptions = descriptions.lock().unwrap();
+                    for metric in report.metrics() {
+                        descriptions.extend(metric.description().map(str::to_owned));
+                    }
+                }
+            })
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(
+                    TimingMetric::new("db")
+                        .with_millis(1.0)
+                        .with_description("user miku@example.com"),
+                );
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(r#", db;desc="user ***";dur=1.0"#), "{hdr}");
+        assert_eq!(*descriptions.lock().unwrap(), ["user ***"]);
+    }
+
+    #[tokio::test]
+    async fn strict() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_strict(StrictMode::Panic)
+            .with_description("gateway")
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db").with_millis(1.0));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(", db;dur=1.0"), "{hdr}");
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Non-compliant `server-timing` header value")]
+    async fn strict_panic() {
+        let _ = ServerTimingLayer::new("svc1")
+            .with_strict(StrictMode::Panic)
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db query").with_millis(1.0));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn strict_non_compliant() {
+        let errors = Arc::new(AtomicUsize::new(0));
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_strict(StrictMode::Report)
+            .with_on_error({
+                let errors = errors.clone();
+                move |e| {
+                    if let ServerTimingError::NonCompliant(_) = e {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db query").with_millis(1.0));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        assert!(!response.headers().contains_key("server-timing"));
+        assert_eq!(errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn namespace() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_precision(0)
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                let db = handle.namespace("db");
+                db.record(TimingMetric::new("query").with_millis(3.0));
+                handle.record(TimingMetric::new("cache").with_millis(1.0));
+                db.namespace("tx")
+                    .record(TimingMetric::new("commit").with_millis(2.0));
+                db.record_early_hints();
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        let (_, metrics) = hdr.split_once(", ").unwrap();
+        let (metrics, _) = metrics.rsplit_once(", early_hints;dur=").unwrap();
+        assert_eq!(
+            metrics,
+            "db;dur=3, db.query;dur=3, cache;dur=1, db.tx;dur=2, db.tx.commit;dur=2"
+        );
+    }
+
+    #[tokio::test]
+    async fn visit_mut() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_visit_mut(|metrics: &mut Vec<TimingMetric>| {
+                metrics.retain(|metric| !metric.millis().is_some_and(|dur| dur < 1.0));
+                for metric in metrics.iter_mut() {
+                    if metric.name() == "sqlx" {
+                        *metric = metric.clone().with_name("db");
+                    }
+                }
+                metrics.sort_by(|a, b| a.name().cmp(b.name()));
+            })
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("sqlx").with_millis(4.0));
+                handle.record(TimingMetric::new("cache").with_millis(0.2));
+                handle.record(TimingMetric::new("auth").with_millis(1.0));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(", auth;dur=1.0, db;dur=4.0"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn tail_sampling() {
+        let timings = Arc::new(AtomicUsize::new(0));
+        let mut svc = ServerTimingLayer::new("svc1")
+            .with_sampling(
+                Sampling::new(0.0)
+                    .with_slow(Duration::from_millis(20))
+                    .with_server_errors(true),
+            )
+            .with_on_timing({
+                let timings = timings.clone();
+                move |report| {
+                    assert_eq!(report.metrics()[0].name(), "svc1");
+                    assert_eq!(report.method(), Some(&http::Method::GET));
+                    assert_eq!(report.route(), Some("/"));
+                    assert!(report.status().is_some());
+                    timings.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .layer(service_fn(|req: Request<u64>| async move {
+                let (delay, status) = match *req.body() {
+                    0 => (0, StatusCode::OK),
+                    1 => (30, StatusCode::OK),
+                    _ => (0, StatusCode::INTERNAL_SERVER_ERROR),
+                };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+
+                let mut response = Response::new(());
+                *response.status_mut() = status;
+                Ok::<_, Infallible>(response)
+            }));
+
+        let mut sampled = Vec::new();
+        for kind in 0..3 {
+            let response = svc
+                .ready()
+                .await
+                .unwrap()
+                .call(Request::new(kind))
+                .await
+                .unwrap();
+            sampled.push(response.headers().contains_key("server-timing"));
+        }
+
+        assert_eq!(sampled, [false, true, true]);
+        assert_eq!(timings.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn independent_on_timing_sampling() {
+        let timings = Arc::new(AtomicUsize::new(0));
+        let mut svc = ServerTimingLayer::new("svc1")
+            .with_on_timing({
+                let timings = timings.clone();
+                move |_| {
+                    timings.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .with_on_timing_sampling(Sampling::new(1.0).with_rate_limit(2))
+            .layer(service_fn(|_req: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+
+        for _ in 0..5 {
+            let response = svc
+                .ready()
+                .await
+                .unwrap()
+                .call(Request::new(()))
+                .await
+                .unwrap();
+            assert!(response.headers().contains_key("server-timing"));
+        }
+
+        assert_eq!(timings.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn start_offsets() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_start_offsets(true)
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(
+                    TimingMetric::new("db")
+                        .with_start(Duration::from_millis(2))
+                        .with_millis(1.0),
+                );
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.ends_with(", db;dur=1.0;start=2.0"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn share() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_share(true)
+            .layer(service_fn(|req: Request<()>| async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db").with_millis(10.0));
+                handle.record(TimingMetric::new("cache"));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        let (_, share) = hdr.split_once("db;dur=10.0;share=").unwrap();
+        let (share, cache) = share.split_once(", ").unwrap();
+        assert!((1..=50).contains(&share.parse::<u64>().unwrap()), "{hdr}");
+        assert_eq!(cache, "cache");
+    }
+
+    #[tokio::test]
+    async fn early_hints() {
+        let response = ServerTimingLayer::new("svc1")
+            .layer(service_fn(|req: Request<()>| async move {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record_early_hints();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        let dur = |name: &str| -> f64 {
+            let entry = hdr.split(", ").find(|entry| entry.starts_with(name));
+            entry
+                .unwrap()
+                .split("dur=")
+                .nth(1)
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        assert!(dur("early_hints;") >= 5.0, "{hdr}");
+        assert!(dur("svc1;") >= dur("early_hints;") + 20.0, "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn upstream_parsing() {
+        async fn call(mode: ParseMode, errors: &Arc<AtomicUsize>) -> Vec<Vec<u8>> {
+            let response = ServerTimingLayer::new("svc1")
+                .with_append(true)
+                .with_upstream_parsing(mode)
+                .with_on_error({
+                    let errors = errors.clone();
+                    move |e| {
+                        assert!(matches!(e, ServerTimingError::MalformedUpstream(_)));
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+                .layer(service_fn(|_req: Request<()>| async move {
+                    let mut response = Response::new(());
+                    let headers = response.headers_mut();
+                    headers.append("server-timing", HeaderValue::from_static("db;dur=1"));
+                    headers.append(
+                        "server-timing",
+                        HeaderValue::from_bytes(b"a;desc=\"\xff\", b c, d;dur=2, e;").unwrap(),
+                    );
+                    Ok::<_, Infallible>(response)
+                }))
+                .oneshot(Request::new(()))
+                .await
+                .unwrap();
+
+            response
+                .headers()
+                .get_all("server-timing")
+                .iter()
+                .map(|value| value.as_bytes().to_vec())
+                .collect()
+        }
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let values = call(ParseMode::Lenient, &errors).await;
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0], b"db;dur=1");
+        assert_eq!(values[1], b"a;desc=\"\xff\", d;dur=2");
+        assert!(values[2].starts_with(b"svc1;dur="));
+        assert_eq!(errors.load(Ordering::Relaxed), 2);
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let values = call(ParseMode::Strict, &errors).await;
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0], b"db;dur=1");
+        assert!(values[1].starts_with(b"svc1;dur="));
+        assert_eq!(errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn duplicate_policy() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_duplicate_policy(DuplicatePolicy::prefix_theirs("upstream-").unwrap())
+            .layer(service_fn(|_req: Request<()>| async move {
+                let mut response = Response::new(());
+                response
+                    .headers_mut()
+                    .insert("server-timing", HeaderValue::from_static("svc1;dur=1, db"));
+                Ok::<_, Infallible>(response)
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(hdr.ends_with(", upstream-svc1;dur=1, db"), "{hdr}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid `server-timing` duplicate policy")]
+    fn duplicate_policy_invalid_prefix() {
+        let _ = ServerTimingLayer::new("svc1")
+            .with_duplicate_policy(DuplicatePolicy::PrefixTheirs("up stream;".into()));
+    }
+
+    #[tokio::test]
+    async fn full_policy() {
+        async fn call(full: FullPolicy, errors: &Arc<AtomicUsize>) -> Response<()> {
+            ServerTimingLayer::new("svc1")
+                .with_full_policy(full)
+                .with_on_error({
+                    let errors = errors.clone();
+                    move |e| {
+                        if let ServerTimingError::MaxSizeReached(_) = e {
+                            errors.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+                .layer(service_fn(|_req: Request<()>| async move {
+                    // Fills the header map up to its max size.
+                    let mut response = Response::new(());
+                    response
+                        .headers_mut()
+                        .insert("x-debug", HeaderValue::from_static("1"));
+                    for i in 0.. {
+                        let name = http::HeaderName::try_from(format!("x-{i}")).unwrap();
+                        let value = HeaderValue::from_static("1");
+                        if response.headers_mut().try_insert(name, value).is_err() {
+                            break;
+                        }
+                    }
+                    Ok::<_, Infallible>(response)
+                }))
+                .oneshot(Request::new(()))
+                .await
+                .unwrap()
+        }
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let response = call(FullPolicy::Report, &errors).await;
+        assert!(response.headers().get("server-timing").is_none());
+        assert!(response.headers().contains_key("x-debug"));
+        assert_eq!(errors.load(Ordering::Relaxed), 1);
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let name = http::HeaderName::from_static("x-debug");
+        let response = call(FullPolicy::Evict(name), &errors).await;
+        assert!(response.headers().contains_key("server-timing"));
+        assert!(!response.headers().contains_key("x-debug"));
+        assert_eq!(errors.load(Ordering::Relaxed), 0);
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let name = http::HeaderName::from_static("x-missing");
+        let response = call(FullPolicy::Evict(name), &errors).await;
+        assert!(response.headers().get("server-timing").is_none());
+        assert_eq!(errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn boxed_service() {
+        // The name doesn't outlive the function, nor does the layer borrow it.
+        let name = format!("svc{}", 1);
+        let svc = ServerTimingLayer::new(name)
+            .with_description(String::from("desc"))
+            .layer(service_fn(|_: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(()))
+            }));
+        let svc = tower::util::BoxCloneService::new(svc);
+
+        let response = tokio::spawn(svc.oneshot(Request::new(())))
+            .await
+            .unwrap()
+            .unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;desc=\"desc\";dur="), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn precision() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_precision(0)
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db").with_millis(12.34));
+                handle.record_with_precision(TimingMetric::new("tls").with_millis(0.1234), 3);
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(
+            hdr.starts_with("svc1;dur=0, db;dur=12, tls;dur=0.123"),
+            "{hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn param_fn() {
+        let response = ServerTimingLayer::new("svc1")
+            .with_version("1.0")
+            .with_param_fn(|response| {
+                let mut params = Vec::new();
+                if let Some(cache) = response.headers().get("x-cache") {
+                    params.push(("cache", cache.to_str().unwrap_or_default().to_owned()));
+                }
+                if let Some(shard) = response.extensions().get::<&str>() {
+                    params.push(("shard", (*shard).to_owned()));
+                }
+                params
+            })
+            .layer(service_fn(|_: Request<()>| async move {
+                let mut response = Response::new("body");
+                response
+                    .headers_mut()
+                    .insert("x-cache", HeaderValue::from_static("hit"));
+                response.extensions_mut().insert("eu 1");
+                Ok::<_, Infallible>(response)
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        assert_eq!(*response.body(), "body");
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(
+            hdr.contains(";cache=hit;shard=\"eu 1\", ver;desc=\"1.0\""),
+            "{hdr}"
+        );
+    }
+
+    #[tokio::test]
+    async fn clamp_negative_durations() {
+        let clamped = Arc::new(AtomicUsize::new(0));
+
+        let response = ServerTimingLayer::new("svc1")
+            .with_clamp_marker(true)
+            .with_on_error({
+                let clamped = clamped.clone();
+                move |e| {
+                    if let ServerTimingError::ClampedDurations(n) = e {
+                        clamped.fetch_add(*n, Ordering::Relaxed);
+                    }
+                }
+            })
+            .layer(service_fn(|req: Request<()>| async move {
+                let handle = req.extensions().get::<ServerTimingHandle>().unwrap();
+                handle.record(TimingMetric::new("db").with_millis(-0.3));
+                handle.record(TimingMetric::new("cache").with_millis(1.0));
+                Ok::<_, Infallible>(Response::new(()))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(
+            hdr.contains(";clamped=1, db;dur=0.0, cache;dur=1.0"),
+            "{hdr}"
+        );
+        assert_eq!(clamped.load(Ordering::Relaxed), 1);
     }
 }
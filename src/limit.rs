@@ -0,0 +1,209 @@
+//! Integration with `tower::limit::ConcurrencyLimit`.
+//!
+//! The concurrency limiter makes requests wait for a permit while the service
+//! is polled for readiness, before the request is even dispatched. Install
+//! [`QueueWaitLayer`] right above the limit layer to report that wait as a
+//! `queue;dur=` entry, separated from the duration of the service itself:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::new()
+//!     .layer(ServerTimingLayer::new("app"))
+//!     .layer(QueueWaitLayer)
+//!     .concurrency_limit(64)
+//!     .service(inner);
+//! ```
+
+use std::{
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use http::Request;
+
+use crate::{ServerTimingHandle, TimingMetric};
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A layer recording the time a request waited for the inner service to be
+/// ready, to be installed right above the concurrency limit layer.
+pub struct QueueWaitLayer;
+
+impl<S> tower_layer::Layer<S> for QueueWaitLayer {
+    type Service = QueueWaitService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        QueueWaitService {
+            service,
+            waiting_since: None,
+            waited: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A service recording the time a request waited for the inner service to be
+/// ready.
+pub struct QueueWaitService<S> {
+    /// The service to wrap.
+    service: S,
+
+    /// When the inner service was first polled for readiness.
+    waiting_since: Option<Instant>,
+
+    /// How long it took the inner service to be ready.
+    waited: Option<Duration>,
+}
+
+/// Clones the service without the wait of a pending request, which belongs
+/// to the original, like `ConcurrencyLimit` drops its permit.
+impl<S: Clone> Clone for QueueWaitService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            waiting_since: None,
+            waited: None,
+        }
+    }
+}
+
+impl<S, ReqBody> tower_service::Service<Request<ReqBody>> for QueueWaitService<S>
+where
+    S: tower_service::Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+
+        let ready = self.service.poll_ready(cx);
+
+        if ready.is_ready() && self.waited.is_none() {
+            self.waited = Some(waiting_since.elapsed());
+        }
+
+        ready
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        self.waiting_since = None;
+
+        if let (Some(waited), Some(handle)) = (
+            self.waited.take(),
+            req.extensions().get::<ServerTimingHandle>(),
+        ) {
+            handle.record(TimingMetric::new("queue").with_duration(waited));
+        }
+
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{poll_fn, ready, Future, Ready},
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use http::{Request, Response};
+    use tokio::time::{sleep, Sleep};
+    use tower::ServiceExt;
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use super::QueueWaitLayer;
+    use crate::ServerTimingLayer;
+
+    /// Stands for a concurrency limiter waiting for a permit.
+    struct Limited(Pin<Box<Sleep>>);
+
+    impl tower_service::Service<Request<()>> for Limited {
+        type Response = Response<()>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<()>, Infallible>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.0.as_mut().poll(cx).map(Ok)
+        }
+
+        fn call(&mut self, _req: Request<()>) -> Self::Future {
+            ready(Ok(Response::new(())))
+        }
+    }
+
+    #[derive(Clone)]
+    /// Stands for a service which is busy when first polled for readiness.
+    struct Busy(Arc<AtomicBool>);
+
+    impl tower_service::Service<Request<()>> for Busy {
+        type Response = Response<()>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<()>, Infallible>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            if self.0.swap(true, Ordering::Relaxed) {
+                Poll::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        fn call(&mut self, _req: Request<()>) -> Self::Future {
+            ready(Ok(Response::new(())))
+        }
+    }
+
+    /// Returns the `queue` duration of the given header.
+    fn queue_wait(hdr: &str) -> f64 {
+        let (_, wait) = hdr.split_once(", queue;dur=").unwrap();
+        wait.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn record_queue_wait() {
+        let service = ServerTimingLayer::new("svc1")
+            .layer(QueueWaitLayer.layer(Limited(Box::pin(sleep(Duration::from_millis(20))))));
+
+        let response = service.oneshot(Request::new(())).await.unwrap();
+
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(queue_wait(hdr) >= 20.0, "Invalid `server-timing`: {hdr}");
+    }
+
+    #[tokio::test]
+    async fn clone_after_poll_ready() {
+        let mut service = QueueWaitLayer.layer(Busy(Arc::default()));
+        let pending = poll_fn(|cx| Poll::Ready(service.poll_ready(cx).is_pending())).await;
+        assert!(pending);
+
+        sleep(Duration::from_millis(30)).await;
+
+        let response = ServerTimingLayer::new("svc1")
+            .layer(service.clone())
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(queue_wait(hdr) < 30.0, "Invalid `server-timing`: {hdr}");
+    }
+}
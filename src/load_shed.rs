@@ -0,0 +1,166 @@
+//! Integration with `tower::load_shed`.
+//!
+//! Requests rejected by the load shedder are fast, and would be
+//! indistinguishable from fast successes in RUM data. Install
+//! [`ShedMarkerLayer`] right above the load shed layer to add a `shed` marker
+//! entry to their responses:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::new()
+//!     .layer(ServerTimingLayer::new("app"))
+//!     .layer(HandleErrorLayer::new(handle_error))
+//!     .layer(ShedMarkerLayer)
+//!     .load_shed()
+//!     .service(inner);
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+
+use http::Request;
+use pin_project_lite::pin_project;
+use tower::{load_shed::error::Overloaded, BoxError};
+
+use crate::{ServerTimingHandle, TimingMetric};
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A layer marking requests rejected by the load shedder, to be installed
+/// right above the load shed layer.
+pub struct ShedMarkerLayer;
+
+impl<S> tower_layer::Layer<S> for ShedMarkerLayer {
+    type Service = ShedMarkerService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ShedMarkerService { service }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A service marking requests rejected by the load shedder.
+pub struct ShedMarkerService<S> {
+    /// The service to wrap.
+    service: S,
+}
+
+impl<S, ReqBody> tower_service::Service<Request<ReqBody>> for ShedMarkerService<S>
+where
+    S: tower_service::Service<Request<ReqBody>, Error = BoxError>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let handle = req.extensions().get::<ServerTimingHandle>().cloned();
+
+        ResponseFuture {
+            inner: self.service.call(req),
+            handle,
+        }
+    }
+}
+
+pin_project! {
+    /// A future marking requests rejected by the load shedder.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        handle: Option<ServerTimingHandle>,
+    }
+}
+
+impl<F, T> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, BoxError>>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let result = ready!(this.inner.poll(cx));
+
+        if let (Err(e), Some(handle)) = (&result, this.handle) {
+            if e.is::<Overloaded>() {
+                handle.record(
+                    TimingMetric::new("shed")
+                        .with_duration(Instant::now().saturating_duration_since(handle.start())),
+                );
+            }
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        task::{Context, Poll},
+    };
+
+    use http::{Request, Response, StatusCode};
+    use tower::{load_shed::error::Overloaded, BoxError, ServiceBuilder, ServiceExt};
+
+    use super::ShedMarkerLayer;
+    use crate::ServerTimingLayer;
+
+    #[derive(Clone)]
+    struct NeverReady;
+
+    impl tower_service::Service<Request<()>> for NeverReady {
+        type Response = Response<()>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Response<()>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Pending
+        }
+
+        fn call(&mut self, _req: Request<()>) -> Self::Future {
+            std::future::ready(Ok(Response::new(())))
+        }
+    }
+
+    #[tokio::test]
+    async fn mark_shed() {
+        let service = ServiceBuilder::new()
+            .layer(ServerTimingLayer::new("svc1"))
+            // What an error handler, e.g. axum's `HandleErrorLayer`, does.
+            .map_result(|result: Result<Response<()>, BoxError>| match result {
+                Err(e) if e.is::<Overloaded>() => {
+                    let mut response = Response::new(());
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    Ok(response)
+                }
+                result => result,
+            })
+            .layer(ShedMarkerLayer)
+            .load_shed()
+            .service(NeverReady);
+
+        let response = service.oneshot(Request::new(())).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            hdr.contains(", shed;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Merging with upstream entries.
+
+use std::{borrow::Cow, time::Duration};
+
+use http::{HeaderMap, HeaderValue};
+
+use crate::{
+    parse::{elements, is_token, parse_header},
+    InvalidName, SERVER_TIMING,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+/// What happens when an upstream `Server-Timing` header already contains an
+/// entry named like the service, e.g. when both a proxy and the service
+/// behind it are called `api`.
+pub enum DuplicatePolicy {
+    #[default]
+    /// Keeps both entries, which may be ambiguous.
+    KeepBoth,
+
+    /// Renames the entry of the service, e.g. `api-edge`.
+    RenameOurs(Cow<'static, str>),
+
+    /// Prefixes the upstream entries, e.g. `upstream-` for `upstream-api`.
+    ///
+    /// The prefix must be a token, see [`DuplicatePolicy::prefix_theirs`].
+    PrefixTheirs(Cow<'static, str>),
+
+    /// Drops the upstream entries, adding their durations to the one of the
+    /// service.
+    Sum,
+}
+
+#[derive(Debug, Default)]
+/// What the entry of the service becomes.
+pub(crate) struct Resolved<'p> {
+    /// The name of the entry, if renamed.
+    pub(crate) rename: Option<&'p str>,
+
+    /// The durations to add to the entry.
+    pub(crate) extra: Duration,
+}
+
+impl DuplicatePolicy {
+    /// Creates a [`DuplicatePolicy::PrefixTheirs`], checking that the prefix
+    /// is a valid `Server-Timing` token right away.
+    ///
+    /// Otherwise, the prefixed upstream entries would be malformed.
+    ///
+    /// # Errors
+    ///
+    /// When the prefix isn't a token, e.g. with spaces, `;` or `,`.
+    pub fn prefix_theirs(prefix: impl Into<Cow<'static, str>>) -> Result<Self, InvalidName> {
+        let prefix = prefix.into();
+        if is_token(&prefix) {
+            Ok(Self::PrefixTheirs(prefix))
+        } else {
+            Err(InvalidName(prefix))
+        }
+    }
+
+    /// Checks that the names of the policy, if any, are valid tokens.
+    pub(crate) fn check(&self) -> Result<(), InvalidName> {
+        match self {
+            Self::RenameOurs(name) | Self::PrefixTheirs(name) if !is_token(name) => {
+                Err(InvalidName(name.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the policy to the upstream headers.
+    pub(crate) fn resolve(&self, headers: &mut HeaderMap, app: &str) -> Resolved<'_> {
+        let mut resolved = Resolved::default();
+        if *self == Self::KeepBoth || !headers.contains_key(SERVER_TIMING) {
+            return resolved;
+        }
+
+        let upstream: Vec<_> = headers.get_all(SERVER_TIMING).iter().cloned().collect();
+        let mut rewritten = Vec::with_capacity(upstream.len());
+        let mut found = false;
+
+        for value in upstream {
+            let mut kept = Vec::with_capacity(value.len());
+            let mut changed = false;
+
+            for element in elements(value.as_bytes()) {
+                let duplicate = match parse_header(element).as_deref() {
+                    Ok([entry]) if entry.name() == app => Some(entry.duration()),
+                    _ => None,
+                };
+
+                let Some(dur) = duplicate else {
+                    push_element(&mut kept, &[element]);
+                    continue;
+                };
+
+                found = true;
+                match self {
+                    Self::KeepBoth | Self::RenameOurs(_) => push_element(&mut kept, &[element]),
+                    Self::PrefixTheirs(prefix) => {
+                        changed = true;
+                        push_element(&mut kept, &[prefix.as_bytes(), element]);
+                    }
+                    Self::Sum => {
+                        changed = true;
+                        let dur =
+                            dur.and_then(|dur| Duration::try_from_secs_f64(dur / 1000.0).ok());
+                        resolved.extra = resolved.extra.saturating_add(dur.unwrap_or_default());
+                    }
+                }
+            }
+
+            rewritten.extend(if changed {
+                // Dropped if nothing is left.
+                HeaderValue::from_bytes(&kept)
+                    .ok()
+                    .filter(|_| !kept.is_empty())
+            } else {
+                Some(value)
+            });
+        }
+
+        if let Self::RenameOurs(name) = self {
+            resolved.rename = found.then_some(name.as_ref());
+        } else {
+            headers.remove(SERVER_TIMING);
+            for value in rewritten {
+                // Room was made by removing the values.
+                let _ = headers.try_append(SERVER_TIMING, value);
+            }
+        }
+
+        resolved
+    }
+}
+
+/// Appends a list element, made of the given parts.
+fn push_element(buf: &mut Vec<u8>, parts: &[&[u8]]) {
+    if !buf.is_empty() {
+        buf.extend_from_slice(b", ");
+    }
+
+    for part in parts {
+        buf.extend_from_slice(part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue};
+
+    use super::DuplicatePolicy;
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "server-timing",
+            HeaderValue::from_static("api;dur=10, db;dur=2, api;desc=\"x, y\";dur=5"),
+        );
+        headers.append("server-timing", HeaderValue::from_static("api;dur=1"));
+        headers.append("server-timing", HeaderValue::from_static("bad bad"));
+        headers
+    }
+
+    fn values(headers: &HeaderMap) -> Vec<&str> {
+        headers
+            .get_all("server-timing")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keep_both() {
+        let mut headers = headers();
+        let resolved = DuplicatePolicy::KeepBoth.resolve(&mut headers, "api");
+        assert_eq!(resolved.rename, None);
+        assert_eq!(resolved.extra, Duration::ZERO);
+        assert_eq!(values(&headers), values(&self::headers()));
+    }
+
+    #[test]
+    fn rename_ours() {
+        let policy = DuplicatePolicy::RenameOurs("api-edge".into());
+
+        let mut headers = headers();
+        let resolved = policy.resolve(&mut headers, "api");
+        assert_eq!(resolved.rename, Some("api-edge"));
+        assert_eq!(values(&headers), values(&self::headers()));
+
+        let resolved = policy.resolve(&mut headers, "web");
+        assert_eq!(resolved.rename, None);
+    }
+
+    #[test]
+    fn prefix_theirs() {
+        let mut headers = headers();
+        DuplicatePolicy::PrefixTheirs("upstream-".into()).resolve(&mut headers, "api");
+        assert_eq!(
+            values(&headers),
+            [
+                "upstream-api;dur=10, db;dur=2, upstream-api;desc=\"x, y\";dur=5",
+                "upstream-api;dur=1",
+                "bad bad"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_prefix() {
+        for prefix in ["", "up stream-", "up;", "a,b"] {
+            let e = DuplicatePolicy::prefix_theirs(prefix).unwrap_err();
+            assert_eq!(e.name(), prefix);
+            DuplicatePolicy::PrefixTheirs(prefix.into())
+                .check()
+                .unwrap_err();
+        }
+
+        let policy = DuplicatePolicy::prefix_theirs("upstream-").unwrap();
+        assert_eq!(policy, DuplicatePolicy::PrefixTheirs("upstream-".into()));
+        policy.check().unwrap();
+        DuplicatePolicy::RenameOurs("api edge".into())
+            .check()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn sum() {
+        let mut headers = headers();
+        let resolved = DuplicatePolicy::Sum.resolve(&mut headers, "api");
+        assert_eq!(resolved.extra, Duration::from_millis(16));
+        assert_eq!(values(&headers), ["db;dur=2", "bad bad"]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Options depending on the request method.
+
+use std::sync::{Arc, OnceLock};
+
+use http::Method;
+
+use crate::{Config, Preset, SharedConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+/// What changes for the requests of a given method, see
+/// [`ServerTimingLayer::with_method_policy`](crate::ServerTimingLayer::with_method_policy).
+pub enum MethodPolicy {
+    /// Skips the middleware altogether, e.g. for `OPTIONS` preflights which
+    /// are just noise.
+    Skip,
+
+    /// Overrides the number of decimal places, e.g. fewer for `GET`.
+    Precision(u8),
+
+    /// Applies a preset on top of the options, e.g. [`Preset::Verbose`] for
+    /// the full detail on `POST`.
+    Preset(Preset),
+}
+
+#[derive(Debug)]
+/// The method policies of the layer, along with the options they derive.
+pub(crate) struct Methods {
+    /// The policies, applied in order.
+    policies: Vec<(Method, MethodPolicy)>,
+
+    /// The options of the methods with policies other than
+    /// [`MethodPolicy::Skip`], derived on first use.
+    derived: OnceLock<Vec<(Method, SharedConfig)>>,
+}
+
+impl Methods {
+    #[inline]
+    pub(crate) const fn new() -> Self {
+        Self {
+            policies: Vec::new(),
+            derived: OnceLock::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, method: Method, policy: MethodPolicy) {
+        self.policies.push((method, policy));
+        self.reset();
+    }
+
+    #[inline]
+    /// Drops the derived options, to be called when the options change.
+    pub(crate) fn reset(&mut self) {
+        self.derived.take();
+    }
+
+    /// Returns whether the requests of the given method are skipped.
+    pub(crate) fn skips(&self, method: &Method) -> bool {
+        self.policies
+            .iter()
+            .any(|(m, policy)| m == method && *policy == MethodPolicy::Skip)
+    }
+
+    /// Returns the options for the requests of the given method, if derived
+    /// from the given ones owning the policies.
+    pub(crate) fn resolve(&self, method: &Method, config: &Config) -> Option<&SharedConfig> {
+        if self.policies.is_empty() {
+            return None;
+        }
+
+        self.derived
+            .get_or_init(|| self.derive(config))
+            .iter()
+            .find(|(m, _)| m == method)
+            .map(|(_, config)| config)
+    }
+
+    /// Derives the options of every method with policies from the given ones.
+    fn derive(&self, config: &Config) -> Vec<(Method, SharedConfig)> {
+        let mut derived: Vec<(Method, Config)> = Vec::new();
+
+        for (method, policy) in &self.policies {
+            if *policy == MethodPolicy::Skip {
+                continue;
+            }
+
+            let index = match derived.iter().position(|(m, _)| m == method) {
+                Some(index) => index,
+                None => {
+                    derived.push((method.clone(), config.clone()));
+                    derived.len() - 1
+                }
+            };
+
+            let config = &mut derived[index].1;
+            match *policy {
+                MethodPolicy::Skip => {}
+                MethodPolicy::Precision(precision) => config.precision = precision,
+                MethodPolicy::Preset(preset) => preset.apply(config),
+            }
+        }
+
+        derived
+            .into_iter()
+            .map(|(method, config)| (method, SharedConfig::Arc(Arc::new(config))))
+            .collect()
+    }
+}
+
+impl Clone for Methods {
+    fn clone(&self) -> Self {
+        // The derived options are only valid for the options owning them.
+        Self {
+            policies: self.policies.clone(),
+            derived: OnceLock::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Method;
+
+    use super::{MethodPolicy, Methods};
+    use crate::{Config, Preset};
+
+    #[test]
+    fn resolve() {
+        let mut methods = Methods::new();
+        methods.push(Method::OPTIONS, MethodPolicy::Skip);
+        methods.push(Method::GET, MethodPolicy::Precision(0));
+        methods.push(Method::POST, MethodPolicy::Preset(Preset::Verbose));
+        methods.push(Method::POST, MethodPolicy::Precision(2));
+
+        assert!(methods.skips(&Method::OPTIONS));
+        assert!(!methods.skips(&Method::GET));
+
+        let config = Config::new("app");
+        assert_eq!(methods.resolve(&Method::GET, &config).unwrap().precision, 0);
+        let post = methods.resolve(&Method::POST, &config).unwrap();
+        assert_eq!(post.precision, 2);
+        assert!(post.start_offsets);
+        assert!(methods.resolve(&Method::PUT, &config).is_none());
+        assert!(methods.resolve(&Method::OPTIONS, &config).is_none());
+
+        let cloned = methods.clone();
+        assert!(cloned.derived.get().is_none());
+        assert!(cloned.skips(&Method::OPTIONS));
+    }
+}
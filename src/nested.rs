@@ -0,0 +1,52 @@
+//! Instances of the middleware applied more than once in the same stack.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+/// What an instance of the middleware does when an outer one is already
+/// timing the request, e.g. when the same layer is applied to both a nested
+/// router and the router it's nested in.
+pub enum NestedPolicy {
+    #[default]
+    /// Adds its own entry, after the one of the outer instance.
+    Keep,
+
+    /// Leaves the header to the outer instance and only joins its
+    /// [`ServerTimingHandle`](crate::ServerTimingHandle), so that metrics
+    /// recorded deeper in the stack are reported once, after the entry of the
+    /// outermost instance.
+    Collapse,
+
+    /// Adds its own entry, suffixed with its depth in the stack, e.g. `api-2`
+    /// for the first nested instance of `api`, so that the entries can be told
+    /// apart.
+    Rename,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The request extension marking how many instances of the middleware time
+/// the request.
+pub(crate) struct Depth(pub(crate) u32);
+
+impl NestedPolicy {
+    /// Returns the name of the entry of an instance at the given depth, if
+    /// renamed.
+    pub(crate) fn rename(self, app: &str, depth: u32) -> Option<String> {
+        (self == Self::Rename && depth > 1).then(|| format!("{app}-{depth}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NestedPolicy;
+
+    #[test]
+    fn rename() {
+        assert_eq!(NestedPolicy::Rename.rename("api", 1), None);
+        assert_eq!(
+            NestedPolicy::Rename.rename("api", 2).as_deref(),
+            Some("api-2")
+        );
+        assert_eq!(NestedPolicy::Keep.rename("api", 2), None);
+        assert_eq!(NestedPolicy::Collapse.rename("api", 3), None);
+    }
+}
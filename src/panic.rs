@@ -0,0 +1,134 @@
+//! Responses to panics of the inner service.
+
+use std::{
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http::{Response, StatusCode};
+
+use crate::TimingMetric;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The response extension marking the responses to panics of the handler,
+/// which get a `panic` marker, e.g. `app;dur=12.3, panic`.
+///
+/// [`ServerTimingLayer::with_catch_panic`] inserts it, and so should the
+/// custom response of `tower_http::catch_panic` when used instead, so that
+/// panics are caught only once:
+///
+/// ```rust,ignore
+/// use tower_http::catch_panic::CatchPanicLayer;
+///
+/// let app = router
+///     // Inner, so that the middleware sees the response.
+///     .layer(CatchPanicLayer::custom(|_| {
+///         let mut response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+///         response.extensions_mut().insert(Panicked);
+///         response
+///     }))
+///     .layer(ServerTimingLayer::new("app"));
+/// ```
+///
+/// With `CatchPanicLayer` applied outside of the middleware instead, the panic
+/// unwinds through it before being caught, leaving no response to time:
+/// enable [`ServerTimingLayer::with_catch_panic`] then, which responds first
+/// and leaves nothing for `CatchPanicLayer` to catch.
+///
+/// [`ServerTimingLayer::with_catch_panic`]: crate::ServerTimingLayer::with_catch_panic
+pub struct Panicked;
+
+impl Panicked {
+    #[inline]
+    /// Returns the `panic` marker.
+    pub(crate) fn marker() -> TimingMetric {
+        TimingMetric::new("panic")
+    }
+}
+
+/// Polls the given future, responding with `500 Internal Server Error` if it
+/// panics, like `tower_http::catch_panic`.
+///
+/// The future must not be polled again after a panic, which the caller
+/// ensures by completing with the response.
+pub(crate) fn poll_catching<F, B, E>(
+    inner: Pin<&mut F>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<Response<B>, E>>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    match catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+        Ok(poll) => poll,
+        Err(payload) => {
+            #[cfg(feature = "feat-tracing")]
+            tracing::error!("Inner service panicked: {}", message(&*payload));
+            #[cfg(not(feature = "feat-tracing"))]
+            drop(payload);
+
+            let mut response = Response::new(B::default());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response.extensions_mut().insert(Panicked);
+            Poll::Ready(Ok(response))
+        }
+    }
+}
+
+#[cfg(feature = "feat-tracing")]
+/// Returns the message of the given panic payload, if a string.
+fn message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::{poll_fn, ready},
+        panic::resume_unwind,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    use http::{Response, StatusCode};
+
+    use super::{poll_catching, Panicked};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn catch() {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+
+        let fut = pin!(ready(Ok::<_, ()>(Response::new("ok"))));
+        let response = match poll_catching(fut, &mut cx) {
+            Poll::Ready(response) => response.unwrap(),
+            Poll::Pending => Response::default(),
+        };
+        assert_eq!(*response.body(), "ok");
+        assert!(response.extensions().get::<Panicked>().is_none());
+
+        let fut = pin!(poll_fn(|_| -> Poll<Result<Response<&str>, ()>> {
+            resume_unwind(Box::new("boom"))
+        }));
+        let response = match poll_catching(fut, &mut cx) {
+            Poll::Ready(response) => response.unwrap(),
+            Poll::Pending => Response::default(),
+        };
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(*response.body(), "");
+        assert!(response.extensions().get::<Panicked>().is_some());
+    }
+}
@@ -0,0 +1,85 @@
+//! Extra params of the entry of the service.
+
+use std::{fmt, sync::Arc};
+
+use http::Response;
+
+use crate::parse::{is_token, push_param};
+
+/// The signature of [`ParamFn`].
+type Params = dyn Fn(&Response<()>) -> Vec<(&'static str, String)> + Send + Sync;
+
+#[derive(Clone)]
+/// Callback returning extra params of the entry of the service, based on the
+/// response.
+pub(crate) struct ParamFn(Arc<Params>);
+
+impl ParamFn {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Response<()>) -> Vec<(&'static str, String)> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    /// Appends the params returned for the given response, skipping the ones
+    /// with invalid names.
+    pub(crate) fn encode<B: Default>(&self, buf: &mut Vec<u8>, response: &mut Response<B>) {
+        // Only the body is swapped, which the callback can't see anyway.
+        let (parts, body) = std::mem::take(response).into_parts();
+        let view = Response::from_parts(parts, ());
+
+        for (name, value) in (self.0)(&view) {
+            if is_token(name) {
+                push_param(buf, name, value.as_bytes());
+            } else {
+                #[cfg(feature = "feat-tracing")]
+                tracing::warn!("Invalid `server-timing` param name: {name:?}");
+            }
+        }
+
+        *response = Response::from_parts(view.into_parts().0, body);
+    }
+}
+
+impl fmt::Debug for ParamFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ParamFn(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, Response};
+
+    use super::ParamFn;
+
+    #[test]
+    fn encode() {
+        let params = ParamFn::new(|response| {
+            let cache = response
+                .headers()
+                .get("x-cache")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("none");
+            vec![
+                ("cache", cache.to_owned()),
+                ("shard", "eu west".to_owned()),
+                ("bad name", "1".to_owned()),
+                ("flag", String::new()),
+            ]
+        });
+
+        let mut response = Response::new("body");
+        response
+            .headers_mut()
+            .insert("x-cache", HeaderValue::from_static("HIT"));
+
+        let mut buf = b"app;dur=1.0".to_vec();
+        params.encode(&mut buf, &mut response);
+        assert_eq!(buf, b"app;dur=1.0;cache=HIT;shard=\"eu west\";flag");
+        assert_eq!(*response.body(), "body");
+        assert_eq!(response.headers()["x-cache"], "HIT");
+    }
+}
@@ -0,0 +1,229 @@
+//! Include and exclude rules on request paths.
+
+#[derive(Debug, Clone, Default)]
+/// The compiled include and exclude patterns of the layer.
+pub(crate) struct PathFilter {
+    /// The paths timed, all if empty.
+    include: Vec<Pattern>,
+
+    /// The paths skipped, even if included.
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    #[inline]
+    pub(crate) const fn new() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn include(&mut self, pattern: &str) {
+        self.include.push(Pattern::new(pattern));
+    }
+
+    #[inline]
+    pub(crate) fn exclude(&mut self, pattern: &str) {
+        self.exclude.push(Pattern::new(pattern));
+    }
+
+    /// Returns whether the given path is included and not excluded.
+    pub(crate) fn allows(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(path)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A path pattern, e.g. `/health`, `/api/**` or `/users/*/avatar`.
+enum Pattern {
+    /// Matches the path only, without wildcards.
+    Exact(String),
+
+    /// Matches the path and the ones below it, from a trailing `/**` without
+    /// other wildcards, e.g. `/api` and `/api/users` for `/api/**`.
+    Prefix(String),
+
+    /// Matches the segments of the path, `*` matching any part of a segment
+    /// and a `**` segment any number of segments.
+    Glob(Vec<String>),
+}
+
+impl Pattern {
+    /// Compiles the given pattern.
+    fn new(pattern: &str) -> Self {
+        if !pattern.contains('*') {
+            return Self::Exact(pattern.to_owned());
+        }
+
+        match pattern.strip_suffix("/**") {
+            Some(prefix) if !prefix.contains('*') => Self::Prefix(prefix.to_owned()),
+            _ => Self::Glob(pattern.split('/').map(str::to_owned).collect()),
+        }
+    }
+
+    /// Returns whether the given path matches.
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Exact(exact) => path == exact,
+            Self::Prefix(prefix) => path
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            Self::Glob(segments) => glob_segments(segments, path),
+        }
+    }
+}
+
+/// Returns whether the segments of the given path match the given pattern
+/// ones.
+///
+/// Like usual glob matchers, a mismatch only backtracks to the last `**`,
+/// making it match one more segment, so that the cost stays linear in the
+/// number of segments of untrusted paths for each pattern segment.
+fn glob_segments(pattern: &[String], path: &str) -> bool {
+    let mut segments = path.split('/');
+    let mut i = 0;
+
+    // The pattern index after the last `**`, and the segments from where it
+    // stops matching.
+    let mut any = None;
+
+    loop {
+        let mut next = segments.clone();
+        let Some(segment) = next.next() else {
+            return pattern[i..].iter().all(|segment| segment == "**");
+        };
+
+        match pattern.get(i) {
+            Some(glob_any) if glob_any == "**" => {
+                i += 1;
+                any = Some((i, segments.clone()));
+            }
+            Some(glob_segment) if glob(glob_segment, segment) => {
+                i += 1;
+                segments = next;
+            }
+            _ => {
+                let Some((after, skipped)) = &mut any else {
+                    return false;
+                };
+
+                skipped.next();
+                segments = skipped.clone();
+                i = *after;
+            }
+        }
+    }
+}
+
+/// Returns whether the given segment matches the given pattern, `*` matching
+/// any part of it.
+fn glob(pattern: &str, segment: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = segment.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    // Without `*`, the segment is the pattern itself.
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob, PathFilter, Pattern};
+
+    #[test]
+    fn compile() {
+        assert_eq!(Pattern::new("/health"), Pattern::Exact("/health".into()));
+        assert_eq!(Pattern::new("/api/**"), Pattern::Prefix("/api".into()));
+        assert_eq!(
+            Pattern::new("/users/*/avatar"),
+            Pattern::Glob(vec!["".into(), "users".into(), "*".into(), "avatar".into()])
+        );
+    }
+
+    #[test]
+    fn matches() {
+        for (pattern, path, expected) in [
+            ("/health", "/health", true),
+            ("/health", "/health/", false),
+            ("/health", "/healthz", false),
+            ("/api/**", "/api", true),
+            ("/api/**", "/api/users/1", true),
+            ("/api/**", "/apis", false),
+            ("/users/*/avatar", "/users/1/avatar", true),
+            ("/users/*/avatar", "/users/1/2/avatar", false),
+            ("/users/*/avatar", "/users/avatar", false),
+            ("/**/*.png", "/static/img/logo.png", true),
+            ("/**/*.png", "/logo.png", true),
+            ("/**/*.png", "/logo.svg", false),
+            ("/v*/users", "/v2/users", true),
+            ("/v*/users", "/api/users", false),
+            ("/**/x/**/y", "/a/x/b/c/y", true),
+            ("/**/x/**/y", "/x/y", true),
+            ("/**/x/**/y", "/y/x", false),
+            ("/**/x/**/y", "/x/y/z", false),
+            ("/api/**/*.json", "/api/v1/users.json", true),
+            ("/api/**/*.json", "/static/users.json", false),
+        ] {
+            assert_eq!(
+                Pattern::new(pattern).matches(path),
+                expected,
+                "{pattern} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn long_path() {
+        let path = "/x".repeat(10_000);
+        let pattern = Pattern::new("/**/x/**/y/**");
+        assert!(!pattern.matches(&path));
+        assert!(pattern.matches(&format!("{path}/y")));
+        assert!(Pattern::new("/**/x/**/x/**").matches(&path));
+    }
+
+    #[test]
+    fn glob_segment() {
+        assert!(glob("*", ""));
+        assert!(glob("*", "abc"));
+        assert!(glob("a*c", "abbc"));
+        assert!(glob("a*b*c", "abc"));
+        assert!(glob("*.tar.*", "x.tar.gz"));
+        assert!(!glob("a*c", "ab"));
+        assert!(!glob("abc", "abcd"));
+        assert!(!glob("a*a", "a"));
+    }
+
+    #[test]
+    fn allows() {
+        let mut filter = PathFilter::new();
+        assert!(filter.allows("/anything"));
+
+        filter.exclude("/health");
+        assert!(filter.allows("/api/users"));
+        assert!(!filter.allows("/health"));
+
+        filter.include("/api/**");
+        filter.exclude("/api/internal/**");
+        assert!(filter.allows("/api/users"));
+        assert!(!filter.allows("/api/internal/metrics"));
+        assert!(!filter.allows("/static/app.js"));
+    }
+}
@@ -0,0 +1,576 @@
+//! Composable decisions on which responses get the header.
+//!
+//! A [`Policy`] is evaluated before the request is passed on, and once more
+//! with the response if it couldn't tell before, e.g. when it depends on the
+//! status. Policies combine with [`Policy::and`], [`Policy::or`] and
+//! [`Policy::not`], and the built-in filters of the layer, i.e.
+//! [`with_include`], [`with_exclude`], [`with_content_type`],
+//! [`with_min_duration`], [`with_sampling`] and [`with_rate_limit`], are
+//! policies too, evaluated along with the ones added with [`with_policy`]:
+//!
+//! ```rust
+//! # use std::time::Duration;
+//! # use http::Method;
+//! # use miku_server_timing::{policy::{self, Policy}, ServerTimingLayer};
+//! // The API, except preflights, and slow or failed responses elsewhere.
+//! let layer = ServerTimingLayer::new("app").with_policy(
+//!     policy::path("/api/**")
+//!         .and(policy::method(Method::OPTIONS).not())
+//!         .or(policy::min_duration(Duration::from_millis(500)))
+//!         .or(policy::status(|status| status.is_server_error())),
+//! );
+//! ```
+//!
+//! [`with_include`]: crate::ServerTimingLayer::with_include
+//! [`with_exclude`]: crate::ServerTimingLayer::with_exclude
+//! [`with_content_type`]: crate::ServerTimingLayer::with_content_type
+//! [`with_min_duration`]: crate::ServerTimingLayer::with_min_duration
+//! [`with_sampling`]: crate::ServerTimingLayer::with_sampling
+//! [`with_rate_limit`]: crate::ServerTimingLayer::with_rate_limit
+//! [`with_policy`]: crate::ServerTimingLayer::with_policy
+//!
+//! [`Sampling`](crate::Sampling) and [`HeaderRateLimit`]
+//! combine like any other policy, e.g. `Sampling::new(0.01).or(policy::path("/checkout"))`.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode, Uri};
+
+use crate::{
+    content_type::ContentTypes, path::PathFilter, sample::TenantSampling, HeaderRateLimit,
+};
+
+#[derive(Debug, Clone, Copy)]
+/// What a [`Policy`] decides on: the request, and the response once known.
+pub struct Exchange<'a> {
+    /// The request method.
+    method: &'a Method,
+
+    /// The request URI.
+    uri: &'a Uri,
+
+    /// The request headers.
+    headers: &'a HeaderMap,
+
+    /// The route, i.e. the path matched by the router if known, or the
+    /// request path.
+    route: &'a str,
+
+    /// The tenant, once known.
+    tenant: Option<&'a str>,
+
+    /// The response, once known.
+    response: Option<Outcome<'a>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The response part of an [`Exchange`].
+struct Outcome<'a> {
+    /// The response status.
+    status: StatusCode,
+
+    /// The response headers.
+    headers: &'a HeaderMap,
+
+    /// The total duration.
+    elapsed: Duration,
+}
+
+impl<'a> Exchange<'a> {
+    #[inline]
+    /// Creates a new [`Exchange`] before the response.
+    pub(crate) fn request(method: &'a Method, uri: &'a Uri, headers: &'a HeaderMap) -> Self {
+        Self {
+            method,
+            uri,
+            headers,
+            route: uri.path(),
+            tenant: None,
+            response: None,
+        }
+    }
+
+    #[inline]
+    /// Sets the route, the request path by default.
+    pub(crate) const fn with_route(mut self, route: &'a str) -> Self {
+        self.route = route;
+        self
+    }
+
+    #[inline]
+    /// Sets the tenant.
+    pub(crate) const fn with_tenant(mut self, tenant: Option<&'a str>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    #[inline]
+    /// Adds the response.
+    pub(crate) const fn with_response(
+        mut self,
+        status: StatusCode,
+        headers: &'a HeaderMap,
+        elapsed: Duration,
+    ) -> Self {
+        self.response = Some(Outcome {
+            status,
+            headers,
+            elapsed,
+        });
+        self
+    }
+
+    #[inline]
+    /// Returns the request method.
+    pub const fn method(&self) -> &Method {
+        self.method
+    }
+
+    #[inline]
+    /// Returns the request URI.
+    pub const fn uri(&self) -> &Uri {
+        self.uri
+    }
+
+    #[inline]
+    /// Returns the request headers.
+    pub const fn request_headers(&self) -> &HeaderMap {
+        self.headers
+    }
+
+    #[inline]
+    /// Returns the route, i.e. the path matched by the axum router with the
+    /// `feat-router` feature, or the request path.
+    pub const fn route(&self) -> &str {
+        self.route
+    }
+
+    #[inline]
+    /// Returns the tenant, see
+    /// [`ServerTimingLayer::with_tenant`](crate::ServerTimingLayer::with_tenant),
+    /// `None` before the response or without a tenant.
+    pub const fn tenant(&self) -> Option<&str> {
+        self.tenant
+    }
+
+    #[inline]
+    /// Returns the response status, `None` before the response.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.response.map(|response| response.status)
+    }
+
+    #[inline]
+    /// Returns the response headers, `None` before the response.
+    pub fn response_headers(&self) -> Option<&HeaderMap> {
+        self.response.map(|response| response.headers)
+    }
+
+    #[inline]
+    /// Returns the total duration, `None` before the response.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.response.map(|response| response.elapsed)
+    }
+}
+
+/// A decision on whether the middleware times a request and its response
+/// gets the header.
+///
+/// Requests denied before they are passed on skip the middleware altogether,
+/// like with [`with_feature_flag`], while responses denied afterwards just
+/// don't get the header.
+///
+/// Implemented for closures:
+///
+/// ```rust
+/// # use miku_server_timing::{policy::Exchange, ServerTimingLayer};
+/// let layer = ServerTimingLayer::new("app").with_policy(|exchange: &Exchange<'_>| {
+///     Some(
+///         exchange
+///             .uri()
+///             .query()
+///             .is_some_and(|query| query.contains("debug")),
+///     )
+/// });
+/// ```
+///
+/// [`with_feature_flag`]: crate::ServerTimingLayer::with_feature_flag
+pub trait Policy: Send + Sync + 'static {
+    /// Returns whether the response gets the header, or `None` if it can't
+    /// tell yet, i.e. before the response for policies depending on it.
+    ///
+    /// Undecided policies are evaluated again with the response, and allow it
+    /// if still undecided.
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool>;
+
+    #[inline]
+    /// Allows what both policies allow.
+    fn and<P: Policy>(self, other: P) -> And<Self, P>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    #[inline]
+    /// Allows what either policy allows.
+    fn or<P: Policy>(self, other: P) -> Or<Self, P>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    #[inline]
+    /// Allows what the policy denies.
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+impl<F> Policy for F
+where
+    F: Fn(&Exchange<'_>) -> Option<bool> + Send + Sync + 'static,
+{
+    #[inline]
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        self(exchange)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Allows what both policies allow, see [`Policy::and`].
+pub struct And<A, B>(A, B);
+
+impl<A: Policy, B: Policy> Policy for And<A, B> {
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        match (self.0.decide(exchange), self.1.decide(exchange)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Allows what either policy allows, see [`Policy::or`].
+pub struct Or<A, B>(A, B);
+
+impl<A: Policy, B: Policy> Policy for Or<A, B> {
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        match (self.0.decide(exchange), self.1.decide(exchange)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Allows what the policy denies, see [`Policy::not`].
+pub struct Not<P>(P);
+
+impl<P: Policy> Policy for Not<P> {
+    #[inline]
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        self.0.decide(exchange).map(|allowed| !allowed)
+    }
+}
+
+/// Allows the request paths matching the given pattern, see
+/// [`ServerTimingLayer::with_include`](crate::ServerTimingLayer::with_include)
+/// for the syntax.
+pub fn path(pattern: &str) -> impl Policy {
+    let mut paths = PathFilter::new();
+    paths.include(pattern);
+    paths
+}
+
+/// Allows the requests of the given method.
+pub fn method(method: Method) -> impl Policy {
+    move |exchange: &Exchange<'_>| Some(*exchange.method() == method)
+}
+
+/// Allows the responses whose content type matches the given media range,
+/// see
+/// [`ServerTimingLayer::with_content_type`](crate::ServerTimingLayer::with_content_type).
+pub fn content_type(range: &str) -> impl Policy {
+    let mut types = ContentTypes::new();
+    types.push(range);
+    types
+}
+
+/// Allows the responses whose status satisfies the given predicate.
+pub fn status<F>(predicate: F) -> impl Policy
+where
+    F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+{
+    move |exchange: &Exchange<'_>| exchange.status().map(&predicate)
+}
+
+/// Allows the responses completed in at least the given duration.
+pub fn min_duration(min: Duration) -> impl Policy {
+    MinDuration(min)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Allows the responses completed in at least the given duration, all of them
+/// if zero.
+pub(crate) struct MinDuration(pub(crate) Duration);
+
+impl Policy for MinDuration {
+    #[inline]
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        if self.0.is_zero() {
+            return Some(true);
+        }
+
+        exchange.elapsed().map(|elapsed| elapsed >= self.0)
+    }
+}
+
+impl Policy for PathFilter {
+    #[inline]
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        Some(self.allows(exchange.uri().path()))
+    }
+}
+
+impl Policy for ContentTypes {
+    #[inline]
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        if self.is_empty() {
+            return Some(true);
+        }
+
+        exchange
+            .response_headers()
+            .map(|headers| self.allows(headers.get(CONTENT_TYPE)))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The request part of an [`Exchange`], kept until the response when the
+/// policies can't tell before.
+pub(crate) struct Parts {
+    /// The request method.
+    method: Method,
+
+    /// The request URI.
+    uri: Uri,
+
+    /// The request headers.
+    headers: HeaderMap,
+
+    /// The route.
+    route: String,
+}
+
+impl Parts {
+    #[inline]
+    /// Keeps the request part of the given [`Exchange`].
+    pub(crate) fn capture(exchange: &Exchange<'_>) -> Self {
+        Self {
+            method: exchange.method.clone(),
+            uri: exchange.uri.clone(),
+            headers: exchange.headers.clone(),
+            route: exchange.route.to_owned(),
+        }
+    }
+
+    #[inline]
+    /// Returns the [`Exchange`] of the request, before the response.
+    pub(crate) fn exchange(&self) -> Exchange<'_> {
+        Exchange::request(&self.method, &self.uri, &self.headers).with_route(&self.route)
+    }
+}
+
+#[derive(Clone)]
+/// The policies set on the layer, all of which must allow: the built-in
+/// filters, then the added policies, then sampling and rate limiting.
+pub(crate) struct Policies {
+    /// The request paths the middleware is enabled for.
+    pub(crate) paths: PathFilter,
+
+    /// The content types of the responses getting the header.
+    pub(crate) content_types: ContentTypes,
+
+    /// Responses completed faster than this don't get the header.
+    pub(crate) min_duration: MinDuration,
+
+    /// The policies added with
+    /// [`ServerTimingLayer::with_policy`](crate::ServerTimingLayer::with_policy).
+    pub(crate) added: Vec<Arc<dyn Policy>>,
+
+    /// Which requests get the header, per tenant.
+    pub(crate) sampling: TenantSampling,
+
+    /// An optional cap on the responses getting the header per route or
+    /// tenant.
+    pub(crate) rate_limit: Option<HeaderRateLimit>,
+}
+
+impl Policies {
+    #[inline]
+    pub(crate) const fn new() -> Self {
+        Self {
+            paths: PathFilter::new(),
+            content_types: ContentTypes::new(),
+            min_duration: MinDuration(Duration::ZERO),
+            added: Vec::new(),
+            sampling: TenantSampling::new(),
+            rate_limit: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, policy: impl Policy) {
+        self.added.push(Arc::new(policy));
+    }
+}
+
+impl Default for Policies {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Policy for Policies {
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        let built_in: [&dyn Policy; 3] = [&self.paths, &self.content_types, &self.min_duration];
+        let policies = built_in
+            .into_iter()
+            .chain(self.added.iter().map(|policy| &**policy))
+            .chain([&self.sampling as &dyn Policy])
+            // Last, so that only the responses which would get the header
+            // take from the budget.
+            .chain(self.rate_limit.iter().map(|policy| policy as &dyn Policy));
+
+        let mut decided = Some(true);
+        for policy in policies {
+            match policy.decide(exchange) {
+                Some(false) => return Some(false),
+                Some(true) => {}
+                None => decided = None,
+            }
+        }
+
+        decided
+    }
+}
+
+impl fmt::Debug for Policies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Policies")
+            .field("paths", &self.paths)
+            .field("content_types", &self.content_types)
+            .field("min_duration", &self.min_duration)
+            .field("added", &self.added.len())
+            .field("sampling", &self.sampling)
+            .field("rate_limit", &self.rate_limit)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+
+    use super::{Exchange, Policies, Policy};
+    use crate::{HeaderRateLimit, Sampling};
+
+    #[test]
+    fn combinators() {
+        let policy = super::path("/api/**")
+            .and(super::method(Method::OPTIONS).not())
+            .or(super::status(|status| status.is_server_error()));
+
+        let uri = Uri::from_static("/api/users");
+        let headers = HeaderMap::new();
+        let exchange = Exchange::request(&Method::GET, &uri, &headers);
+        assert_eq!(policy.decide(&exchange), Some(true));
+        let exchange = Exchange::request(&Method::OPTIONS, &uri, &headers);
+        assert_eq!(policy.decide(&exchange), None);
+        let exchange = exchange.with_response(StatusCode::OK, &headers, Duration::ZERO);
+        assert_eq!(policy.decide(&exchange), Some(false));
+
+        let uri = Uri::from_static("/static/app.js");
+        let exchange = Exchange::request(&Method::GET, &uri, &headers);
+        assert_eq!(policy.decide(&exchange), None);
+        let failed = exchange.with_response(StatusCode::BAD_GATEWAY, &headers, Duration::ZERO);
+        assert_eq!(policy.decide(&failed), Some(true));
+    }
+
+    #[test]
+    fn built_in() {
+        let uri = Uri::from_static("/");
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("image/png"));
+        let request_headers = HeaderMap::new();
+        let exchange = Exchange::request(&Method::GET, &uri, &request_headers);
+        let response = exchange.with_response(StatusCode::OK, &headers, Duration::from_millis(5));
+
+        let policy = super::content_type("text/html");
+        assert_eq!(policy.decide(&exchange), None);
+        assert_eq!(policy.decide(&response), Some(false));
+
+        let policy = super::min_duration(Duration::from_millis(5));
+        assert_eq!(policy.decide(&exchange), None);
+        assert_eq!(policy.decide(&response), Some(true));
+        assert_eq!(policy.not().decide(&response), Some(false));
+    }
+
+    #[test]
+    fn all() {
+        let uri = Uri::from_static("/api");
+        let headers = HeaderMap::new();
+        let exchange = Exchange::request(&Method::GET, &uri, &headers);
+        let response = exchange.with_response(StatusCode::OK, &headers, Duration::ZERO);
+
+        let mut policies = Policies::new();
+        assert_eq!(policies.decide(&exchange), Some(true));
+
+        policies.push(super::path("/api"));
+        policies.push(super::status(|status| status.is_success()));
+        assert_eq!(policies.decide(&exchange), None);
+        assert_eq!(policies.decide(&response), Some(true));
+
+        policies.push(super::method(Method::POST));
+        assert_eq!(policies.decide(&exchange), Some(false));
+    }
+
+    #[test]
+    fn sampling_and_rate_limit() {
+        let uri = Uri::from_static("/api");
+        let headers = HeaderMap::new();
+        let exchange = Exchange::request(&Method::GET, &uri, &headers).with_route("/:id");
+        let response = exchange.with_response(StatusCode::OK, &headers, Duration::ZERO);
+        assert_eq!(response.route(), "/:id");
+
+        let mut policies = Policies::new();
+        policies.sampling.default = Some(Sampling::new(0.0));
+        assert_eq!(policies.decide(&exchange), None);
+        assert_eq!(policies.decide(&response), Some(false));
+
+        policies.sampling.set("acme".to_owned(), Sampling::new(1.0));
+        assert_eq!(policies.decide(&response), Some(false));
+        let acme = response.with_tenant(Some("acme"));
+        assert_eq!(policies.decide(&acme), Some(true));
+
+        policies.rate_limit = Some(HeaderRateLimit::per_route(1));
+        assert_eq!(policies.decide(&acme), Some(true));
+        assert_eq!(policies.decide(&acme), Some(false));
+
+        // Combined like any other policy.
+        let policy = Sampling::new(0.0).or(super::path("/api"));
+        assert_eq!(policy.decide(&response), Some(true));
+        let policy = HeaderRateLimit::per_tenant(0).not();
+        assert_eq!(policy.decide(&exchange), None);
+        assert_eq!(policy.decide(&response), Some(true));
+    }
+}
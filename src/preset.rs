@@ -0,0 +1,84 @@
+//! Bundles of options for common setups.
+
+use http::HeaderValue;
+
+use crate::{Config, NestedPolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+/// A sensible combination of options, see
+/// [`ServerTimingLayer::with_preset`](crate::ServerTimingLayer::with_preset).
+pub enum Preset {
+    /// The bare minimum, e.g. `app;dur=12`: whole milliseconds, no entry for
+    /// nested instances nor `404 Not Found` responses of unmatched routes.
+    Minimal,
+
+    /// What browser devtools and RUM scripts make the most of, e.g.
+    /// `app;dur=12.3, db;dur=4.5;start=1.2, cdn-cache;desc="HIT"`: start
+    /// offsets for waterfalls, cache and timeout markers, renamed nested
+    /// entries, and `Timing-Allow-Origin: *` so that cross-origin pages can
+    /// read the timings too.
+    Browser,
+
+    /// Everything useful when debugging: microseconds, start offsets, every
+    /// marker, the protocol, the `route` and `handler` phases and sequence
+    /// numbers.
+    Verbose,
+}
+
+impl Preset {
+    /// Applies the options of the preset.
+    pub(crate) fn apply(self, config: &mut Config) {
+        match self {
+            Self::Minimal => {
+                config.precision = 0;
+                config.nested = NestedPolicy::Collapse;
+                config.not_found = false;
+            }
+            Self::Browser => {
+                config.precision = 1;
+                config.start_offsets = true;
+                config.timeout_marker = true;
+                config.cache_markers = true;
+                config.nested = NestedPolicy::Rename;
+                config.timing_allow_origin = Some(HeaderValue::from_static("*"));
+            }
+            Self::Verbose => {
+                config.precision = 3;
+                config.start_offsets = true;
+                config.timeout_marker = true;
+                config.clamp_marker = true;
+                config.cache_markers = true;
+                config.protocol = true;
+                config.phases = true;
+                config.sequence = true;
+                config.nested = NestedPolicy::Rename;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Preset;
+    use crate::{Config, NestedPolicy};
+
+    #[test]
+    fn apply() {
+        let mut config = Config::new("app");
+        Preset::Minimal.apply(&mut config);
+        assert_eq!(config.precision, 0);
+        assert_eq!(config.nested, NestedPolicy::Collapse);
+        assert!(!config.not_found);
+
+        let mut config = Config::new("app");
+        Preset::Browser.apply(&mut config);
+        assert!(config.start_offsets);
+        assert_eq!(config.timing_allow_origin.as_ref().unwrap(), "*");
+
+        let mut config = Config::new("app");
+        Preset::Verbose.apply(&mut config);
+        assert_eq!(config.precision, 3);
+        assert!(config.phases && config.protocol && config.sequence);
+    }
+}
@@ -0,0 +1,61 @@
+//! Redaction of sensitive descriptions.
+
+use std::{fmt, sync::Arc};
+
+use crate::TimingMetric;
+
+#[derive(Clone)]
+/// A hook rewriting the descriptions of metrics before they are serialized.
+pub(crate) struct Redact(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl Redact {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    /// Rewrites the descriptions of the given metrics.
+    pub(crate) fn apply(&self, metrics: Vec<TimingMetric>) -> Vec<TimingMetric> {
+        metrics
+            .into_iter()
+            .map(
+                |metric| match metric.description().map(|desc| (self.0)(desc)) {
+                    Some(desc) => metric.with_description(desc),
+                    None => metric,
+                },
+            )
+            .collect()
+    }
+}
+
+impl fmt::Debug for Redact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Redact(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redact;
+    use crate::TimingMetric;
+
+    #[test]
+    fn apply() {
+        let redact = Redact::new(|desc| match desc.split_once('?') {
+            Some((path, _)) => format!("{path}?.."),
+            None => desc.to_owned(),
+        });
+
+        let metrics = redact.apply(vec![
+            TimingMetric::new("fetch").with_description("/users?email=miku@example.com"),
+            TimingMetric::new("db").with_description("users"),
+            TimingMetric::new("cache"),
+        ]);
+        assert_eq!(metrics[0].description(), Some("/users?.."));
+        assert_eq!(metrics[1].description(), Some("users"));
+        assert_eq!(metrics[2].description(), None);
+    }
+}
@@ -0,0 +1,476 @@
+//! A set of custom metrics.
+
+#[cfg(feature = "feat-layer")]
+mod hook;
+
+use std::{borrow::Cow, fmt::Write, slice};
+
+use http::{header::InvalidHeaderValue, HeaderName, HeaderValue, Method, StatusCode};
+use server_timing_core::DEFAULT_PRECISION;
+
+#[cfg(feature = "feat-layer")]
+pub(crate) use self::hook::{inspect, AppFn, Enrich, OnTiming, RequestMeta, TenantFn};
+use crate::{
+    diff::{self, TimingDiff},
+    TimingMetric,
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A set of custom metrics, serialized as the value of the `Server-Timing`
+/// header, e.g. `db;dur=12.3, cache;desc="hit"`.
+///
+/// Reports passed to the [`on_timing`] callback also tell which request they
+/// belong to, for exporters.
+///
+/// [`on_timing`]: crate::ServerTimingLayer::with_on_timing
+pub struct TimingReport {
+    /// The recorded metrics, in order.
+    metrics: Vec<TimingMetric>,
+
+    /// The request method, if known.
+    method: Option<Method>,
+
+    /// The request path, if known.
+    route: Option<String>,
+
+    /// The selected request headers.
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Labels segmenting the request, e.g. by client country.
+    labels: Vec<(String, String)>,
+
+    /// The tenant the request belongs to, if known.
+    tenant: Option<String>,
+
+    /// The response status, if known.
+    status: Option<StatusCode>,
+}
+
+impl TimingReport {
+    #[inline]
+    /// Creates a new empty [`TimingReport`].
+    pub const fn new() -> Self {
+        Self {
+            metrics: Vec::new(),
+            method: None,
+            route: None,
+            headers: Vec::new(),
+            labels: Vec::new(),
+            tenant: None,
+            status: None,
+        }
+    }
+
+    #[inline]
+    /// Adds a metric.
+    pub fn push(&mut self, metric: TimingMetric) {
+        self.metrics.push(metric);
+    }
+
+    #[inline]
+    /// Adds a metric, for chaining.
+    pub fn with(mut self, metric: TimingMetric) -> Self {
+        self.metrics.push(metric);
+        self
+    }
+
+    #[inline]
+    /// Returns the recorded metrics.
+    pub fn metrics(&self) -> &[TimingMetric] {
+        &self.metrics
+    }
+
+    #[inline]
+    /// Returns an iterator over the recorded metrics, in order.
+    pub fn iter(&self) -> slice::Iter<'_, TimingMetric> {
+        self.metrics.iter()
+    }
+
+    /// Compares the durations of the metrics of the report with the ones of
+    /// `other`, e.g. a baseline with a canary.
+    ///
+    /// Metrics are matched by full name, and the durations of metrics
+    /// recorded more than once are summed:
+    ///
+    /// ```rust
+    /// # use miku_server_timing::{TimingMetric, TimingReport};
+    /// let baseline = TimingReport::new()
+    ///     .with(TimingMetric::new("db").with_millis(12.0))
+    ///     .with(TimingMetric::new("render").with_millis(3.0));
+    /// let canary = TimingReport::new()
+    ///     .with(TimingMetric::new("db").with_millis(20.0))
+    ///     .with(TimingMetric::new("render").with_millis(2.5));
+    ///
+    /// let diff = baseline.diff(&canary);
+    /// assert_eq!(diff.get("db").unwrap().delta(), Some(8.0));
+    /// assert_eq!(diff.regressions(5.0).count(), 1);
+    /// ```
+    pub fn diff(&self, other: &TimingReport) -> TimingDiff {
+        diff::diff(self, other)
+    }
+
+    #[inline]
+    /// Sets the request method.
+    pub fn with_method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    #[inline]
+    /// Sets the request path.
+    pub fn with_route(mut self, route: impl Into<String>) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+
+    #[inline]
+    /// Adds a request header.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    #[inline]
+    /// Adds a label, e.g. `country=FR`.
+    pub fn with_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
+
+    #[inline]
+    /// Sets the tenant the request belongs to.
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    #[inline]
+    /// Sets the response status.
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    #[inline]
+    /// Returns the request method, if known.
+    pub fn method(&self) -> Option<&Method> {
+        self.method.as_ref()
+    }
+
+    #[inline]
+    /// Returns the request path, if known.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    #[inline]
+    /// Returns the selected request headers, in order.
+    pub fn headers(&self) -> &[(HeaderName, HeaderValue)] {
+        &self.headers
+    }
+
+    #[inline]
+    /// Returns the value of the given request header, if selected.
+    pub fn header(&self, name: impl AsRef<str>) -> Option<&HeaderValue> {
+        let name = name.as_ref();
+        self.headers
+            .iter()
+            .find(|(key, _)| key.as_str().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    #[inline]
+    /// Returns the labels, in order.
+    pub fn labels(&self) -> &[(String, String)] {
+        &self.labels
+    }
+
+    #[inline]
+    /// Returns the value of the given label, if any.
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[inline]
+    /// Returns the tenant the request belongs to, if known.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    #[inline]
+    /// Returns the response status, if known.
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    #[inline]
+    /// Returns whether no metric has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+
+    /// Serializes the metrics as a header value.
+    ///
+    /// # Errors
+    ///
+    /// When names or descriptions contain characters not allowed in header
+    /// values.
+    pub fn to_header_value(&self) -> Result<HeaderValue, InvalidHeaderValue> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        HeaderValue::from_bytes(&buf)
+    }
+
+    /// Serializes the report as JSON, e.g. for exporters:
+    ///
+    /// ```json
+    /// {"method":"GET","route":"/users","headers":{"user-agent":"curl"},"labels":{"country":"FR"},"tenant":"acme","status":200,"metrics":[{"name":"db","dur":12.3,"desc":"query users"}]}
+    /// ```
+    ///
+    /// `dur` and `start` are in milliseconds, and omitted along with the other
+    /// fields if not set. Header values which are not valid UTF-8 are
+    /// converted lossily.
+    pub fn to_json(&self) -> String {
+        let mut buf = String::from("{");
+
+        if let Some(method) = &self.method {
+            buf.push_str("\"method\":");
+            push_json_str(&mut buf, method.as_str());
+            buf.push(',');
+        }
+
+        if let Some(route) = &self.route {
+            buf.push_str("\"route\":");
+            push_json_str(&mut buf, route);
+            buf.push(',');
+        }
+
+        push_json_map(
+            &mut buf,
+            "headers",
+            self.headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes()))),
+        );
+        push_json_map(
+            &mut buf,
+            "labels",
+            self.labels
+                .iter()
+                .map(|(name, value)| (name.as_str(), Cow::Borrowed(value.as_str()))),
+        );
+
+        if let Some(tenant) = &self.tenant {
+            buf.push_str("\"tenant\":");
+            push_json_str(&mut buf, tenant);
+            buf.push(',');
+        }
+
+        if let Some(status) = self.status {
+            let _ = write!(buf, "\"status\":{},", status.as_u16());
+        }
+
+        buf.push_str("\"metrics\":[");
+
+        for (i, metric) in self.metrics.iter().enumerate() {
+            if i > 0 {
+                buf.push(',');
+            }
+
+            buf.push_str("{\"name\":");
+            push_json_str(&mut buf, &metric.full_name());
+
+            if let Some(dur) = metric.millis().filter(|dur| dur.is_finite()) {
+                // Writing to a `String` never fails.
+                let _ = write!(buf, ",\"dur\":{dur}");
+            }
+
+            if let Some(start) = metric.start_millis().filter(|start| start.is_finite()) {
+                let _ = write!(buf, ",\"start\":{start}");
+            }
+
+            if let Some(desc) = metric.description() {
+                buf.push_str(",\"desc\":");
+                push_json_str(&mut buf, desc);
+            }
+
+            buf.push('}');
+        }
+
+        buf.push_str("]}");
+        buf
+    }
+
+    /// Appends the serialized metrics to the given buffer.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        for (i, metric) in self.metrics.iter().enumerate() {
+            if i > 0 {
+                buf.extend_from_slice(b", ");
+            }
+
+            metric.encode(buf, true, DEFAULT_PRECISION);
+        }
+    }
+}
+
+impl From<Vec<TimingMetric>> for TimingReport {
+    fn from(metrics: Vec<TimingMetric>) -> Self {
+        Self {
+            metrics,
+            ..Self::new()
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a TimingReport {
+    type Item = &'a TimingMetric;
+    type IntoIter = slice::Iter<'a, TimingMetric>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<TimingMetric> for TimingReport {
+    fn from_iter<I: IntoIterator<Item = TimingMetric>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+/// Appends the given pairs as a JSON object field, followed by a comma, unless
+/// there are none.
+fn push_json_map<'p>(
+    buf: &mut String,
+    key: &str,
+    pairs: impl ExactSizeIterator<Item = (&'p str, Cow<'p, str>)>,
+) {
+    if pairs.len() == 0 {
+        return;
+    }
+
+    let _ = write!(buf, "\"{key}\":{{");
+
+    for (i, (name, value)) in pairs.enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+
+        push_json_str(buf, name);
+        buf.push(':');
+        push_json_str(buf, &value);
+    }
+
+    buf.push_str("},");
+}
+
+/// Appends the given string as a JSON string.
+pub(crate) fn push_json_str(buf: &mut String, s: &str) {
+    buf.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+
+    buf.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header::USER_AGENT, HeaderName, HeaderValue, Method, StatusCode};
+
+    use super::TimingReport;
+    use crate::TimingMetric;
+
+    #[test]
+    fn to_header_value() {
+        assert_eq!(TimingReport::new().to_header_value().unwrap(), "");
+
+        let report = TimingReport::new()
+            .with(TimingMetric::new("db").with_millis(12.34))
+            .with(TimingMetric::new("cache").with_description("hit"));
+        assert_eq!(
+            report.to_header_value().unwrap(),
+            "db;dur=12.3, cache;desc=\"hit\""
+        );
+    }
+
+    #[test]
+    fn iter() {
+        let report = TimingReport::new()
+            .with(TimingMetric::new("db").with_millis(12.0))
+            .with(TimingMetric::new("cache"));
+
+        let names: Vec<_> = report.iter().map(TimingMetric::name).collect();
+        assert_eq!(names, ["db", "cache"]);
+
+        let mut count = 0;
+        for metric in &report {
+            assert_eq!(metric, &report.metrics()[count]);
+            count += 1;
+        }
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn to_json() {
+        assert_eq!(TimingReport::new().to_json(), r#"{"metrics":[]}"#);
+
+        let report = TimingReport::new()
+            .with(TimingMetric::new("db").with_millis(12.5))
+            .with(TimingMetric::new("cache").with_description("say \"hi\"\n"))
+            .with(TimingMetric::new("nan").with_millis(f64::NAN));
+        assert_eq!(
+            report.to_json(),
+            r#"{"metrics":[{"name":"db","dur":12.5},{"name":"cache","desc":"say \"hi\"\n"},{"name":"nan"}]}"#
+        );
+
+        let report = TimingReport::new()
+            .with_method(Method::GET)
+            .with_route("/users")
+            .with_status(StatusCode::OK);
+        assert_eq!(
+            report.to_json(),
+            r#"{"method":"GET","route":"/users","status":200,"metrics":[]}"#
+        );
+
+        let report = TimingReport::new()
+            .with_header(USER_AGENT, HeaderValue::from_static("curl/8.0"))
+            .with_header(
+                HeaderName::from_static("x-tenant"),
+                HeaderValue::from_bytes(b"a\xff\"").unwrap(),
+            );
+        assert_eq!(
+            report.to_json(),
+            r#"{"headers":{"user-agent":"curl/8.0","x-tenant":"a�\""},"metrics":[]}"#
+        );
+
+        let report = TimingReport::new()
+            .with_label("country", "FR")
+            .with_label("client", "bot");
+        assert_eq!(
+            report.to_json(),
+            r#"{"labels":{"country":"FR","client":"bot"},"metrics":[]}"#
+        );
+
+        let report = TimingReport::new()
+            .with_tenant("acme")
+            .with_status(StatusCode::OK);
+        assert_eq!(
+            report.to_json(),
+            r#"{"tenant":"acme","status":200,"metrics":[]}"#
+        );
+    }
+}
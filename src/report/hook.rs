@@ -0,0 +1,262 @@
+//! Hooks capturing request metadata for the [`on_timing`] callback.
+//!
+//! [`on_timing`]: crate::ServerTimingLayer::with_on_timing
+
+use std::{borrow::Cow, fmt, sync::Arc};
+
+use http::{HeaderName, HeaderValue, Method, Request};
+
+use super::TimingReport;
+
+#[derive(Debug)]
+/// Request metadata captured for the [`on_timing`] callback before the request
+/// is consumed.
+///
+/// [`on_timing`]: crate::ServerTimingLayer::with_on_timing
+pub(crate) struct RequestMeta {
+    /// The request method.
+    method: Method,
+
+    /// The request path.
+    route: String,
+
+    /// The selected request headers.
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// The labels returned by the enrichment hook.
+    labels: Vec<(String, String)>,
+}
+
+impl RequestMeta {
+    /// Captures the metadata of the given request, with the given headers if
+    /// present.
+    pub(crate) fn capture<B>(req: &Request<B>, headers: &[HeaderName]) -> Self {
+        Self {
+            method: req.method().clone(),
+            route: req.uri().path().to_owned(),
+            headers: headers
+                .iter()
+                .flat_map(|name| {
+                    req.headers()
+                        .get_all(name)
+                        .iter()
+                        .map(|value| (name.clone(), value.clone()))
+                })
+                .collect(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Adds the labels returned by the given hook, passing it the request
+    /// without its body.
+    pub(crate) fn enrich<B>(&mut self, req: Request<B>, enrich: &Enrich) -> Request<B> {
+        let (req, labels) = inspect(req, |view| (enrich.0)(view));
+        self.labels.extend(
+            labels
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value)),
+        );
+        req
+    }
+
+    /// Adds the metadata to the given report.
+    pub(crate) fn apply(self, report: TimingReport) -> TimingReport {
+        TimingReport {
+            method: Some(self.method),
+            route: Some(self.route),
+            headers: self.headers,
+            labels: self.labels,
+            ..report
+        }
+    }
+}
+
+/// Passes the given request without its body to the given function.
+pub(crate) fn inspect<B, T>(req: Request<B>, f: impl FnOnce(&Request<()>) -> T) -> (Request<B>, T) {
+    let (parts, body) = req.into_parts();
+    let view = Request::from_parts(parts, ());
+    let output = f(&view);
+    (Request::from_parts(view.into_parts().0, body), output)
+}
+
+/// The signature of [`TenantFn`].
+type Tenant = dyn Fn(&Request<()>) -> Option<String> + Send + Sync;
+
+#[derive(Clone)]
+/// A hook extracting the tenant of the request.
+pub(crate) struct TenantFn(Arc<Tenant>);
+
+impl TenantFn {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Option<String> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    #[inline]
+    /// Extracts the tenant of the given request.
+    pub(crate) fn extract<B>(&self, req: Request<B>) -> (Request<B>, Option<String>) {
+        inspect(req, |view| (self.0)(view))
+    }
+}
+
+impl fmt::Debug for TenantFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TenantFn(..)")
+    }
+}
+
+/// The signature of [`AppFn`].
+type App = dyn Fn(&Request<()>) -> Cow<'static, str> + Send + Sync;
+
+#[derive(Clone)]
+/// A hook resolving the service name per request.
+pub(crate) struct AppFn(Arc<App>);
+
+impl AppFn {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Cow<'static, str> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    #[inline]
+    /// Resolves the service name of the given request.
+    pub(crate) fn resolve<B>(&self, req: Request<B>) -> (Request<B>, Cow<'static, str>) {
+        inspect(req, |view| (self.0)(view))
+    }
+}
+
+impl fmt::Debug for AppFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AppFn(..)")
+    }
+}
+
+/// The signature of [`Enrich`].
+type Labels = dyn Fn(&Request<()>) -> Vec<(&'static str, String)> + Send + Sync;
+
+#[derive(Clone)]
+/// A hook returning labels of the request for the reports.
+pub(crate) struct Enrich(Arc<Labels>);
+
+impl Enrich {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Vec<(&'static str, String)> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for Enrich {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Enrich(..)")
+    }
+}
+
+#[derive(Clone)]
+/// A callback observing the timings of sampled requests.
+pub(crate) struct OnTiming(Arc<dyn Fn(&TimingReport) + Send + Sync>);
+
+impl OnTiming {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&TimingReport) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    #[inline]
+    pub(crate) fn call(&self, report: &TimingReport) {
+        (self.0)(report);
+    }
+}
+
+impl fmt::Debug for OnTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnTiming(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header::USER_AGENT, HeaderName, Method, Request, StatusCode};
+
+    use super::{Enrich, RequestMeta, TenantFn};
+    use crate::TimingReport;
+
+    #[test]
+    fn request_meta() {
+        let req = Request::post("/users?id=1")
+            .header(USER_AGENT, "curl/8.0")
+            .header("x-tenant", "a")
+            .header("x-tenant", "b")
+            .header("x-secret", "s")
+            .body(())
+            .unwrap();
+
+        let tenant = HeaderName::from_static("x-tenant");
+        let missing = HeaderName::from_static("x-missing");
+        let report = RequestMeta::capture(&req, &[USER_AGENT, tenant, missing])
+            .apply(TimingReport::new().with_status(StatusCode::CREATED));
+
+        assert_eq!(report.method(), Some(&Method::POST));
+        assert_eq!(report.route(), Some("/users"));
+        assert_eq!(report.status(), Some(StatusCode::CREATED));
+        assert_eq!(report.headers().len(), 3);
+        assert_eq!(report.header("User-Agent").unwrap(), "curl/8.0");
+        assert_eq!(report.header("x-tenant").unwrap(), "a");
+        assert!(report.header("x-secret").is_none());
+    }
+
+    #[test]
+    fn enrich() {
+        let enrich = Enrich::new(|req| {
+            let client = match req.headers().get(USER_AGENT) {
+                Some(agent) if agent.as_bytes().starts_with(b"curl") => "cli",
+                Some(_) => "browser",
+                None => "unknown",
+            };
+            vec![("client", client.to_owned())]
+        });
+
+        let req = Request::get("/")
+            .header(USER_AGENT, "curl/8.0")
+            .body(1)
+            .unwrap();
+        let mut meta = RequestMeta::capture(&req, &[]);
+        let req = meta.enrich(req, &enrich);
+        assert_eq!(*req.body(), 1);
+        assert_eq!(req.headers()[USER_AGENT], "curl/8.0");
+
+        let report = meta.apply(TimingReport::new());
+        assert_eq!(report.label("client"), Some("cli"));
+        assert_eq!(report.label("country"), None);
+    }
+
+    #[test]
+    fn tenant() {
+        let tenant = TenantFn::new(|req| {
+            let host = req.headers().get("host")?.to_str().ok()?;
+            host.split_once('.').map(|(tenant, _)| tenant.to_owned())
+        });
+
+        let req = Request::get("/")
+            .header("host", "acme.example.com")
+            .body(1)
+            .unwrap();
+        let (req, extracted) = tenant.extract(req);
+        assert_eq!(extracted.as_deref(), Some("acme"));
+        assert_eq!(*req.body(), 1);
+
+        let (_, extracted) = tenant.extract(Request::new(()));
+        assert_eq!(extracted, None);
+    }
+}
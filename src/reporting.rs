@@ -0,0 +1,142 @@
+//! Timing without the header, handing the reports to the caller instead.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use http::{Request, Response};
+use pin_project_lite::pin_project;
+
+use crate::{ResponseFuture, SharedConfig, TimingReport};
+
+#[derive(Debug, Clone)]
+/// A layer timing requests like [`ServerTimingLayer`], but responding with
+/// the [`TimingReport`] alongside the response instead of adding the header,
+/// see [`ServerTimingLayer::into_report_layer`].
+///
+/// [`ServerTimingLayer`]: crate::ServerTimingLayer
+/// [`ServerTimingLayer::into_report_layer`]: crate::ServerTimingLayer::into_report_layer
+pub struct ReportLayer {
+    /// The options of the middleware.
+    config: SharedConfig,
+}
+
+impl ReportLayer {
+    #[inline]
+    pub(crate) const fn new(config: SharedConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl<S> tower_layer::Layer<S> for ReportLayer {
+    type Service = ReportService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ReportService {
+            service,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A service responding with the [`TimingReport`] alongside the response, see
+/// [`ReportLayer`].
+pub struct ReportService<S> {
+    /// The service to wrap.
+    service: S,
+
+    /// The options of the middleware.
+    config: SharedConfig,
+}
+
+impl<S, ReqBody, ResBody> tower_service::Service<Request<ReqBody>> for ReportService<S>
+where
+    S: tower_service::Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = (Response<ResBody>, TimingReport);
+    type Error = S::Error;
+    type Future = ReportFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        ReportFuture {
+            inner: ResponseFuture::start(&self.config, req, |req| self.service.call(req), true),
+        }
+    }
+}
+
+pin_project! {
+    /// A future responding with the [`TimingReport`] alongside the response.
+    pub struct ReportFuture<F> {
+        #[pin]
+        inner: ResponseFuture<F>,
+    }
+}
+
+impl<F, B, E> Future for ReportFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Default,
+{
+    type Output = Result<(Response<B>, TimingReport), E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut response = ready!(self.project().inner.poll(cx))?;
+
+        // Missing if the middleware is disabled for the request.
+        let report = response
+            .extensions_mut()
+            .remove::<TimingReport>()
+            .unwrap_or_default();
+
+        Poll::Ready(Ok((response, report)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http::{Request, Response, StatusCode};
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+
+    use crate::{ServerTimingHandle, ServerTimingLayer, TimingMetric};
+
+    #[tokio::test]
+    async fn report() {
+        let svc = ServerTimingLayer::new("svc1")
+            .with_feature_flag(|req: &Request<()>| req.uri().path() != "/off")
+            .into_report_layer()
+            .layer(service_fn(|req: Request<()>| async move {
+                if let Some(handle) = req.extensions().get::<ServerTimingHandle>() {
+                    handle.record(TimingMetric::new("db").with_millis(1.0));
+                }
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .status(StatusCode::CREATED)
+                        .body(())
+                        .unwrap(),
+                )
+            }));
+
+        let req = Request::builder().uri("/users").body(()).unwrap();
+        let (response, report) = svc.clone().oneshot(req).await.unwrap();
+        assert!(response.headers().get("server-timing").is_none());
+        assert_eq!(report.status(), Some(StatusCode::CREATED));
+        assert_eq!(report.route(), Some("/users"));
+        let names: Vec<_> = report.metrics().iter().map(TimingMetric::name).collect();
+        assert_eq!(names, ["svc1", "db"]);
+
+        let req = Request::builder().uri("/off").body(()).unwrap();
+        let (_, report) = svc.oneshot(req).await.unwrap();
+        assert!(report.metrics().is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+//! Server-Timing without the middleware.
+
+use axum_core::response::{IntoResponse, Response};
+
+use crate::{insert_header, TimingReport};
+
+#[derive(Debug, Clone)]
+/// A response wrapper adding the given [`TimingReport`] to the `Server-Timing`
+/// header of the response, for the few endpoints needing it without
+/// installing [`ServerTimingLayer`](crate::ServerTimingLayer).
+///
+/// Existing `Server-Timing` values of the response are kept, after the
+/// report.
+///
+/// ```rust,ignore
+/// async fn handler() -> Timed<&'static str> {
+///     let start = Instant::now();
+///     // query the database...
+///     let report = TimingReport::new().with(TimingMetric::new("db").with_duration(start.elapsed()));
+///
+///     Timed("Hello, World!", report)
+/// }
+/// ```
+pub struct Timed<T>(pub T, pub TimingReport);
+
+impl<T> IntoResponse for Timed<T>
+where
+    T: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let Timed(inner, report) = self;
+
+        let mut response = inner.into_response();
+
+        if !report.is_empty() {
+            let mut value = Vec::new();
+            report.encode(&mut value);
+
+            if let Err(_e) = insert_header(response.headers_mut(), &mut value, false) {
+                #[cfg(feature = "feat-tracing")]
+                tracing::error!("Failed to add `server-timing` header: {_e:?}");
+            }
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get, Router};
+    use http::{HeaderMap, HeaderValue, Request};
+    use tower::ServiceExt;
+
+    use super::Timed;
+    use crate::{TimingMetric, TimingReport};
+
+    #[tokio::test]
+    async fn timed_response() {
+        let app = Router::new().route(
+            "/",
+            get(|| async move {
+                let mut hdr = HeaderMap::new();
+                hdr.insert("server-timing", HeaderValue::from_static("inner;dur=23"));
+
+                Timed(
+                    (hdr, ""),
+                    TimingReport::new().with(TimingMetric::new("db").with_millis(12.0)),
+                )
+            }),
+        );
+
+        let response = app.oneshot(Request::new(Body::empty())).await.unwrap();
+
+        assert_eq!(
+            response.headers().get("server-timing").unwrap(),
+            "db;dur=12.0, inner;dur=23"
+        );
+    }
+}
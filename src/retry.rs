@@ -0,0 +1,144 @@
+//! Integration with `tower::retry`.
+//!
+//! When [`ServerTimingLayer`](crate::ServerTimingLayer) wraps a retry layer,
+//! the reported duration naturally covers all attempts. Install
+//! [`AttemptLayer`] under the retry layer to also report the number of
+//! attempts as an `attempts=N` param:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::new()
+//!     .layer(ServerTimingLayer::new("app"))
+//!     .layer(RetryLayer::new(policy))
+//!     .layer(AttemptLayer)
+//!     .service(inner);
+//! ```
+//!
+//! The retry policy must keep the request extensions when cloning the
+//! request.
+
+use std::task::{Context, Poll};
+
+use http::Request;
+
+use crate::ServerTimingHandle;
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A layer counting the attempts of dispatching a request, to be installed
+/// under the retry layer.
+pub struct AttemptLayer;
+
+impl<S> tower_layer::Layer<S> for AttemptLayer {
+    type Service = AttemptService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AttemptService { service }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A service counting the attempts of dispatching a request.
+pub struct AttemptService<S> {
+    /// The service to wrap.
+    service: S,
+}
+
+impl<S, ReqBody> tower_service::Service<Request<ReqBody>> for AttemptService<S>
+where
+    S: tower_service::Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if let Some(handle) = req.extensions().get::<ServerTimingHandle>() {
+            handle.attempt();
+        }
+
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::{ready, Ready},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use http::{Request, Response};
+    use tower::{retry::Policy, service_fn, ServiceBuilder, ServiceExt};
+
+    use super::AttemptLayer;
+    use crate::ServerTimingLayer;
+
+    #[derive(Clone)]
+    struct RetryOnce(bool);
+
+    impl<Res, E> Policy<Request<()>, Res, E> for RetryOnce {
+        type Future = Ready<()>;
+
+        fn retry(
+            &mut self,
+            _req: &mut Request<()>,
+            result: &mut Result<Res, E>,
+        ) -> Option<Self::Future> {
+            if result.is_err() && !self.0 {
+                self.0 = true;
+                Some(ready(()))
+            } else {
+                None
+            }
+        }
+
+        fn clone_request(&mut self, req: &Request<()>) -> Option<Request<()>> {
+            let mut cloned = Request::new(());
+            *cloned.extensions_mut() = req.extensions().clone();
+            Some(cloned)
+        }
+    }
+
+    #[tokio::test]
+    async fn count_attempts() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let service = ServiceBuilder::new()
+            .layer(ServerTimingLayer::new("svc1"))
+            .retry(RetryOnce(false))
+            .layer(AttemptLayer)
+            .service(service_fn(move |_req: Request<()>| {
+                let n = calls.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    if n == 0 {
+                        Err("fail")
+                    } else {
+                        Ok(Response::new(()))
+                    }
+                }
+            }));
+
+        let response = service.oneshot(Request::new(())).await.unwrap();
+
+        let hdr = response
+            .headers()
+            .get("server-timing")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            hdr.starts_with("svc1;dur="),
+            "Invalid `server-timing`: {hdr}"
+        );
+        assert!(
+            hdr.ends_with(";attempts=2"),
+            "Invalid `server-timing`: {hdr}"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Installing the middleware on axum routers.
+
+use std::{borrow::Cow, convert::Infallible, future::Future, pin::Pin};
+
+use axum::{extract::Request, middleware::Next, response::Response, Router};
+
+use crate::{ResponseFuture, ServerTimingLayer};
+
+/// Installs the middleware on an axum [`Router`] in one line.
+///
+/// ```rust,ignore
+/// let app = Router::new()
+///     .route("/", get(handler))
+///     .with_server_timing("app");
+/// ```
+pub trait RouterExt {
+    /// Adds a [`ServerTimingLayer`] with the given service name and the
+    /// process-wide default options, see [`ServerTimingLayer::from_default`].
+    ///
+    /// Like [`Router::layer`], only the routes added before are covered.
+    #[must_use]
+    fn with_server_timing(self, app: impl Into<Cow<'static, str>>) -> Self;
+}
+
+impl<S> RouterExt for Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn with_server_timing(self, app: impl Into<Cow<'static, str>>) -> Self {
+        self.layer(ServerTimingLayer::from_default(app))
+    }
+}
+
+/// The future of [`middleware`].
+pub type MiddlewareFuture =
+    ResponseFuture<Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>>;
+
+/// Returns the middleware as a function, for `axum::middleware::from_fn` and
+/// `from_fn_with_state`, with the given service name and the process-wide
+/// default options, see [`ServerTimingLayer::from_default`].
+///
+/// ```rust,ignore
+/// let app = Router::new()
+///     .route("/", get(handler))
+///     .layer(axum::middleware::from_fn(miku_server_timing::middleware("app")));
+/// ```
+///
+/// It behaves exactly like the layer, e.g. handlers get the
+/// [`ServerTimingHandle`](crate::ServerTimingHandle) the same way.
+pub fn middleware(
+    app: impl Into<Cow<'static, str>>,
+) -> impl Fn(Request, Next) -> MiddlewareFuture + Clone + Send + Sync + 'static {
+    let layer = ServerTimingLayer::from_default(app);
+
+    move |req: Request, next: Next| ResponseFuture::wrap(&layer, req, |req| run(req, next))
+}
+
+/// Runs the rest of the middleware stack.
+fn run(
+    req: Request,
+    next: Next,
+) -> Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>> {
+    Box::pin(async move { Ok(next.run(req).await) })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get, Router};
+    use http::Request;
+    use tower::ServiceExt;
+
+    use super::{middleware, RouterExt};
+
+    #[tokio::test]
+    async fn with_server_timing() {
+        let app = Router::new()
+            .route("/", get(|| async { "" }))
+            .with_server_timing(String::from("svc1"));
+
+        let response = app
+            .clone()
+            .oneshot(Request::new(Body::empty()))
+            .await
+            .unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+
+        // Unmatched routes get the header by default, like with the layer.
+        let response = app
+            .oneshot(Request::get("/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn from_fn() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|handle: crate::ServerTimingHandle| async move {
+                    handle.record(crate::TimingMetric::new("db").with_millis(1.0));
+                }),
+            )
+            .layer(axum::middleware::from_fn(middleware("svc1")));
+
+        let response = app.oneshot(Request::new(Body::empty())).await.unwrap();
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert!(hdr.ends_with(", db;dur=1.0"), "{hdr}");
+    }
+}
@@ -0,0 +1,442 @@
+//! Sampling of the requests getting the header.
+
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use http::{header, HeaderMap, HeaderName, StatusCode};
+
+use crate::policy::{Exchange, Policy};
+
+#[derive(Debug, Clone)]
+/// Which requests get the header, and the [`on_timing`] callback.
+///
+/// A fixed ratio of the requests is sampled when they are received, and the
+/// interesting ones can be always sampled when the response is ready whatever
+/// the ratio, so that they are never lost:
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use miku_server_timing::Sampling;
+/// // 1% of the requests, plus the slow ones and server errors.
+/// let sampling = Sampling::new(0.01)
+///     .with_slow(Duration::from_millis(500))
+///     .with_server_errors(true);
+/// ```
+///
+/// With a [`SampleKey`], the decision is made consistently for a given key, so
+/// that a user either always or never gets the header during a session.
+///
+/// Sampling is a [`Policy`] deciding with the response, which combines with
+/// the other ones, e.g. to always sample some paths.
+///
+/// [`on_timing`]: crate::ServerTimingLayer::with_on_timing
+pub struct Sampling {
+    /// The ratio of sampled requests, from 0 to 1.
+    rate: f64,
+
+    /// Requests slower than this are always sampled.
+    slow: Option<Duration>,
+
+    /// Whether `5xx` responses are always sampled.
+    server_errors: bool,
+
+    /// What the decision is consistent for, if any.
+    key: Option<SampleKey>,
+
+    /// An optional cap on the number of sampled requests per second.
+    rate_limit: Option<Arc<RateLimit>>,
+
+    /// The number of requests seen so far, shared by all the clones.
+    counter: Arc<AtomicU64>,
+}
+
+impl Sampling {
+    #[inline]
+    /// Creates a new [`Sampling`] with the given ratio of sampled requests,
+    /// clamped to `0.0..=1.0`.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate: if rate.is_nan() {
+                0.0
+            } else {
+                rate.clamp(0.0, 1.0)
+            },
+            slow: None,
+            server_errors: false,
+            key: None,
+            rate_limit: None,
+            counter: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    #[inline]
+    /// Always samples requests taking at least the given duration.
+    pub fn with_slow(mut self, slow: Duration) -> Self {
+        self.slow = Some(slow);
+        self
+    }
+
+    #[inline]
+    /// Always samples `5xx` responses.
+    pub fn with_server_errors(mut self, server_errors: bool) -> Self {
+        self.server_errors = server_errors;
+        self
+    }
+
+    #[inline]
+    /// Makes the decision consistent for the given key.
+    ///
+    /// Requests without the key fall back to the fixed ratio.
+    pub fn with_key(mut self, key: SampleKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    #[inline]
+    /// Samples at most the given number of requests per second, including
+    /// the ones always sampled.
+    pub fn with_rate_limit(mut self, per_second: u32) -> Self {
+        self.rate_limit = Some(Arc::new(RateLimit {
+            origin: Instant::now(),
+            per_second,
+            window: AtomicU64::new(0),
+        }));
+        self
+    }
+
+    #[inline]
+    /// Decides whether the request is sampled, given its headers.
+    pub(crate) fn sample(&self, headers: &HeaderMap) -> bool {
+        match self.key.as_ref().and_then(|key| key.extract(headers)) {
+            Some(key) => below(hash(key), self.rate),
+            None => self.sample_nth(self.counter.fetch_add(1, Ordering::Relaxed)),
+        }
+    }
+
+    #[inline]
+    /// Makes the final decision when the response is ready, given whether the
+    /// request is sampled.
+    fn decide_sampled(&self, sampled: bool, elapsed: Duration, status: StatusCode) -> bool {
+        (sampled || self.force(elapsed, status))
+            && self
+                .rate_limit
+                .as_ref()
+                .map_or(true, |rate_limit| rate_limit.acquire())
+    }
+
+    #[inline]
+    /// Decides whether the request is sampled anyway, when the response is
+    /// ready.
+    fn force(&self, elapsed: Duration, status: StatusCode) -> bool {
+        self.slow.is_some_and(|slow| elapsed >= slow)
+            || (self.server_errors && status.is_server_error())
+    }
+
+    #[inline]
+    fn sample_nth(&self, n: u64) -> bool {
+        // Fibonacci hashing spreads consecutive numbers evenly over `u64`, so
+        // that the sampled ratio is accurate even over a few requests.
+        below(n.wrapping_mul(0x9E37_79B9_7F4A_7C15), self.rate)
+    }
+}
+
+impl Policy for Sampling {
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        let (status, elapsed) = exchange.status().zip(exchange.elapsed())?;
+        let sampled = self.sample(exchange.request_headers());
+
+        Some(self.decide_sampled(sampled, elapsed, status))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The sampling of the requests getting the header, overridden per tenant.
+pub(crate) struct TenantSampling {
+    /// The sampling of the requests without an overriding tenant, all of them
+    /// if `None`.
+    pub(crate) default: Option<Sampling>,
+
+    /// The sampling of the requests of the given tenants.
+    pub(crate) tenants: Vec<(String, Sampling)>,
+}
+
+impl TenantSampling {
+    #[inline]
+    pub(crate) const fn new() -> Self {
+        Self {
+            default: None,
+            tenants: Vec::new(),
+        }
+    }
+
+    #[inline]
+    /// Returns the sampling of the requests of the given tenant.
+    pub(crate) fn get(&self, tenant: Option<&str>) -> Option<&Sampling> {
+        tenant
+            .and_then(|tenant| {
+                self.tenants
+                    .iter()
+                    .find_map(|(name, sampling)| (name == tenant).then_some(sampling))
+            })
+            .or(self.default.as_ref())
+    }
+
+    #[inline]
+    /// Samples the requests of the given tenant with the given sampling.
+    pub(crate) fn set(&mut self, tenant: String, sampling: Sampling) {
+        self.tenants.retain(|(name, _)| *name != tenant);
+        self.tenants.push((tenant, sampling));
+    }
+
+    #[inline]
+    /// Samples all the requests.
+    pub(crate) fn clear(&mut self) {
+        self.default = None;
+        self.tenants.clear();
+    }
+}
+
+impl Policy for TenantSampling {
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        if self.default.is_none() && self.tenants.is_empty() {
+            return Some(true);
+        }
+
+        // The tenant is only known with the response.
+        exchange.status()?;
+        self.get(exchange.tenant())
+            .map_or(Some(true), |sampling| sampling.decide(exchange))
+    }
+}
+
+#[derive(Debug)]
+/// A fixed window rate limiter.
+struct RateLimit {
+    /// The reference point of windows.
+    origin: Instant,
+
+    /// The max number of permits per window.
+    per_second: u32,
+
+    /// The current window in the upper 32 bits, and the permits taken in it
+    /// in the lower 32 bits.
+    window: AtomicU64,
+}
+
+impl RateLimit {
+    /// Takes a permit if any is left in the current window.
+    fn acquire(&self) -> bool {
+        if self.per_second == 0 {
+            return false;
+        }
+
+        let now = self.origin.elapsed().as_secs() << 32;
+
+        self.window
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |window| {
+                if window & !u64::from(u32::MAX) != now {
+                    // A new window.
+                    Some(now | 1)
+                } else if (window as u32) < self.per_second {
+                    Some(window + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+/// What consistent sampling is keyed by.
+pub enum SampleKey {
+    /// The trace ID of the W3C `traceparent` header.
+    TraceParent,
+
+    /// The value of the given request header.
+    Header(HeaderName),
+
+    /// The value of the given cookie, e.g. a session ID.
+    Cookie(Cow<'static, str>),
+
+    /// The client IP, i.e. the first hop of `X-Forwarded-For`, or
+    /// `X-Real-IP`.
+    ClientIp,
+}
+
+impl SampleKey {
+    /// Extracts the key from the request headers.
+    fn extract<'h>(&self, headers: &'h HeaderMap) -> Option<&'h [u8]> {
+        match self {
+            Self::TraceParent => headers
+                .get("traceparent")
+                .and_then(|value| value.as_bytes().get(3..35)),
+            Self::Header(name) => headers.get(name).map(|value| value.as_bytes()),
+            Self::Cookie(name) => headers
+                .get_all(header::COOKIE)
+                .iter()
+                .flat_map(|value| value.as_bytes().split(|&b| b == b';'))
+                .find_map(|pair| trim(pair).strip_prefix(name.as_bytes())?.strip_prefix(b"=")),
+            Self::ClientIp => headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.as_bytes().split(|&b| b == b',').next())
+                .or_else(|| headers.get("x-real-ip").map(|value| value.as_bytes()))
+                .map(trim),
+        }
+        .filter(|key| !key.is_empty())
+    }
+}
+
+#[inline]
+/// Trims ASCII whitespaces.
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = bytes {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        bytes = rest;
+    }
+
+    while let [rest @ .., last] = bytes {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        bytes = rest;
+    }
+
+    bytes
+}
+
+#[inline]
+/// A stable hash of the key, FNV-1a with a final mix, so that all the
+/// replicas make the same decision.
+fn hash(key: &[u8]) -> u64 {
+    let mut hash = key.iter().fold(0xCBF2_9CE4_8422_2325, |hash: u64, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3)
+    });
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    hash ^ (hash >> 33)
+}
+
+#[inline]
+/// Whether the hash, mapped to `0.0..1.0`, is below the given ratio.
+fn below(hash: u64, rate: f64) -> bool {
+    ((hash >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue, StatusCode};
+
+    use super::{SampleKey, Sampling};
+
+    #[test]
+    fn rate() {
+        let sampling = Sampling::new(0.25);
+        let sampled = (0..1000).filter(|&n| sampling.sample_nth(n)).count();
+        assert!((240..=260).contains(&sampled), "{sampled}");
+
+        let sampling = Sampling::new(0.0);
+        assert!((0..1000).all(|n| !sampling.sample_nth(n)));
+
+        let sampling = Sampling::new(2.0);
+        assert!((0..1000).all(|n| sampling.sample_nth(n)));
+    }
+
+    #[test]
+    fn force() {
+        let sampling = Sampling::new(0.0);
+        assert!(!sampling.force(Duration::from_secs(10), StatusCode::INTERNAL_SERVER_ERROR));
+
+        let sampling = sampling
+            .with_slow(Duration::from_millis(500))
+            .with_server_errors(true);
+        assert!(!sampling.force(Duration::from_millis(10), StatusCode::OK));
+        assert!(sampling.force(Duration::from_millis(500), StatusCode::OK));
+        assert!(sampling.force(Duration::from_millis(10), StatusCode::BAD_GATEWAY));
+        assert!(!sampling.force(Duration::from_millis(10), StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let sampling = Sampling::new(1.0).with_rate_limit(3);
+        let decided = (0..10)
+            .filter(|_| sampling.decide_sampled(true, Duration::ZERO, StatusCode::OK))
+            .count();
+        assert_eq!(decided, 3);
+
+        let sampling = Sampling::new(1.0).with_rate_limit(0);
+        assert!(!sampling.decide_sampled(true, Duration::ZERO, StatusCode::OK));
+    }
+
+    #[test]
+    fn extract_key() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        headers.insert("cookie", HeaderValue::from_static("a=1; sid=abc; sid2=def"));
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7 , 10.0.0.1"),
+        );
+
+        assert_eq!(
+            SampleKey::TraceParent.extract(&headers),
+            Some(&b"4bf92f3577b34da6a3ce929d0e0e4736"[..])
+        );
+        assert_eq!(
+            SampleKey::Cookie("sid".into()).extract(&headers),
+            Some(&b"abc"[..])
+        );
+        assert_eq!(SampleKey::Cookie("b".into()).extract(&headers), None);
+        assert_eq!(
+            SampleKey::ClientIp.extract(&headers),
+            Some(&b"203.0.113.7"[..])
+        );
+        assert_eq!(
+            SampleKey::Header(http::header::USER_AGENT).extract(&headers),
+            None
+        );
+    }
+
+    #[test]
+    fn consistent() {
+        let sampling = Sampling::new(0.5).with_key(SampleKey::Cookie("sid".into()));
+
+        for sid in ["a", "b", "c", "d"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "cookie",
+                HeaderValue::from_str(&format!("sid={sid}")).unwrap(),
+            );
+
+            let first = sampling.sample(&headers);
+            assert!((0..10).all(|_| sampling.sample(&headers) == first));
+        }
+
+        let sampled = (0..1000)
+            .filter(|n| {
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    "cookie",
+                    HeaderValue::from_str(&format!("sid={n}")).unwrap(),
+                );
+                sampling.sample(&headers)
+            })
+            .count();
+        assert!((400..=600).contains(&sampled), "{sampled}");
+    }
+}
@@ -0,0 +1,49 @@
+//! Per-thread scratch space for formatting headers.
+
+use std::cell::Cell;
+
+/// The max capacity kept between responses, so that a single huge header
+/// doesn't pin memory on every worker.
+const MAX_CAPACITY: usize = 4096;
+
+thread_local! {
+    static SCRATCH: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+}
+
+/// Takes the scratch buffer of the current thread, empty, or a new one if it
+/// is already taken or the thread is being destroyed.
+pub(crate) fn take() -> Vec<u8> {
+    SCRATCH.try_with(Cell::take).unwrap_or_default()
+}
+
+/// Gives the buffer back to the current thread, for the next response.
+pub(crate) fn put(mut buf: Vec<u8>) {
+    if buf.capacity() <= MAX_CAPACITY {
+        buf.clear();
+        let _ = SCRATCH.try_with(|scratch| scratch.set(buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{put, take, MAX_CAPACITY};
+
+    #[test]
+    fn reuse() {
+        let mut buf = take();
+        buf.extend_from_slice(b"app;dur=1.0");
+        let ptr = buf.as_ptr();
+        put(buf);
+
+        let buf = take();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+
+        // Taken twice, e.g. by nested services.
+        assert_eq!(take().capacity(), 0);
+        put(buf);
+
+        put(Vec::with_capacity(MAX_CAPACITY + 1));
+        assert!(take().capacity() <= MAX_CAPACITY);
+    }
+}
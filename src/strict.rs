@@ -0,0 +1,14 @@
+//! Spec-compliance checks of the final header value.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What happens to non-compliant header values in strict mode, see
+/// [`ServerTimingLayer::with_strict`](crate::ServerTimingLayer::with_strict).
+pub enum StrictMode {
+    /// Skips the header, logging and reporting
+    /// [`ServerTimingError::NonCompliant`](crate::ServerTimingError::NonCompliant),
+    /// e.g. in production.
+    Report,
+
+    /// Panics, so that tests fail loudly.
+    Panic,
+}
@@ -0,0 +1,356 @@
+//! Converting `tracing` spans into custom metrics.
+//!
+//! Add [`SpanTimingLayer`] to the subscriber, install
+//! [`ContextLayer`](crate::context::ContextLayer) under
+//! [`ServerTimingLayer`](crate::ServerTimingLayer), and spans with a
+//! `server_timing = true` field created while handling a request are recorded
+//! when closed, so that instrumentation needs no extra code:
+//!
+//! ```rust,ignore
+//! tracing_subscriber::registry()
+//!     .with(tracing_subscriber::fmt::layer())
+//!     .with(SpanTimingLayer::new().with_desc_fields(&["db.statement"]))
+//!     .init();
+//!
+//! #[instrument(fields(server_timing = true))]
+//! async fn query_users() -> Vec<User> {
+//!     // ...
+//! }
+//! ```
+//!
+//! The request a span belongs to is the one of its closest ancestor created
+//! while handling a request, or the request being handled by the current task
+//! otherwise.
+//!
+//! Selected fields of the spans, e.g. `db.statement`, can be shown as the
+//! description of the metrics, see [`SpanTimingLayer::with_desc_fields`].
+
+use std::{fmt, time::Instant};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{context, ServerTimingHandle, TimingMetric};
+
+/// The field marking spans to be recorded.
+const FIELD: &str = "server_timing";
+
+#[derive(Debug, Clone, Copy)]
+/// A `tracing_subscriber` layer recording spans with a `server_timing = true`
+/// field as custom metrics of the request they belong to.
+pub struct SpanTimingLayer {
+    /// The fields making up the description, in order.
+    desc_fields: &'static [&'static str],
+
+    /// The max length of the description, in bytes.
+    max_desc_len: usize,
+}
+
+impl Default for SpanTimingLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpanTimingLayer {
+    #[inline]
+    /// Creates a new [`SpanTimingLayer`], without descriptions.
+    pub const fn new() -> Self {
+        Self {
+            desc_fields: &[],
+            max_desc_len: 64,
+        }
+    }
+
+    #[inline]
+    /// Sets the span fields making up the description of the metric, e.g.
+    /// `db.statement` or `cache.key`.
+    ///
+    /// The values of the fields present are joined with spaces, in the given
+    /// order, including the ones recorded after the span is created.
+    ///
+    /// Beware that descriptions are sent to the client: only allow fields
+    /// which are safe to disclose.
+    pub const fn with_desc_fields(mut self, desc_fields: &'static [&'static str]) -> Self {
+        self.desc_fields = desc_fields;
+        self
+    }
+
+    #[inline]
+    /// Sets the max length of the description in bytes, 64 by default.
+    ///
+    /// Longer descriptions are truncated, ending with `...`.
+    pub const fn with_max_desc_len(mut self, max_desc_len: usize) -> Self {
+        self.max_desc_len = max_desc_len;
+        self
+    }
+
+    /// Builds the description from the captured fields.
+    fn description(&self, fields: &[Option<String>]) -> Option<String> {
+        let mut desc = String::new();
+        for value in fields.iter().flatten() {
+            if !desc.is_empty() {
+                desc.push(' ');
+            }
+            desc.push_str(value);
+        }
+
+        if desc.len() > self.max_desc_len {
+            let mut end = self.max_desc_len.saturating_sub(3);
+            while !desc.is_char_boundary(end) {
+                end -= 1;
+            }
+            desc.truncate(end);
+            desc.push_str(&"..."[..self.max_desc_len.min(3)]);
+        }
+
+        (!desc.is_empty()).then_some(desc)
+    }
+}
+
+/// The state of a span created while handling a request.
+struct SpanTiming {
+    /// The handle of the request.
+    handle: ServerTimingHandle,
+
+    /// When the span was created, if it's to be recorded.
+    created: Option<Instant>,
+
+    /// The values of the description fields, indexed like
+    /// [`SpanTimingLayer::desc_fields`].
+    fields: Vec<Option<String>>,
+}
+
+impl<S> Layer<S> for SpanTimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let handle = span
+            .parent()
+            .and_then(|parent| {
+                let extensions = parent.extensions();
+                extensions
+                    .get::<SpanTiming>()
+                    .map(|timing| timing.handle.clone())
+            })
+            .or_else(context::current);
+        let Some(handle) = handle else {
+            return;
+        };
+
+        let mut visitor = FieldVisitor {
+            enabled: false,
+            desc_fields: self.desc_fields,
+            fields: vec![None; self.desc_fields.len()],
+        };
+        attrs.record(&mut visitor);
+
+        span.extensions_mut().insert(SpanTiming {
+            handle,
+            created: visitor.enabled.then(Instant::now),
+            fields: visitor.fields,
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if self.desc_fields.is_empty() {
+            return;
+        }
+
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        let Some(timing) = extensions.get_mut::<SpanTiming>() else {
+            return;
+        };
+
+        let mut visitor = FieldVisitor {
+            enabled: false,
+            desc_fields: self.desc_fields,
+            fields: std::mem::take(&mut timing.fields),
+        };
+        values.record(&mut visitor);
+        timing.fields = visitor.fields;
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let extensions = span.extensions();
+        let Some(SpanTiming {
+            handle,
+            created: Some(created),
+            fields,
+        }) = extensions.get::<SpanTiming>()
+        else {
+            return;
+        };
+
+        let mut metric = TimingMetric::new(span.metadata().name())
+            .with_start(created.saturating_duration_since(handle.start()))
+            .with_duration(created.elapsed());
+        if let Some(desc) = self.description(fields) {
+            metric = metric.with_description(desc);
+        }
+
+        handle.record(metric);
+    }
+}
+
+/// Looks for the `server_timing = true` field, and the description fields.
+struct FieldVisitor {
+    /// Whether the field is set.
+    enabled: bool,
+
+    /// The fields making up the description.
+    desc_fields: &'static [&'static str],
+
+    /// The values of the description fields.
+    fields: Vec<Option<String>>,
+}
+
+impl FieldVisitor {
+    /// Returns the slot of the value of a description field.
+    fn slot(&mut self, field: &Field) -> Option<&mut Option<String>> {
+        let i = self
+            .desc_fields
+            .iter()
+            .position(|&name| name == field.name())?;
+        self.fields.get_mut(i)
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == FIELD {
+            self.enabled = value;
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if let Some(slot) = self.slot(field) {
+            *slot = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if let Some(slot) = self.slot(field) {
+            *slot = Some(format!("{value:?}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http::{Request, Response};
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::SpanTimingLayer;
+    use crate::{context::ContextLayer, ServerTimingLayer};
+
+    #[tokio::test]
+    async fn record_spans() {
+        let subscriber = tracing_subscriber::registry().with(SpanTimingLayer::new());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let response = ServerTimingLayer::new("svc1")
+            .layer(
+                ContextLayer.layer(service_fn(|_req: Request<()>| async move {
+                    let db = tracing::info_span!("db", server_timing = true);
+                    {
+                        let _entered = db.enter();
+                        // Not recorded, but belongs to the request as well.
+                        let _query = tracing::info_span!("query").entered();
+                    }
+                    drop(db);
+
+                    let _ignored = tracing::info_span!("ignored");
+                    Ok::<_, Infallible>(Response::new(()))
+                })),
+            )
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.contains(", db;dur="), "{hdr}");
+        assert!(!hdr.contains("query") && !hdr.contains("ignored"), "{hdr}");
+    }
+
+    #[tokio::test]
+    async fn desc_fields() {
+        let subscriber = tracing_subscriber::registry().with(
+            SpanTimingLayer::new()
+                .with_desc_fields(&["db.statement", "cache.key"])
+                .with_max_desc_len(16),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let response = ServerTimingLayer::new("svc1")
+            .layer(
+                ContextLayer.layer(service_fn(|_req: Request<()>| async move {
+                    drop(tracing::info_span!(
+                        "db",
+                        server_timing = true,
+                        db.statement = "SELECT * FROM users",
+                        other = 1,
+                    ));
+
+                    let cache = tracing::info_span!(
+                        "cache",
+                        server_timing = true,
+                        cache.key = tracing::field::Empty,
+                    );
+                    cache.record("cache.key", "\"k1\"");
+                    drop(cache);
+
+                    Ok::<_, Infallible>(Response::new(()))
+                })),
+            )
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let hdr = response.headers()["server-timing"].to_str().unwrap();
+        assert!(hdr.contains(", db;desc=\"SELECT * FROM...\";dur="), "{hdr}");
+        assert!(hdr.contains(", cache;desc=\"\\\"k1\\\"\";dur="), "{hdr}");
+    }
+
+    #[test]
+    fn truncate_desc() {
+        let layer = SpanTimingLayer::new().with_max_desc_len(6);
+        assert_eq!(
+            layer.description(&[Some("ab".to_owned()), None, Some("cd".to_owned())]),
+            Some("ab cd".to_owned())
+        );
+        assert_eq!(
+            layer.description(&[Some("ab\u{e9}cdef".to_owned())]),
+            Some("ab...".to_owned())
+        );
+        assert_eq!(layer.description(&[None]), None);
+        assert_eq!(
+            SpanTimingLayer::new()
+                .with_max_desc_len(2)
+                .description(&[Some("abc".to_owned())]),
+            Some("..".to_owned())
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Assertions on the `Server-Timing` header, for testing instrumentation.
+//!
+//! [`ServerTimingAssertions`] is implemented for [`Response`] (e.g. from
+//! [`ServiceExt::oneshot`]) and [`HeaderMap`] (e.g. the headers of an
+//! `axum-test` response):
+//!
+//! ```rust,ignore
+//! use miku_server_timing::testing::ServerTimingAssertions;
+//!
+//! let response = app.oneshot(request).await?;
+//! response
+//!     .assert_timing_metric("db")
+//!     .assert_total_within(..Duration::from_millis(100));
+//!
+//! server.get("/users").await.headers().assert_timing_metric("db");
+//! ```
+//!
+//! [`MockUpstream`] is a service responding with pre-existing `Server-Timing`
+//! headers, e.g. to exercise how they are merged:
+//!
+//! ```rust,ignore
+//! let response = ServerTimingLayer::new("app")
+//!     .layer(MockUpstream::new().with_valid().with_opaque())
+//!     .oneshot(Request::new(()))
+//!     .await?;
+//! ```
+//!
+//! [`ServiceExt::oneshot`]: https://docs.rs/tower/latest/tower/trait.ServiceExt.html#method.oneshot
+
+use std::{
+    convert::Infallible,
+    fmt,
+    future::{ready, Ready},
+    marker::PhantomData,
+    ops::RangeBounds,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http::{HeaderMap, HeaderValue, Request, Response};
+use tower_service::Service;
+
+use crate::{
+    parse::{parse_headers, TimingEntry},
+    SERVER_TIMING,
+};
+
+/// Assertions on the `Server-Timing` header.
+///
+/// The assertions panic with the header value when they fail, and return
+/// `self` so that they can be chained.
+pub trait ServerTimingAssertions {
+    /// Returns the headers holding `Server-Timing`.
+    fn server_timing_headers(&self) -> &HeaderMap;
+
+    #[track_caller]
+    /// Returns all the entries of the `Server-Timing` headers.
+    ///
+    /// # Panics
+    ///
+    /// When the header is malformed.
+    fn timing_entries(&self) -> Vec<TimingEntry> {
+        let headers = self.server_timing_headers();
+        let entries = parse_headers(headers);
+
+        let error = entries.as_ref().err();
+        assert!(
+            error.is_none(),
+            "{}: {}",
+            error.map(ToString::to_string).unwrap_or_default(),
+            display(headers)
+        );
+        entries.unwrap_or_default()
+    }
+
+    #[track_caller]
+    /// Returns the first entry with the given name, if any.
+    ///
+    /// # Panics
+    ///
+    /// When the header is malformed.
+    fn timing_metric(&self, name: &str) -> Option<TimingEntry> {
+        self.timing_entries()
+            .into_iter()
+            .find(|entry| entry.name() == name)
+    }
+
+    #[track_caller]
+    /// Asserts that there's an entry with the given name.
+    ///
+    /// # Panics
+    ///
+    /// When there's none, or the header is malformed.
+    fn assert_timing_metric(&self, name: &str) -> &Self {
+        assert!(
+            self.timing_metric(name).is_some(),
+            "no `{name}` metric in `Server-Timing`: {}",
+            display(self.server_timing_headers())
+        );
+        self
+    }
+
+    #[track_caller]
+    /// Asserts that there's no entry with the given name.
+    ///
+    /// # Panics
+    ///
+    /// When there's one, or the header is malformed.
+    fn assert_no_timing_metric(&self, name: &str) -> &Self {
+        assert!(
+            self.timing_metric(name).is_none(),
+            "unexpected `{name}` metric in `Server-Timing`: {}",
+            display(self.server_timing_headers())
+        );
+        self
+    }
+
+    #[track_caller]
+    /// Asserts that there's an entry with the given name whose duration is
+    /// within the given range.
+    ///
+    /// # Panics
+    ///
+    /// When there's none, it has no duration, the duration is out of the
+    /// range, or the header is malformed.
+    fn assert_metric_within<R: RangeBounds<Duration>>(&self, name: &str, range: R) -> &Self {
+        let dur = self
+            .timing_metric(name)
+            .and_then(|entry| entry.duration())
+            .map(|dur| Duration::from_secs_f64(dur.max(0.0) / 1000.0));
+
+        assert!(
+            dur.is_some_and(|dur| range.contains(&dur)),
+            "`{name}` metric took {dur:?}, out of {:?}..{:?}: {}",
+            range.start_bound(),
+            range.end_bound(),
+            display(self.server_timing_headers())
+        );
+        self
+    }
+
+    #[track_caller]
+    /// Asserts that the total duration, i.e. the one of the first entry which
+    /// is the one of the outermost [`ServerTimingLayer`], is within the given
+    /// range.
+    ///
+    /// # Panics
+    ///
+    /// When there's no entry, it has no duration, the duration is out of the
+    /// range, or the header is malformed.
+    ///
+    /// [`ServerTimingLayer`]: crate::ServerTimingLayer
+    fn assert_total_within<R: RangeBounds<Duration>>(&self, range: R) -> &Self {
+        let entries = self.timing_entries();
+        let name = entries.first().map_or("", TimingEntry::name);
+        self.assert_metric_within(name, range)
+    }
+}
+
+impl ServerTimingAssertions for HeaderMap {
+    #[inline]
+    fn server_timing_headers(&self) -> &HeaderMap {
+        self
+    }
+}
+
+impl<B> ServerTimingAssertions for Response<B> {
+    #[inline]
+    fn server_timing_headers(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+/// A service responding with the given `Server-Timing` headers, like an
+/// upstream service, e.g. behind a proxy.
+///
+/// Each `with_*` call adds a header, so that they can be combined.
+pub struct MockUpstream<B = ()> {
+    /// The headers of the responses, in order.
+    headers: Vec<HeaderValue>,
+
+    /// The response body type.
+    _body: PhantomData<fn() -> B>,
+}
+
+impl<B> fmt::Debug for MockUpstream<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockUpstream")
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+impl<B> Clone for MockUpstream<B> {
+    fn clone(&self) -> Self {
+        Self {
+            headers: self.headers.clone(),
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<B> Default for MockUpstream<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B> MockUpstream<B> {
+    #[inline]
+    /// Creates a new [`MockUpstream`] without any header.
+    pub const fn new() -> Self {
+        Self {
+            headers: Vec::new(),
+            _body: PhantomData,
+        }
+    }
+
+    #[inline]
+    /// Adds the given header value.
+    pub fn with_header(mut self, value: HeaderValue) -> Self {
+        self.headers.push(value);
+        self
+    }
+
+    #[inline]
+    /// Adds a valid header, `db;desc="query, users";dur=12.3, cache;hit`.
+    pub fn with_valid(self) -> Self {
+        self.with_header(HeaderValue::from_static(
+            "db;desc=\"query, users\";dur=12.3, cache;hit",
+        ))
+    }
+
+    #[inline]
+    /// Adds a malformed header, with an empty param value and an unterminated
+    /// quoted string.
+    pub fn with_invalid(self) -> Self {
+        self.with_header(HeaderValue::from_static(
+            "db;dur=, ;;, cache;desc=\"unterminated",
+        ))
+    }
+
+    /// Adds a valid header of at least the given length in bytes, made of
+    /// `upstreamN;dur=1.0` entries.
+    pub fn with_huge(self, len: usize) -> Self {
+        let mut value = String::with_capacity(len + 32);
+        for i in 0.. {
+            if value.len() >= len {
+                break;
+            }
+            if i > 0 {
+                value.push_str(", ");
+            }
+            value.push_str(&format!("upstream{i};dur=1.0"));
+        }
+
+        match HeaderValue::try_from(value) {
+            Ok(value) => self.with_header(value),
+            Err(_) => self,
+        }
+    }
+
+    #[inline]
+    /// Adds a header with opaque non-UTF-8 bytes in a description, which
+    /// must be kept as is.
+    pub fn with_opaque(self) -> Self {
+        match HeaderValue::from_bytes(b"opaque;desc=\"\xfa\xfb\";dur=1") {
+            Ok(value) => self.with_header(value),
+            Err(_) => self,
+        }
+    }
+}
+
+impl<B: Default, ReqBody> Service<Request<ReqBody>> for MockUpstream<B> {
+    type Response = Response<B>;
+    type Error = Infallible;
+    type Future = Ready<Result<Response<B>, Infallible>>;
+
+    #[inline]
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request<ReqBody>) -> Self::Future {
+        let mut response = Response::new(B::default());
+        for value in &self.headers {
+            response.headers_mut().append(SERVER_TIMING, value.clone());
+        }
+
+        ready(Ok(response))
+    }
+}
+
+/// Joins the `Server-Timing` headers for display.
+fn display(headers: &HeaderMap) -> String {
+    let values: Vec<_> = headers
+        .get_all(SERVER_TIMING)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()))
+        .collect();
+
+    if values.is_empty() {
+        "(none)".to_owned()
+    } else {
+        format!("{:?}", values.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use http::{HeaderMap, HeaderValue, Request, Response};
+    use tower::{service_fn, ServiceExt};
+    use tower_layer::Layer;
+
+    use super::{MockUpstream, ServerTimingAssertions};
+    use crate::{parse::parse_header, ServerTimingLayer};
+
+    #[tokio::test]
+    async fn assertions() {
+        let response = ServerTimingLayer::new("svc1")
+            .layer(service_fn(|_req: Request<()>| async move {
+                let mut response = Response::new(());
+                response
+                    .headers_mut()
+                    .insert("server-timing", HeaderValue::from_static("db;dur=5.0"));
+                Ok::<_, Infallible>(response)
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        response
+            .assert_timing_metric("svc1")
+            .assert_timing_metric("db")
+            .assert_no_timing_metric("cache")
+            .assert_metric_within("db", Duration::from_millis(4)..Duration::from_millis(6))
+            .assert_total_within(..Duration::from_secs(5));
+
+        response.headers().assert_timing_metric("db");
+    }
+
+    #[test]
+    #[should_panic(expected = "no `db` metric in `Server-Timing`: (none)")]
+    fn missing_metric() {
+        HeaderMap::new().assert_timing_metric("db");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid `server-timing` header value at byte 3: \"db;\"")]
+    fn malformed() {
+        let mut headers = HeaderMap::new();
+        headers.insert("server-timing", HeaderValue::from_static("db;"));
+        headers.timing_entries();
+    }
+
+    #[test]
+    #[should_panic(expected = "`db` metric took Some(5ms), out of Unbounded..Excluded(1ms)")]
+    fn out_of_range() {
+        let mut headers = HeaderMap::new();
+        headers.insert("server-timing", HeaderValue::from_static("db;dur=5"));
+        headers.assert_metric_within("db", ..Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn merge_upstream() {
+        let upstream = MockUpstream::<()>::new()
+            .with_valid()
+            .with_opaque()
+            .with_huge(16 * 1024);
+        let response = ServerTimingLayer::new("svc1")
+            .layer(upstream.clone())
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        let values: Vec<_> = response.headers().get_all("server-timing").iter().collect();
+        assert_eq!(values.len(), 1);
+
+        let value = values[0].as_bytes();
+        assert!(value.starts_with(b"svc1;dur="));
+        for header in &upstream.headers {
+            let header = header.as_bytes();
+            assert!(value.windows(header.len()).any(|window| window == header));
+        }
+
+        let entries = parse_header(value).unwrap();
+        assert_eq!(entries[0].name(), "svc1");
+        assert!(entries.len() > 100);
+        response
+            .assert_metric_within(
+                "db",
+                Duration::from_micros(12_300)..=Duration::from_micros(12_300),
+            )
+            .assert_timing_metric("cache")
+            .assert_timing_metric("opaque")
+            .assert_timing_metric("upstream0");
+    }
+
+    #[tokio::test]
+    async fn merge_invalid_upstream() {
+        let upstream = MockUpstream::<()>::new().with_invalid();
+        let invalid = upstream.headers[0].as_bytes().to_vec();
+        parse_header(&invalid).unwrap_err();
+
+        let response = ServerTimingLayer::new("svc1")
+            .layer(upstream)
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+
+        // Kept as is.
+        let value = response.headers()["server-timing"].as_bytes();
+        assert!(value.starts_with(b"svc1;dur="));
+        assert!(value.ends_with(&invalid));
+    }
+}
@@ -0,0 +1,241 @@
+//! Rate limiting of the header per route or tenant.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+
+use crate::policy::{Exchange, Policy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+/// What [`HeaderRateLimit`] keeps a separate budget for.
+pub enum LimitKey {
+    /// The route, i.e. the path matched by the axum router with the
+    /// `feat-router` feature, or the request path.
+    Route,
+
+    /// The tenant, see [`ServerTimingLayer::with_tenant`]. Requests without a
+    /// tenant share a budget.
+    ///
+    /// [`ServerTimingLayer::with_tenant`]: crate::ServerTimingLayer::with_tenant
+    Tenant,
+}
+
+#[derive(Debug, Clone)]
+/// A token bucket per route or tenant, limiting how many responses per second
+/// get the header.
+///
+/// Popular endpoints would otherwise generate an overwhelming volume of RUM
+/// ingestion downstream, while rarely hit ones still get a steady trickle of
+/// samples:
+///
+/// ```rust
+/// # use miku_server_timing::HeaderRateLimit;
+/// // At most 10 responses per second and route, with bursts of 20.
+/// let limit = HeaderRateLimit::per_route(10).with_burst(20);
+/// ```
+///
+/// Clones share the buckets.
+///
+/// The limit is a [`Policy`] deciding with the response, which combines with
+/// the other ones.
+pub struct HeaderRateLimit {
+    /// What budgets are kept for.
+    key: LimitKey,
+
+    /// The number of tokens added per second.
+    per_second: u32,
+
+    /// The capacity of the buckets.
+    burst: u32,
+
+    /// The max number of buckets, beyond which new keys share one.
+    max_keys: usize,
+
+    /// The buckets, by key.
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Debug, Default)]
+/// The buckets of [`HeaderRateLimit`].
+struct Buckets {
+    /// The buckets of the known keys.
+    keys: HashMap<String, Bucket>,
+
+    /// The bucket shared by the keys beyond the max number.
+    overflow: Option<Bucket>,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A token bucket.
+struct Bucket {
+    /// The tokens left.
+    tokens: f64,
+
+    /// When tokens were last added.
+    refilled: Instant,
+}
+
+impl HeaderRateLimit {
+    #[inline]
+    /// Creates a new [`HeaderRateLimit`] with the given number of responses
+    /// per second and key.
+    pub fn new(key: LimitKey, per_second: u32) -> Self {
+        Self {
+            key,
+            per_second,
+            burst: per_second,
+            max_keys: 1024,
+            buckets: Arc::default(),
+        }
+    }
+
+    #[inline]
+    /// Creates a new [`HeaderRateLimit`] with the given number of responses
+    /// per second and route.
+    pub fn per_route(per_second: u32) -> Self {
+        Self::new(LimitKey::Route, per_second)
+    }
+
+    #[inline]
+    /// Creates a new [`HeaderRateLimit`] with the given number of responses
+    /// per second and tenant.
+    pub fn per_tenant(per_second: u32) -> Self {
+        Self::new(LimitKey::Tenant, per_second)
+    }
+
+    #[inline]
+    /// Sets how many responses in a row can get the header after a quiet
+    /// period, default the number per second.
+    pub const fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    #[inline]
+    /// Sets the max number of routes or tenants with their own budget,
+    /// default 1024, beyond which new ones share a single budget.
+    ///
+    /// Bounds the memory used when keys are unbounded, e.g. request paths
+    /// carrying IDs.
+    pub const fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    #[inline]
+    /// Returns what budgets are kept for.
+    pub const fn key(&self) -> LimitKey {
+        self.key
+    }
+
+    /// Takes a token from the bucket of the given key, if any is left.
+    pub(crate) fn acquire(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let full = Bucket {
+            tokens: f64::from(self.burst),
+            refilled: now,
+        };
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let Buckets { keys, overflow } = &mut *buckets;
+
+        let bucket = if let Some(bucket) = keys.get_mut(key) {
+            bucket
+        } else if keys.len() < self.max_keys {
+            keys.entry(key.to_owned()).or_insert(full)
+        } else {
+            overflow.get_or_insert(full)
+        };
+
+        bucket.acquire(now, f64::from(self.per_second), f64::from(self.burst))
+    }
+}
+
+impl Policy for HeaderRateLimit {
+    fn decide(&self, exchange: &Exchange<'_>) -> Option<bool> {
+        exchange.status()?;
+
+        let key = match self.key {
+            LimitKey::Route => exchange.route(),
+            LimitKey::Tenant => exchange.tenant().unwrap_or_default(),
+        };
+
+        Some(self.acquire(key))
+    }
+}
+
+impl Bucket {
+    /// Refills the bucket, then takes a token if any is left.
+    fn acquire(&mut self, now: Instant, per_second: f64, burst: f64) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = elapsed.mul_add(per_second, self.tokens).min(burst);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Bucket, HeaderRateLimit, LimitKey};
+
+    #[test]
+    fn per_key() {
+        let limit = HeaderRateLimit::per_route(2);
+        assert_eq!(limit.key(), LimitKey::Route);
+
+        let granted = (0..10).filter(|_| limit.acquire("/users")).count();
+        assert_eq!(granted, 2);
+
+        // Other routes have their own budget, shared by clones.
+        assert!(limit.clone().acquire("/orders"));
+        assert!(!limit.acquire("/users"));
+
+        let limit = HeaderRateLimit::per_tenant(0);
+        assert!(!limit.acquire("acme"));
+    }
+
+    #[test]
+    fn max_keys() {
+        let limit = HeaderRateLimit::per_route(1).with_max_keys(1);
+        assert!(limit.acquire("/a"));
+        assert!(limit.acquire("/b"));
+        assert!(!limit.acquire("/c"));
+        assert!(!limit.acquire("/a"));
+    }
+
+    #[test]
+    fn refill() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            refilled: start,
+        };
+
+        assert!(bucket.acquire(start, 4.0, 2.0));
+        assert!(bucket.acquire(start, 4.0, 2.0));
+        assert!(!bucket.acquire(start, 4.0, 2.0));
+
+        // A token every 250ms.
+        let later = start + Duration::from_millis(260);
+        assert!(bucket.acquire(later, 4.0, 2.0));
+        assert!(!bucket.acquire(later, 4.0, 2.0));
+
+        // Never more than the burst.
+        let much_later = start + Duration::from_secs(60);
+        let granted = (0..10)
+            .filter(|_| bucket.acquire(much_later, 4.0, 2.0))
+            .count();
+        assert_eq!(granted, 2);
+    }
+}
@@ -0,0 +1,276 @@
+//! Emitting the header as a trailer when the response headers are full, see
+//! [`FullPolicy::Trailer`](crate::FullPolicy::Trailer).
+//!
+//! Install [`TrailerLayer`] right above the [`ServerTimingLayer`] so that the
+//! response body can be wrapped:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::new()
+//!     .layer(TrailerLayer)
+//!     .layer(ServerTimingLayer::new("app").with_full_policy(FullPolicy::Trailer))
+//!     .service(inner);
+//! ```
+//!
+//! Trailers only reach clients speaking HTTP/2 or chunked HTTP/1.1, and not
+//! every browser shows them in the devtools.
+//!
+//! [`ServerTimingLayer`]: crate::ServerTimingLayer
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use http::{HeaderMap, HeaderValue, Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+use crate::SERVER_TIMING;
+
+#[derive(Debug, Clone, Copy)]
+/// Tells the middleware that the response body gets wrapped by
+/// [`TrailerService`], inserted into the request and response extensions.
+pub(crate) struct TrailerSupport;
+
+#[derive(Debug, Clone)]
+/// The header value to emit as a trailer, inserted into the response
+/// extensions by the middleware.
+pub(crate) struct PendingTrailer(pub(crate) HeaderValue);
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A layer emitting the `Server-Timing` header as a trailer when it didn't
+/// fit in the response headers, to be installed right above the
+/// [`ServerTimingLayer`](crate::ServerTimingLayer).
+pub struct TrailerLayer;
+
+impl<S> tower_layer::Layer<S> for TrailerLayer {
+    type Service = TrailerService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        TrailerService { service }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A service emitting the `Server-Timing` header as a trailer, see
+/// [`TrailerLayer`].
+pub struct TrailerService<S> {
+    /// The service to wrap.
+    service: S,
+}
+
+impl<S, ReqBody, ResBody> tower_service::Service<Request<ReqBody>> for TrailerService<S>
+where
+    S: tower_service::Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<TrailerBody<ResBody>>;
+    type Error = S::Error;
+    type Future = TrailerFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        req.extensions_mut().insert(TrailerSupport);
+
+        TrailerFuture {
+            inner: self.service.call(req),
+        }
+    }
+}
+
+pin_project! {
+    /// The future of [`TrailerService`].
+    pub struct TrailerFuture<F> {
+        #[pin]
+        inner: F,
+    }
+}
+
+impl<F, B, E> Future for TrailerFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<TrailerBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut response = ready!(self.project().inner.poll(cx))?;
+
+        response.extensions_mut().remove::<TrailerSupport>();
+        let trailer = response
+            .extensions_mut()
+            .remove::<PendingTrailer>()
+            .map(|trailer| trailer.0);
+
+        Poll::Ready(Ok(response.map(|body| TrailerBody { body, trailer })))
+    }
+}
+
+pin_project! {
+    /// A response body followed by the `Server-Timing` trailer, if the header
+    /// didn't fit in the response headers.
+    pub struct TrailerBody<B> {
+        #[pin]
+        body: B,
+        trailer: Option<HeaderValue>,
+    }
+}
+
+impl<B: Body> Body for TrailerBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        let frame = match ready!(this.body.poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+            None => {
+                return Poll::Ready(this.trailer.take().map(|value| {
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert(SERVER_TIMING, value);
+                    Ok(Frame::trailers(trailers))
+                }));
+            }
+        };
+
+        // Merged into the trailers of the body, if any.
+        let frame = match (frame.into_trailers(), this.trailer.take()) {
+            (Ok(mut trailers), Some(value)) => {
+                trailers.append(SERVER_TIMING, value);
+                Frame::trailers(trailers)
+            }
+            (Ok(trailers), None) => Frame::trailers(trailers),
+            (Err(frame), trailer) => {
+                *this.trailer = trailer;
+                frame
+            }
+        };
+
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailer.is_none() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl<B: Default> Default for TrailerBody<B> {
+    fn default() -> Self {
+        Self {
+            body: B::default(),
+            trailer: None,
+        }
+    }
+}
+
+impl<B> std::fmt::Debug for TrailerBody<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrailerBody")
+            .field("trailer", &self.trailer)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use http::{HeaderName, HeaderValue, Request, Response};
+    use http_body_util::BodyExt;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+    use tower_layer::Layer;
+
+    use super::TrailerLayer;
+    use crate::{FullPolicy, ServerTimingError, ServerTimingLayer};
+
+    /// Returns a response whose header map is full.
+    fn full_response() -> Response<String> {
+        let mut response = Response::new(String::from("body"));
+        for i in 0.. {
+            let name = HeaderName::try_from(format!("x-{i}")).unwrap();
+            if response
+                .headers_mut()
+                .try_insert(name, HeaderValue::from_static("1"))
+                .is_err()
+            {
+                return response;
+            }
+        }
+
+        response
+    }
+
+    #[tokio::test]
+    async fn trailer() {
+        let errors = Arc::new(AtomicUsize::new(0));
+        let layer = ServerTimingLayer::new("svc1")
+            .with_full_policy(FullPolicy::Trailer)
+            .with_on_error({
+                let errors = errors.clone();
+                move |e| {
+                    if let ServerTimingError::MaxSizeReached(_) = e {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+
+        let response = ServiceBuilder::new()
+            .layer(TrailerLayer)
+            .layer(layer.clone())
+            .service(service_fn(|_req: Request<()>| async move {
+                Ok::<_, Infallible>(full_response())
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("server-timing"));
+
+        let body = response.into_body().collect().await.unwrap();
+        let hdr = body.trailers().unwrap()["server-timing"].to_str().unwrap();
+        assert!(hdr.starts_with("svc1;dur="), "{hdr}");
+        assert_eq!(body.to_bytes(), "body");
+        assert_eq!(errors.load(Ordering::Relaxed), 0);
+
+        // Room left, the header is added as usual.
+        let response = ServiceBuilder::new()
+            .layer(TrailerLayer)
+            .layer(layer.clone())
+            .service(service_fn(|_req: Request<()>| async move {
+                Ok::<_, Infallible>(Response::new(String::from("body")))
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        assert!(response.headers().contains_key("server-timing"));
+        let body = response.into_body().collect().await.unwrap();
+        assert!(body.trailers().is_none());
+
+        // Without the trailer layer, reported like `FullPolicy::Report`.
+        let response = layer
+            .layer(service_fn(|_req: Request<()>| async move {
+                Ok::<_, Infallible>(full_response())
+            }))
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("server-timing"));
+        assert_eq!(errors.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,111 @@
+//! Integration with the typed headers of the `headers` crate, e.g. axum-extra's
+//! `TypedHeader`.
+//!
+//! ```rust,ignore
+//! async fn handler(TypedHeader(timing): TypedHeader<ServerTiming>) -> impl IntoResponse {
+//!     let upstream = timing.0.metrics().len();
+//!     // ...
+//!     TypedHeader(ServerTiming(
+//!         TimingReport::new().with(TimingMetric::new("db").with_millis(12.3)),
+//!     ))
+//! }
+//! ```
+
+use http::{HeaderName, HeaderValue};
+
+use crate::{parse::parse_header, TimingMetric, TimingReport, SERVER_TIMING};
+
+/// The name of the header, as a `static` since `Header::name` returns a
+/// reference.
+static NAME: HeaderName = SERVER_TIMING;
+
+#[derive(Debug, Clone, Default)]
+/// The `Server-Timing` header as a typed header, wrapping its metrics.
+///
+/// Decoding keeps the name, `dur` and `desc` of the entries and fails on
+/// malformed values, see [`parse_header`](crate::parse::parse_header) to get
+/// the other params.
+pub struct ServerTiming(pub TimingReport);
+
+impl headers::Header for ServerTiming {
+    fn name() -> &'static HeaderName {
+        &NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let mut report = TimingReport::new();
+
+        for value in values {
+            let entries = parse_header(value.as_bytes()).map_err(|_| headers::Error::invalid())?;
+
+            for entry in entries {
+                let mut metric = TimingMetric::new(entry.name().to_owned());
+                if let Some(dur) = entry.duration() {
+                    metric = metric.with_millis(dur);
+                }
+                if let Some(desc) = entry.description() {
+                    metric = metric.with_description(desc.to_owned());
+                }
+                report.push(metric);
+            }
+        }
+
+        Ok(Self(report))
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        match self.0.to_header_value() {
+            Ok(value) => values.extend(std::iter::once(value)),
+            Err(_e) => {
+                #[cfg(feature = "feat-tracing")]
+                tracing::error!("Failed to encode `server-timing` header: {_e:?}");
+            }
+        }
+    }
+}
+
+impl From<TimingReport> for ServerTiming {
+    #[inline]
+    fn from(report: TimingReport) -> Self {
+        Self(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use headers::{HeaderMapExt, HeaderValue};
+    use http::HeaderMap;
+
+    use super::ServerTiming;
+    use crate::{TimingMetric, TimingReport};
+
+    #[test]
+    fn roundtrip() {
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(ServerTiming(
+            TimingReport::new()
+                .with(TimingMetric::new("db").with_millis(12.3))
+                .with(TimingMetric::new("cache").with_description("hit")),
+        ));
+        assert_eq!(headers["server-timing"], "db;dur=12.3, cache;desc=\"hit\"");
+
+        headers.append("server-timing", HeaderValue::from_static("app;dur=20"));
+        let ServerTiming(report) = headers.typed_get().unwrap();
+        let metrics = report.metrics();
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].name(), "db");
+        assert_eq!(metrics[0].millis(), Some(12.3));
+        assert_eq!(metrics[1].description(), Some("hit"));
+        assert_eq!(metrics[2].millis(), Some(20.0));
+
+        headers.insert("server-timing", HeaderValue::from_static("db;desc=\"oops"));
+        headers.typed_try_get::<ServerTiming>().unwrap_err();
+    }
+}
@@ -0,0 +1,59 @@
+//! Programmatic edits of the metrics before serialization.
+
+use std::{fmt, sync::Arc};
+
+use crate::TimingMetric;
+
+/// The signature of [`VisitMut`].
+type Visit = dyn Fn(&mut Vec<TimingMetric>) + Send + Sync;
+
+#[derive(Clone)]
+/// A hook filtering, renaming or re-ordering the metrics right before they
+/// are serialized.
+pub(crate) struct VisitMut(Arc<Visit>);
+
+impl VisitMut {
+    #[inline]
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: Fn(&mut Vec<TimingMetric>) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    #[inline]
+    /// Passes the given metrics to the hook.
+    pub(crate) fn apply(&self, metrics: &mut Vec<TimingMetric>) {
+        (self.0)(metrics);
+    }
+}
+
+impl fmt::Debug for VisitMut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("VisitMut(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VisitMut;
+    use crate::TimingMetric;
+
+    #[test]
+    fn apply() {
+        let visit = VisitMut::new(|metrics: &mut Vec<TimingMetric>| {
+            metrics.retain(|metric| !metric.millis().is_some_and(|dur| dur < 1.0));
+            metrics.reverse();
+        });
+
+        let mut metrics = vec![
+            TimingMetric::new("db").with_millis(12.0),
+            TimingMetric::new("cache").with_millis(0.2),
+            TimingMetric::new("miss"),
+        ];
+        visit.apply(&mut metrics);
+
+        let names: Vec<_> = metrics.iter().map(TimingMetric::name).collect();
+        assert_eq!(names, ["miss", "db"]);
+    }
+}
@@ -0,0 +1,145 @@
+//! Waterfall rendering of a single request.
+
+use std::fmt::Write;
+
+use crate::TimingReport;
+
+/// The width of the bars area, in pixels.
+const WIDTH: f64 = 800.0;
+
+/// The width of the labels column, in pixels.
+const LABEL_WIDTH: usize = 200;
+
+/// The height of a row, in pixels.
+const ROW_HEIGHT: usize = 20;
+
+/// Renders the report as a standalone HTML page with an SVG waterfall, so
+/// that where the time went can be seen without external APM.
+///
+/// The first metric, i.e. the entry of the service, spans the whole request.
+/// Metrics with a start offset (see [`TimingMetric::with_start`]) are placed
+/// accordingly, the others are drawn hatched from the start of the request.
+///
+/// [`TimingMetric::with_start`]: crate::TimingMetric::with_start
+pub fn render_waterfall(report: &TimingReport) -> String {
+    let metrics = report.metrics();
+
+    // The scale, at least the whole request.
+    let total = metrics
+        .iter()
+        .map(|metric| {
+            metric.start_millis().unwrap_or_default() + metric.millis().unwrap_or_default()
+        })
+        .filter(|end| end.is_finite())
+        .fold(0.0_f64, f64::max);
+    let scale = if total > 0.0 { WIDTH / total } else { 0.0 };
+
+    let mut page = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Waterfall</title></head><body \
+         style=\"font-family:monospace\"><h1>",
+    );
+    push_escaped(
+        &mut page,
+        report.method().map_or("", |method| method.as_str()),
+    );
+    page.push(' ');
+    push_escaped(&mut page, report.route().unwrap_or_default());
+    page.push_str("</h1>");
+
+    // Writing to a `String` never fails.
+    let _ = write!(
+        page,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\"><defs><pattern \
+         id=\"h\" width=\"4\" height=\"4\" patternUnits=\"userSpaceOnUse\"><path d=\"M0 4L4 0\" \
+         stroke=\"#4a90d9\"/></pattern></defs>",
+        LABEL_WIDTH + WIDTH as usize + 100,
+        metrics.len() * ROW_HEIGHT,
+    );
+
+    for (i, metric) in metrics.iter().enumerate() {
+        let y = i * ROW_HEIGHT;
+        let dur = metric
+            .millis()
+            .filter(|dur| dur.is_finite())
+            .unwrap_or_default();
+        let start = metric.start_millis().filter(|start| start.is_finite());
+
+        page.push_str("<g><title>");
+        push_escaped(&mut page, &metric.full_name());
+        if let Some(desc) = metric.description() {
+            page.push_str(": ");
+            push_escaped(&mut page, desc);
+        }
+        let _ = write!(
+            page,
+            "</title><text x=\"0\" y=\"{}\">",
+            y + ROW_HEIGHT * 3 / 4
+        );
+        push_escaped(&mut page, &metric.full_name());
+        let _ = write!(
+            page,
+            "</text><rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"/><text \
+             x=\"{:.1}\" y=\"{}\">{dur:.1}ms</text></g>",
+            LABEL_WIDTH as f64 + start.unwrap_or_default() * scale,
+            y + 2,
+            (dur * scale).max(1.0),
+            ROW_HEIGHT - 4,
+            if i == 0 || start.is_some() {
+                "#4a90d9"
+            } else {
+                "url(#h)"
+            },
+            LABEL_WIDTH as f64 + (start.unwrap_or_default() + dur) * scale + 4.0,
+            y + ROW_HEIGHT * 3 / 4,
+        );
+    }
+
+    page.push_str("</svg></body></html>");
+    page
+}
+
+/// Appends the given text, escaped for HTML.
+pub(crate) fn push_escaped(page: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => page.push_str("&amp;"),
+            '<' => page.push_str("&lt;"),
+            '>' => page.push_str("&gt;"),
+            '"' => page.push_str("&quot;"),
+            '\'' => page.push_str("&#39;"),
+            c => page.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::render_waterfall;
+    use crate::{TimingMetric, TimingReport};
+
+    #[test]
+    fn waterfall() {
+        let report = TimingReport::new()
+            .with(TimingMetric::new("app").with_millis(100.0))
+            .with(
+                TimingMetric::new("db")
+                    .with_start(Duration::from_millis(50))
+                    .with_millis(25.0)
+                    .with_description("<users>"),
+            )
+            .with(TimingMetric::new("cache").with_millis(10.0));
+
+        let page = render_waterfall(&report);
+        assert!(page.contains("<title>db: &lt;users&gt;</title>"));
+        // 800px for 100ms, after the 200px labels.
+        assert!(page.contains("<rect x=\"200.0\" y=\"2\" width=\"800.0\""));
+        assert!(page.contains(
+            "<rect x=\"600.0\" y=\"22\" width=\"200.0\" height=\"16\" fill=\"#4a90d9\"/>"
+        ));
+        assert!(page.contains(
+            "<rect x=\"200.0\" y=\"42\" width=\"80.0\" height=\"16\" fill=\"url(#h)\"/>"
+        ));
+    }
+}